@@ -0,0 +1,49 @@
+/**
+ * Build the library for the packages that depend on the router. tsc has
+ * already emitted the declarations in `dist`, and every source file is
+ * stripped of its types next to them. The `#src/*.ts` imports, which the
+ * package maps to the sources, are made relative so that `dist` stands alone.
+ */
+import { mkdir, readdir, readFile, writeFile } from "node:fs/promises";
+import { stripTypeScriptTypes } from "node:module";
+import path from "node:path";
+
+const SOURCE_DIRECTORY = "src";
+const OUTPUT_DIRECTORY = "dist";
+
+function rewriteImports(code: string, outputPath: string): string {
+	return code.replace(
+		/(["'])#src\/([^"']+)\.ts\1/g,
+		(_match, quote: string, modulePath: string) => {
+			const relativePath = path.posix.relative(
+				path.posix.dirname(outputPath),
+				path.posix.join(OUTPUT_DIRECTORY, `${modulePath}.js`),
+			);
+			const specifier = relativePath.startsWith(".")
+				? relativePath
+				: `./${relativePath}`;
+			return `${quote}${specifier}${quote}`;
+		},
+	);
+}
+
+const entries = await readdir(SOURCE_DIRECTORY, { recursive: true });
+for (const entry of entries.filter((entry) => entry.endsWith(".ts"))) {
+	const outputPath = path.posix.join(
+		OUTPUT_DIRECTORY,
+		entry.replace(/\.ts$/, ".js"),
+	);
+	const source = await readFile(path.join(SOURCE_DIRECTORY, entry), "utf8");
+	await mkdir(path.dirname(outputPath), { recursive: true });
+	await writeFile(
+		outputPath,
+		rewriteImports(stripTypeScriptTypes(source), outputPath),
+	);
+
+	const declarationPath = outputPath.replace(/\.js$/, ".d.ts");
+	const declaration = await readFile(declarationPath, "utf8");
+	await writeFile(
+		declarationPath,
+		rewriteImports(declaration, declarationPath),
+	);
+}
@@ -0,0 +1,141 @@
+import type { ConfigRepository } from "#src/repositories/configRepository.ts";
+import { DryRunGgufParserRepository } from "#src/repositories/dryRunGgufParserRepository.ts";
+import { DryRunLlamaServerRepository } from "#src/repositories/dryRunLlamaServerRepository.ts";
+import { DryRunRocmSmiRepository } from "#src/repositories/dryRunRocmSmiRepository.ts";
+import { GgufParserRepository } from "#src/repositories/ggufParserRepository.ts";
+import {
+	LlamaServerRepository,
+	type LlamaServerRuntime,
+} from "#src/repositories/llamaServerRepository.ts";
+import { RocmSmiRepository } from "#src/repositories/rocmSmiRepository.ts";
+import { UsageRepository } from "#src/repositories/usageRepository.ts";
+import { AnthropicController } from "#src/server/controllers/AnthropicController.ts";
+import { ChatController } from "#src/server/controllers/ChatController.ts";
+import { ConfigController } from "#src/server/controllers/ConfigController.ts";
+import { EmbeddingsController } from "#src/server/controllers/EmbeddingsController.ts";
+import { ModelFitsController } from "#src/server/controllers/ModelFitsController.ts";
+import { ModelsController } from "#src/server/controllers/ModelsController.ts";
+import { OllamaController } from "#src/server/controllers/OllamaController.ts";
+import { RerankController } from "#src/server/controllers/RerankController.ts";
+import { Router } from "#src/server/router.ts";
+import { Server } from "#src/server/server.ts";
+import { ConfigService } from "#src/services/configService.ts";
+import { LlamaProxyService } from "#src/services/llamaProxyService.ts";
+import { ModelFitService } from "#src/services/modelFitService.ts";
+import { ModelsService } from "#src/services/modelsService.ts";
+import { UsageService } from "#src/services/usageService.ts";
+
+export type Application = {
+	router: Router;
+	server: Server;
+	rocmSmiRepository: RocmSmiRepository;
+	llamaProxyService: LlamaProxyService;
+	modelFitService: ModelFitService;
+	usageService: UsageService;
+};
+
+/**
+ * Wire the repositories, services and controllers of a router for a
+ * configuration, without starting anything.
+ *
+ * @param llamaServerRuntime Starts the models, llama-server by default
+ * @param ggufParserRepository Estimates the memory of the models
+ * @param rocmSmiRepository Measures the VRAM of the GPUs
+ */
+export function createApplication(
+	configRepository: ConfigRepository,
+	llamaServerRuntime: LlamaServerRuntime = new LlamaServerRepository(
+		configRepository.getSystemConfiguration().llamaServer,
+	),
+	ggufParserRepository: GgufParserRepository = new GgufParserRepository(
+		configRepository.getSystemConfiguration().ggufParser,
+	),
+	rocmSmiRepository: RocmSmiRepository = new RocmSmiRepository(
+		configRepository.getSystemConfiguration().rocmSmi,
+		configRepository.getVramPollTtl(),
+	),
+): Application {
+	// Services
+	const modelFitService = new ModelFitService(
+		ggufParserRepository,
+		rocmSmiRepository,
+		configRepository,
+	);
+	const modelService = new ModelsService(configRepository, modelFitService);
+	const { file: usageFile, saveInterval } =
+		configRepository.getUsageConfiguration();
+	const usageService = new UsageService(
+		usageFile ? new UsageRepository(usageFile) : null,
+		saveInterval,
+	);
+	const llamaProxyService = new LlamaProxyService(
+		configRepository,
+		llamaServerRuntime,
+		modelFitService,
+		usageService,
+	);
+
+	// Controllers
+	const modelsController = new ModelsController(
+		modelService,
+		llamaProxyService,
+		usageService,
+	);
+	const modelFitsController = new ModelFitsController(modelFitService);
+	const chatController = new ChatController(
+		llamaProxyService,
+		configRepository,
+	);
+	const embeddingsController = new EmbeddingsController(llamaProxyService);
+	const ollamaController = new OllamaController(
+		modelService,
+		llamaProxyService,
+	);
+	const anthropicController = new AnthropicController(llamaProxyService);
+	const rerankController = new RerankController(llamaProxyService);
+
+	// Router and Server
+	const configService = new ConfigService(configRepository);
+	const configController = new ConfigController(configService);
+	const router = new Router(
+		modelsController,
+		modelFitsController,
+		chatController,
+		embeddingsController,
+		configController,
+		ollamaController,
+		anthropicController,
+		rerankController,
+		configRepository,
+		configRepository.getServerConfiguration().basePath,
+	);
+	const server = new Server(
+		router.getApp(),
+		configRepository,
+		router.getWebSocketRoutes(),
+	);
+
+	return {
+		router,
+		server,
+		rocmSmiRepository,
+		llamaProxyService,
+		modelFitService,
+		usageService,
+	};
+}
+
+/**
+ * Wire a router whose models are stubs that always fit, without running
+ * llama-server, gguf-parser or rocm-smi.
+ */
+export function createDryRunApplication(
+	configRepository: ConfigRepository,
+): Application {
+	return createApplication(
+		configRepository,
+		new DryRunLlamaServerRepository(),
+		new DryRunGgufParserRepository(),
+		new DryRunRocmSmiRepository(),
+	);
+}
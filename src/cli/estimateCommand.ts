@@ -0,0 +1,87 @@
+import { parseArgs } from "node:util";
+import {
+	CacheTypeSchema,
+	ConfigRepository,
+	ContextSizeSchema,
+} from "#src/repositories/configRepository.ts";
+import { GgufParserRepository } from "#src/repositories/ggufParserRepository.ts";
+
+const USAGE =
+	"Usage: estimate <model.gguf> [--context 8192] [--kv-quant q8_0] [--mmproj <file>] [--no-mmap] [--flash-attention] [--gguf-parser <path> | --config <path>]";
+
+/**
+ * Estimate the memory usage of a single GGUF file, without requiring the
+ * model to be declared in the configuration.
+ */
+export async function runEstimateCommand(argv: string[]): Promise<void> {
+	const { values, positionals } = parseArgs({
+		args: argv,
+		allowPositionals: true,
+		options: {
+			context: { type: "string" },
+			"kv-quant": { type: "string", default: "f16" },
+			mmproj: { type: "string" },
+			"no-mmap": { type: "boolean", default: false },
+			"flash-attention": { type: "boolean", default: false },
+			"gguf-parser": { type: "string" },
+			config: { type: "string", default: "./config.json" },
+		},
+	});
+
+	const modelFilePath = positionals[0];
+	if (!modelFilePath) {
+		throw new Error(USAGE);
+	}
+
+	const contextSize = ContextSizeSchema.safeParse(values.context);
+	if (!contextSize.success) {
+		throw new Error(`Invalid --context value "${values.context}"`);
+	}
+	const cacheType = CacheTypeSchema.safeParse(values["kv-quant"]);
+	if (!cacheType.success) {
+		throw new Error(
+			`Invalid --kv-quant value "${values["kv-quant"]}", expected one of: ${CacheTypeSchema.options.join(", ")}`,
+		);
+	}
+
+	const ggufParserPath =
+		values["gguf-parser"] ??
+		(await ConfigRepository.createFromFile(values.config))
+			.getSystemConfiguration()
+			.ggufParser;
+	const ggufParserRepository = new GgufParserRepository(ggufParserPath);
+
+	const { estimate } = await ggufParserRepository.getMemoryEstimate({
+		modelFilePath,
+		mmprojFilePath: values.mmproj ?? null,
+		contextSize: contextSize.data || undefined,
+		noMmap: values["no-mmap"],
+		flashAttention: values["flash-attention"],
+		cacheTypeK: cacheType.data,
+		cacheTypeV: cacheType.data,
+	});
+
+	const item = estimate.items[0];
+	if (!item) {
+		throw new Error("gguf‑parser returned no estimate items");
+	}
+	const vramBytes = item.vrams.reduce((sum, vram) => sum + vram.nonuma, 0);
+
+	console.log(
+		`${modelFilePath} (ctx ${contextSize.data}, kv ${cacheType.data}): ${vramBytes.toLocaleString()} B VRAM + ${item.ram.nonuma.toLocaleString()} B RAM${item.fullOffloaded ? "" : " (partially offloaded)"}`,
+	);
+	console.log(
+		JSON.stringify(
+			{
+				architecture: estimate.architecture,
+				contextSize: estimate.contextSize,
+				offloadLayers: item.offloadLayers,
+				fullOffloaded: item.fullOffloaded,
+				ram: item.ram,
+				vrams: item.vrams,
+			},
+			null,
+			2,
+		),
+	);
+}
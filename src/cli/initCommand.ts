@@ -0,0 +1,53 @@
+import { readFile, writeFile } from "node:fs/promises";
+import { parseArgs } from "node:util";
+import { CacheTypeSchema } from "#src/repositories/configRepository.ts";
+
+const USAGE = "Usage: init [config.json] [--force]";
+
+// Example configuration shipped with the router, used as the template. JSON
+// has no comments, the fields are described in the README.
+const TEMPLATE_PATH = new URL("../../config.example.json", import.meta.url);
+
+/**
+ * Write a configuration template to get started, without overwriting an
+ * existing file unless `--force` is given.
+ */
+export async function runInitCommand(argv: string[]): Promise<void> {
+	const { values, positionals } = parseArgs({
+		args: argv,
+		allowPositionals: true,
+		options: {
+			force: { type: "boolean", default: false },
+		},
+	});
+	if (positionals.length > 1) {
+		throw new Error(USAGE);
+	}
+	const configPath = positionals[0] ?? "./config.json";
+
+	const template = await readFile(TEMPLATE_PATH, { encoding: "utf8" });
+	try {
+		await writeFile(configPath, template, {
+			encoding: "utf8",
+			flag: values.force ? "w" : "wx",
+		});
+	} catch (err) {
+		if ((err as NodeJS.ErrnoException).code === "EEXIST") {
+			throw new Error(
+				`"${configPath}" already exists, use --force to overwrite it`,
+			);
+		}
+		throw err;
+	}
+
+	console.log(`Wrote a configuration template to "${configPath}"`);
+	console.log(
+		[
+			"Before starting the router:",
+			"- set the paths of llama-server, gguf-parser and rocm-smi in `system`",
+			"- declare your models in `models`, each with its own network port",
+			`- pick a \`common.cacheType\` among: ${CacheTypeSchema.options.join(", ")}`,
+			"Every field is described in the Configuration section of the README.",
+		].join("\n"),
+	);
+}
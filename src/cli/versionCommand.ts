@@ -0,0 +1,36 @@
+import { execFile } from "node:child_process";
+import { readFile } from "node:fs/promises";
+import { fileURLToPath } from "node:url";
+import { promisify } from "node:util";
+
+const ROOT_URL = new URL("../../", import.meta.url);
+
+/**
+ * Commit the router runs from, when it runs from a git checkout
+ */
+async function getGitCommit(): Promise<string | null> {
+	try {
+		const { stdout } = await promisify(execFile)(
+			"git",
+			["rev-parse", "--short", "HEAD"],
+			{ cwd: fileURLToPath(ROOT_URL) },
+		);
+		return stdout.trim() || null;
+	} catch {
+		return null;
+	}
+}
+
+/**
+ * Print the version of the router and of its runtime, for bug reports.
+ */
+export async function runVersionCommand(): Promise<void> {
+	const packageJson = JSON.parse(
+		await readFile(new URL("package.json", ROOT_URL), { encoding: "utf8" }),
+	);
+	const commit = await getGitCommit();
+	console.log(
+		`${packageJson.name} ${packageJson.version}${commit ? ` (${commit})` : ""}`,
+	);
+	console.log(`node ${process.version} ${process.platform}-${process.arch}`);
+}
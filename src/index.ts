@@ -1,24 +1,56 @@
+import { parseArgs } from "node:util";
+import {
+	createApplication,
+	createDryRunApplication,
+} from "#src/application.ts";
+import { runEstimateCommand } from "#src/cli/estimateCommand.ts";
+import { runInitCommand } from "#src/cli/initCommand.ts";
+import { runVersionCommand } from "#src/cli/versionCommand.ts";
+import { isLogFormat, setLogFormat } from "#src/logging.ts";
 import { ConfigRepository } from "#src/repositories/configRepository.ts";
-import { GgufParserRepository } from "#src/repositories/ggufParserRepository.ts";
-import { LlamaServerRepository } from "#src/repositories/llamaServerRepository.ts";
-import { RocmSmiRepository } from "#src/repositories/rocmSmiRepository.ts";
-import { ChatController } from "#src/server/controllers/ChatController.ts";
-import { ConfigController } from "#src/server/controllers/ConfigController.ts";
-import { EmbeddingsController } from "#src/server/controllers/EmbeddingsController.ts";
-import { ModelFitsController } from "#src/server/controllers/ModelFitsController.ts";
-import { ModelsController } from "#src/server/controllers/ModelsController.ts";
-import { Router } from "#src/server/router.ts";
-import { Server } from "#src/server/server.ts";
-import { ConfigService } from "#src/services/configService.ts";
-import { LlamaProxyService } from "#src/services/llamaProxyService.ts";
-import { ModelFitService } from "#src/services/modelFitService.ts";
-import { ModelsService } from "#src/services/modelsService.ts";
+import { VramLoggerService } from "#src/services/vramLoggerService.ts";
 
-if (import.meta.main) {
-	// Repositories
+const VERSION_FLAGS = ["--version", "-v"];
+
+if (import.meta.main && VERSION_FLAGS.includes(process.argv[2])) {
+	await runVersionCommand();
+} else if (import.meta.main && process.argv[2] === "estimate") {
+	await runEstimateCommand(process.argv.slice(3)).catch((err) => {
+		console.error((err as Error).message);
+		process.exit(1);
+	});
+} else if (import.meta.main && process.argv[2] === "init") {
+	await runInitCommand(process.argv.slice(3)).catch((err) => {
+		console.error((err as Error).message);
+		process.exit(1);
+	});
+} else if (import.meta.main) {
+	const { values, positionals } = parseArgs({
+		args: process.argv.slice(2),
+		allowPositionals: true,
+		options: {
+			"log-format": {
+				type: "string",
+				default: process.env.LOG_FORMAT ?? "text",
+			},
+			// Answer with stub servers that always fit, without llama-server or GPU
+			"dry-run": {
+				type: "boolean",
+				default: false,
+			},
+		},
+	});
+	const logFormat = values["log-format"];
+	if (!isLogFormat(logFormat)) {
+		console.error(`Invalid log format "${logFormat}", expected text or json`);
+		process.exit(1);
+	}
+	setLogFormat(logFormat);
+
+	// Configuration
 	let configPath = "./config.json";
-	if (process.argv.length > 2) {
-		configPath = process.argv.slice(2).join(" ");
+	if (positionals.length > 0) {
+		configPath = positionals.join(" ");
 	}
 	const configRepository = await ConfigRepository.createFromFile(
 		configPath,
@@ -26,44 +58,68 @@ if (import.meta.main) {
 		console.error(err);
 		process.exit(1);
 	});
-	const llamaServerRepository = new LlamaServerRepository(
-		configRepository.getSystemConfiguration().llamaServer,
-	);
-	const ggufParserRepository = new GgufParserRepository(
-		configRepository.getSystemConfiguration().ggufParser,
-	);
-	const rocmSmiRepository = new RocmSmiRepository(
-		configRepository.getSystemConfiguration().rocmSmi,
-	);
 
-	// Services
-	const modelFitService = new ModelFitService(
-		ggufParserRepository,
+	// Application
+	const {
+		server,
 		rocmSmiRepository,
+		llamaProxyService,
+		modelFitService,
+		usageService,
+	} = values["dry-run"]
+		? createDryRunApplication(configRepository)
+		: createApplication(configRepository);
+	await usageService.load().catch((error) => {
+		console.error("Failed to load the usage counters:", error);
+	});
+	usageService.start();
+
+	// Servers left behind by a crashed router still hold their port and VRAM
+	await llamaProxyService.adoptRunningModels().catch((error) => {
+		console.error("Failed to adopt the running llama-server:", error);
+	});
+
+	// Catch the models that can never be loaded before they are requested
+	modelFitService
+		.getOversizedModels()
+		.then((oversizedModels) => {
+			for (const model of oversizedModels) {
+				console.warn(
+					`⚠️ Model "${model.modelName}" needs ${model.requiredVramBytes.toLocaleString()} B of VRAM but the GPU has ${model.totalVramBytes.toLocaleString()} B in total, it can never be loaded`,
+				);
+			}
+		})
+		.catch((error) => {
+			console.error("Failed to check the VRAM needed by the models:", error);
+		});
+
+	new VramLoggerService(
 		configRepository,
-	);
-	const modelService = new ModelsService(configRepository);
-	const llamaProxyService = new LlamaProxyService(
-		configRepository,
-		llamaServerRepository,
+		rocmSmiRepository,
+		llamaProxyService,
 		modelFitService,
-	);
+	).start();
 
-	// Controllers
-	const modelsController = new ModelsController(modelService);
-	const modelFitsController = new ModelFitsController(modelFitService);
-	const chatController = new ChatController(llamaProxyService);
-	const embeddingsController = new EmbeddingsController(llamaProxyService);
+	server.run();
+
+	// Pick up the edits of the configuration file and of the API keys
+	process.on("SIGHUP", () => {
+		console.log("Received SIGHUP, reloading the configuration");
+		configRepository.reloadFromFile().catch((error) => {
+			console.error("Failed to reload the configuration:", error);
+		});
+	});
 
-	// Router and Server
-	const configService = new ConfigService(configRepository);
-	const configController = new ConfigController(configService);
-	const router = new Router(
-		modelsController,
-		modelFitsController,
-		chatController,
-		embeddingsController,
-		configController,
-	);
-	new Server(router.getApp(), configRepository).run();
+	// Do not leave llama-server processes behind
+	const shutdown = async (signal: NodeJS.Signals) => {
+		console.log(`Received ${signal}, shutting down`);
+		await server.shutdown();
+		await usageService.save().catch((error) => {
+			console.error("Failed to save the usage counters:", error);
+		});
+		await llamaProxyService.unloadAll();
+		process.exit(0);
+	};
+	process.once("SIGINT", shutdown);
+	process.once("SIGTERM", shutdown);
 }
@@ -0,0 +1,43 @@
+/**
+ * Public API of the router, to embed it in another service. The command line
+ * entry point is `src/index.ts`.
+ */
+
+export {
+	type Application,
+	createApplication,
+	createDryRunApplication,
+} from "#src/application.ts";
+export {
+	ConfigRepository,
+	createModelConfiguration,
+	type ModelConfiguration,
+	type ModelConfigurationOptions,
+} from "#src/repositories/configRepository.ts";
+export { DryRunGgufParserRepository } from "#src/repositories/dryRunGgufParserRepository.ts";
+export { DryRunLlamaServerRepository } from "#src/repositories/dryRunLlamaServerRepository.ts";
+export { DryRunRocmSmiRepository } from "#src/repositories/dryRunRocmSmiRepository.ts";
+export {
+	type EstimateParameters,
+	type GgufParserJson,
+	GgufParserRepository,
+} from "#src/repositories/ggufParserRepository.ts";
+export {
+	type LlamaServerHandle,
+	LlamaServerRepository,
+	type LlamaServerRuntime,
+	type LlamaServerStartOptions,
+} from "#src/repositories/llamaServerRepository.ts";
+export { toApiError } from "#src/server/apiError.ts";
+export { Router } from "#src/server/router.ts";
+export { Server } from "#src/server/server.ts";
+export { readSseData } from "#src/server/sse.ts";
+export {
+	LlamaProxyService,
+	selectModelsToEvict,
+} from "#src/services/llamaProxyService.ts";
+export {
+	computeParallelSlots,
+	type ModelFitResult,
+	ModelFitService,
+} from "#src/services/modelFitService.ts";
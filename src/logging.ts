@@ -0,0 +1,33 @@
+import { format } from "node:util";
+
+export const LOG_FORMATS = ["text", "json"] as const;
+
+export type LogFormat = (typeof LOG_FORMATS)[number];
+
+export function isLogFormat(value: string): value is LogFormat {
+	return (LOG_FORMATS as readonly string[]).includes(value);
+}
+
+/**
+ * Switch the console to one JSON object per line so that logs can be
+ * ingested by log pipelines. The text format leaves the console untouched.
+ */
+export function setLogFormat(logFormat: LogFormat): void {
+	if (logFormat === "text") {
+		return;
+	}
+
+	for (const method of ["debug", "info", "log", "warn", "error"] as const) {
+		const output =
+			method === "warn" || method === "error" ? process.stderr : process.stdout;
+		const level = method === "log" ? "info" : method;
+		console[method] = (...args: unknown[]) => {
+			const line = JSON.stringify({
+				time: new Date().toISOString(),
+				level,
+				message: format(...args),
+			});
+			output.write(`${line}\n`);
+		};
+	}
+}
@@ -12,13 +12,18 @@ export abstract class BaseCliCommandRepository {
 		this.binaryPath = path.resolve(binaryPath);
 	}
 
+	/**
+	 * Run the binary to completion. Rejects when it cannot be started, such as
+	 * when it is missing, instead of the unhandled error crashing the process.
+	 */
 	spawnAsync(
 		args: string[],
 	): Promise<{ stdout: string; stderr: string; exitCode: number | null }> {
-		return new Promise((resolve) => {
+		return new Promise((resolve, reject) => {
 			const child = spawn(this.binaryPath, args, {
 				stdio: ["ignore", "pipe", "pipe"],
 			});
+			child.on("error", reject);
 
 			let stdout = "";
 			let stderr = "";
@@ -1,8 +1,11 @@
-import { readFile, writeFile } from "node:fs/promises";
+import { existsSync } from "node:fs";
+import { readdir, readFile, writeFile } from "node:fs/promises";
+import path from "node:path";
+import { isDeepStrictEqual } from "node:util";
 import { z } from "zod";
 import { ZodIssueCode } from "zod/v3";
 
-const CacheTypeSchema = z.enum([
+export const CacheTypeSchema = z.enum([
 	"f32",
 	"f16",
 	"bf16",
@@ -14,15 +17,29 @@ const CacheTypeSchema = z.enum([
 	"q5_1",
 ]);
 
+const KILO = 1024;
+const MEGA = 1024 * 1024;
+
 function parseKString(v: unknown): unknown {
 	if (typeof v !== "string") return v;
 
-	const trimmed = v.trim().toLowerCase();
+	// Allow digit separators such as "32_768"
+	const trimmed = v.trim().toLowerCase().replaceAll("_", "");
+	if (trimmed === "") return v;
 
 	if (trimmed.endsWith("k")) {
 		const numPart = trimmed.slice(0, -1);
+		// A bare suffix is left for the validation to reject, not read as 0
+		if (numPart === "") return v;
+		const parsed = Number(numPart);
+		if (!Number.isNaN(parsed)) return parsed * KILO;
+	}
+
+	if (trimmed.endsWith("m")) {
+		const numPart = trimmed.slice(0, -1);
+		if (numPart === "") return v;
 		const parsed = Number(numPart);
-		if (!Number.isNaN(parsed)) return parsed * 1024;
+		if (!Number.isNaN(parsed)) return parsed * MEGA;
 	}
 
 	const asNumber = Number(trimmed);
@@ -31,27 +48,86 @@ function parseKString(v: unknown): unknown {
 	return v;
 }
 
-const ContextSizeSchema = z
+/**
+ * Format a context size back with the "M" suffix (1024 * 1024) when it is an
+ * exact multiple of it, so that saved configurations stay readable.
+ */
+export function formatContextSize(contextSize: number): number | string {
+	if (contextSize > 0 && contextSize % MEGA === 0) {
+		return `${contextSize / MEGA}M`;
+	}
+	return contextSize;
+}
+
+export const ContextSizeSchema = z
 	.preprocess(parseKString, z.number().int().nonnegative())
 	.default(4096);
 
 const CommonSchema = z.object({
 	cacheType: CacheTypeSchema.default("q8_0"),
+	// 0 uses the context length the model was trained with
 	contextSize: ContextSizeSchema,
 	threads: z.number().int().default(-1),
 	nGpuLayers: z.number().int().nonnegative().default(99),
+	// Set to false to memory-map the model file instead of reading it in RAM
 	noMmap: z.boolean().default(true),
+	// Lock the model in RAM so that it is never swapped out
+	mlock: z.boolean().default(false),
 	flashAttention: z.boolean().default(true),
 	jinja: z.boolean().default(true),
+	// "auto" fits as many slots of `contextSize` as the free VRAM allows
+	parallel: z
+		.union([z.number().int().positive(), z.literal("auto")])
+		.optional(),
+	// Batch the requests of all the parallel slots together, llama.cpp decides
+	// when unset
+	contBatching: z.boolean().optional(),
+	// Logical and physical batch sizes, llama.cpp uses 2048 and 512 when unset
+	batchSize: z.number().int().positive().optional(),
+	ubatchSize: z.number().int().positive().optional(),
+	// RoPE scaling, to run a model beyond the context it was trained with
+	ropeScaling: z.enum(["none", "linear", "yarn"]).optional(),
+	ropeFreqBase: z.number().positive().optional(),
+	ropeFreqScale: z.number().positive().optional(),
 });
 
-const SamplingSchema = z.object({
-	temperature: z.number().positive().default(0.8),
-	topK: z.number().int().nonnegative().default(40),
-	topP: z.number().min(0).max(1).default(0.9),
-	minP: z.number().min(0).max(1).default(0.1),
-	repeatPenalty: z.number().positive().default(1.0),
-	mirostat: z.union([z.literal(0), z.literal(1), z.literal(2)]).default(2),
+/**
+ * Penalty presets, each relying on a single main mechanism so that they do
+ * not penalize the same tokens twice. Explicit values take precedence.
+ */
+export const PENALTY_PROFILES = {
+	none: { repeatPenalty: 1.0, presencePenalty: 0, frequencyPenalty: 0 },
+	light: { repeatPenalty: 1.05, presencePenalty: 0, frequencyPenalty: 0 },
+	aggressive: { repeatPenalty: 1.1, presencePenalty: 0.3, frequencyPenalty: 0 },
+} as const;
+
+const SamplingSchema = z
+	.object({
+		temperature: z.number().positive().default(0.8),
+		topK: z.number().int().nonnegative().default(40),
+		topP: z.number().min(0).max(1).default(0.9),
+		minP: z.number().min(0).max(1).default(0.1),
+		penaltyProfile: z.enum(["none", "light", "aggressive"]).default("none"),
+		repeatPenalty: z.number().positive().optional(),
+		presencePenalty: z.number().min(-2).max(2).optional(),
+		frequencyPenalty: z.number().min(-2).max(2).optional(),
+		mirostat: z.union([z.literal(0), z.literal(1), z.literal(2)]).default(2),
+	})
+	.transform((sampling) => {
+		const preset = PENALTY_PROFILES[sampling.penaltyProfile];
+		return {
+			...sampling,
+			repeatPenalty: sampling.repeatPenalty ?? preset.repeatPenalty,
+			presencePenalty: sampling.presencePenalty ?? preset.presencePenalty,
+			frequencyPenalty: sampling.frequencyPenalty ?? preset.frequencyPenalty,
+		};
+	});
+
+// Declarative metadata for clients, not enforced by the router
+const CapabilitiesSchema = z.object({
+	vision: z.boolean().default(false),
+	tools: z.boolean().default(false),
+	jsonMode: z.boolean().default(false),
 });
 
 const NetworkSchema = z.object({
@@ -62,22 +138,40 @@ const NetworkSchema = z.object({
 const ModelConfigurationSchema = z
 	.object({
 		modelFilePath: z.string(),
+		// Overrides the global owner in listings
+		owner: z.string().optional(),
+		// Labels to filter the listing with
+		tags: z.array(z.string()).default([]),
 		multimodalProjectorFilePath: z.string().nullable().default(null),
 		unloadable: z.boolean().default(true),
-		embeddings: z.boolean().default(false),
+		// Kind of requests the model serves, which sets the llama-server flags
+		task: z.enum(["chat", "embedding", "rerank"]).default("chat"),
 		pooling: z.string().default("none"),
+		// Defensive cap on the size of a response, null to disable
+		maxResponseBytes: z.number().int().positive().nullable().default(null),
+		// Requests served by the model at once, unlimited when unset
+		maxConcurrent: z.number().int().positive().optional(),
+		// GPU the model runs on, all visible ones when unset
+		gpuDevice: z.number().int().nonnegative().optional(),
+		// Pass the sampling parameters to llama-server as its defaults
+		bakeSamplingParams: z.boolean().default(true),
+		capabilities: CapabilitiesSchema.default({
+			vision: false,
+			tools: false,
+			jsonMode: false,
+		}),
 		common: CommonSchema,
 		sampling: SamplingSchema,
 		network: NetworkSchema,
 	})
 	.superRefine((data, ctx) => {
 		if (
-			data.embeddings &&
+			data.task === "embedding" &&
 			!["mean", "cls", "last", "rank"].includes(data.pooling)
 		) {
 			ctx.addIssue({
 				code: ZodIssueCode.custom,
-				message: `Pooling should be one of: "mean", "cls", "last", "rank" when embeddings is enabled`,
+				message: `Pooling should be one of: "mean", "cls", "last", "rank" when the task is embedding`,
 				path: ["pooling"],
 			});
 		}
@@ -89,19 +183,267 @@ const SystemConfigurationSchema = z.object({
 	rocmSmi: z.string(),
 });
 
-const ServerConfigurationSchema = z.object({
-	hostname: z.string().default("0.0.0.0"),
-	port: z.number().default(8080),
+const AuthConfigurationSchema = z.object({
+	// Keys accepted as a bearer token, requests are not checked when none
+	apiKeys: z.array(z.string().min(1)).default([]),
+	// File with one more key per line
+	apiKeysFile: z.string().optional(),
+	// Environment variable with more comma separated keys
+	apiKeysEnv: z.string().optional(),
+});
+
+const TlsConfigurationSchema = z.object({
+	certPath: z.string({
+		error: "Both certPath and keyPath are required to enable TLS",
+	}),
+	keyPath: z.string({
+		error: "Both certPath and keyPath are required to enable TLS",
+	}),
+});
+
+const ServerConfigurationSchema = z
+	.object({
+		// Defaults to 0.0.0.0:8080 when no socketPath is given
+		hostname: z.string().optional(),
+		port: z.number().optional(),
+		// Listen on a Unix domain socket instead of TCP
+		socketPath: z.string().optional(),
+		// Prefix of the OpenAI compatible routes
+		basePath: z
+			.string()
+			.startsWith("/", { message: "Base path must start with a slash (/)" })
+			.default("/v1"),
+		// Serve over HTTPS when set
+		tls: TlsConfigurationSchema.optional(),
+		// Largest request body accepted by the model routes, 50 MiB by default
+		maxRequestBodyBytes: z
+			.number()
+			.int()
+			.positive()
+			.default(50 * MEGA),
+		// Seconds to answer a non-streaming request, 0 or negative to disable
+		requestTimeout: z.number().int().default(0),
+	})
+	.superRefine((data, ctx) => {
+		if (
+			data.socketPath !== undefined &&
+			(data.hostname !== undefined || data.port !== undefined)
+		) {
+			ctx.addIssue({
+				code: ZodIssueCode.custom,
+				message: "socketPath cannot be combined with hostname or port",
+				path: ["socketPath"],
+			});
+		}
+	});
+
+const UsageConfigurationSchema = z.object({
+	// JSON file keeping the usage counters across restarts, in memory if unset
+	file: z.string().optional(),
+	// Seconds between two saves of the counters
+	saveInterval: z.number().int().positive().default(60),
+});
+
+const VramLogConfigurationSchema = z.object({
+	// Seconds between two VRAM log lines, 0 or negative value to disable
+	interval: z.number().int().default(0),
+	level: z.enum(["info", "debug"]).default("info"),
 });
 
+const AutodiscoverySchema = z.object({
+	// Serve the GGUF files of modelsDirectory that are not declared in models
+	modelsAutodiscover: z.boolean().default(false),
+	// Directory scanned for models, relative to the configuration file
+	modelsDirectory: z.string().default("./models"),
+	// First llama-server port given to the discovered models
+	discoveredModelsPort: z.number().int().positive().default(8100),
+});
+
+/** Version of the configuration files written by this router */
+export const CONFIG_VERSION = 2;
+
+/** JSON object of a configuration that is not validated yet */
+type RawConfig = Record<string, unknown>;
+
+/**
+ * Upgrade of a configuration from a version to the next one, returning a
+ * description of every change
+ */
+type ConfigMigration = (config: RawConfig) => string[];
+
+const CONFIG_MIGRATIONS: Record<number, ConfigMigration> = {
+	// Version 1 declared embedding models with `embeddings: true`
+	1: (config) => {
+		const changes: string[] = [];
+		const models = (config.models ?? {}) as Record<string, RawConfig>;
+		for (const [modelName, model] of Object.entries(models)) {
+			if (typeof model !== "object" || !model || !("embeddings" in model)) {
+				continue;
+			}
+			if (model.embeddings === true && model.task === undefined) {
+				model.task = "embedding";
+				changes.push(`models.${modelName}.embeddings became task "embedding"`);
+			} else {
+				changes.push(`models.${modelName}.embeddings was removed`);
+			}
+			delete model.embeddings;
+		}
+		return changes;
+	},
+};
+
+/**
+ * Upgrade a configuration written by an older router to the current version,
+ * files without a version being version 1. Newer versions are left for the
+ * validation to reject.
+ */
+function migrateConfig(json: unknown): unknown {
+	if (typeof json !== "object" || json === null || Array.isArray(json)) {
+		return json;
+	}
+	const config = structuredClone(json) as RawConfig;
+	let version = config.version ?? 1;
+	while (typeof version === "number" && version < CONFIG_VERSION) {
+		const changes = CONFIG_MIGRATIONS[version]?.(config) ?? [];
+		for (const change of changes) {
+			console.warn(`Config migrated to version ${version + 1}: ${change}`);
+		}
+		version++;
+	}
+	config.version = version;
+	return config;
+}
+
+function isRawConfig(value: unknown): value is RawConfig {
+	return typeof value === "object" && value !== null && !Array.isArray(value);
+}
+
+/** Merge two objects and their nested objects, override taking precedence */
+function mergeDeep(base: RawConfig, override: RawConfig): RawConfig {
+	const merged = { ...base };
+	for (const [key, value] of Object.entries(override)) {
+		const baseValue = base[key];
+		merged[key] =
+			isRawConfig(value) && isRawConfig(baseValue)
+				? mergeDeep(baseValue, value)
+				: value;
+	}
+	return merged;
+}
+
+/**
+ * Apply the model defaults of a configuration to each of its models, the
+ * values of the model taking precedence over them.
+ */
+function applyModelDefaults(json: unknown): unknown {
+	if (!isRawConfig(json) || !isRawConfig(json.defaults)) {
+		return json;
+	}
+	const defaults = json.defaults;
+	const models = isRawConfig(json.models) ? json.models : {};
+	return {
+		...json,
+		models: Object.fromEntries(
+			Object.entries(models).map(([modelName, model]) => [
+				modelName,
+				isRawConfig(model) ? mergeDeep(defaults, model) : model,
+			]),
+		),
+	};
+}
+
+/** Leave out the values of an object and its nested objects found in base */
+function omitEqualValues(value: RawConfig, base: RawConfig): RawConfig {
+	const result: RawConfig = {};
+	for (const [key, nested] of Object.entries(value)) {
+		const baseValue = base[key];
+		if (isRawConfig(nested) && isRawConfig(baseValue)) {
+			result[key] = omitEqualValues(nested, baseValue);
+		} else if (!isDeepStrictEqual(nested, baseValue)) {
+			result[key] = nested;
+		}
+	}
+	return result;
+}
+
+/**
+ * Leave out the values a model inherits from the model defaults, so that it
+ * keeps following them when they change. Its file and network are required
+ * and always kept.
+ */
+function omitInheritedValues(
+	model: ModelConfiguration,
+	defaults: RawConfig,
+): RawConfig {
+	const inherited = ModelConfigurationSchema.safeParse(
+		mergeDeep(defaults, {
+			modelFilePath: model.modelFilePath,
+			network: model.network,
+			common: {},
+			sampling: {},
+		}),
+	);
+	if (!inherited.success) {
+		return model;
+	}
+	return {
+		modelFilePath: model.modelFilePath,
+		...omitEqualValues(model, inherited.data),
+		network: model.network,
+	};
+}
+
 const ConfigFileSchema = z
 	.object({
+		version: z
+			.number()
+			.int()
+			.max(CONFIG_VERSION, {
+				message: "Written by a newer version of the router",
+			})
+			.default(CONFIG_VERSION),
 		owner: z.string(),
+		// Files whose models are merged before the ones of this file
+		include: z.array(z.string()).optional(),
+		...AutodiscoverySchema.shape,
+		// Model settings inherited by every model, validated as part of them
+		defaults: z.record(z.string(), z.unknown()).optional(),
 		unloadDuration: z.number().int().default(30),
+		// Seconds to wait for a model to start, 0 or negative value to disable
+		startupTimeout: z.number().int().default(300),
+		// Seconds to wait for ongoing requests on exit, 0 or negative to disable
+		shutdownTimeout: z.number().int().default(30),
+		// Seconds without data before aborting a response, 0 or negative to disable
+		streamIdleTimeout: z.number().int().default(600),
+		// Number of llama-server log lines reported when a model fails to start
+		startupLogLines: z.number().int().nonnegative().default(20),
+		// "retry" answers 503 while a model loads instead of holding the request
+		loadMode: z.enum(["block", "retry"]).default("block"),
+		// Send SSE comments to streaming chat clients while their model loads
+		loadingEvents: z.boolean().default(true),
+		// Requests forwarded to models at once, unlimited when unset
+		maxConcurrentRequests: z.number().int().positive().optional(),
 		// Set to 0 or negative value to disable
 		concurrentModels: z.number().int().default(2),
+		// Index of the GPU measured by the fit checks of the models not pinned to
+		// one, as listed by rocm-smi. Every GPU when unset, as those models run on
+		// all of them.
+		gpuDevice: z.number().int().nonnegative().optional(),
+		// Reject the configuration when a model file does not exist, only warn
+		// about it when false
+		strictModelFiles: z.boolean().default(true),
+		// Fraction added to the VRAM estimates as a safety margin
+		vramHeadroom: z.number().nonnegative().default(0),
+		// Milliseconds a rocm-smi VRAM poll is reused for, 0 to always poll
+		vramPollTtl: z.number().int().nonnegative().default(500),
+		vramLog: VramLogConfigurationSchema.default({
+			interval: 0,
+			level: "info",
+		}),
+		usage: UsageConfigurationSchema.default({ saveInterval: 60 }),
 		system: SystemConfigurationSchema,
 		server: ServerConfigurationSchema,
+		auth: AuthConfigurationSchema.default({ apiKeys: [] }),
 		models: z.record(
 			z.string().superRefine((data, ctx) => {
 				if (data.includes(".")) {
@@ -141,22 +483,254 @@ const ConfigFileSchema = z
 
 export type ModelConfiguration = z.infer<typeof ModelConfigurationSchema>;
 
+export type ModelTask = ModelConfiguration["task"];
+
+export type ModelCapabilities = z.infer<typeof CapabilitiesSchema>;
+
 export type ConfigFile = z.infer<typeof ConfigFileSchema>;
 
 export type SystemConfiguration = z.infer<typeof SystemConfigurationSchema>;
 
 export type ServerConfiguration = z.infer<typeof ServerConfigurationSchema>;
 
+export type AuthConfiguration = z.infer<typeof AuthConfigurationSchema>;
+
+export type LoadMode = ConfigFile["loadMode"];
+
+export type VramLogConfiguration = z.infer<typeof VramLogConfigurationSchema>;
+
+export type UsageConfiguration = z.infer<typeof UsageConfigurationSchema>;
+
+/** Configuration that is valid but cannot be used as is */
+export class ConfigError extends Error {}
+
+type ModelConfigurationInput = z.input<typeof ModelConfigurationSchema>;
+
+/** Fields of a model configuration, where only the file and port are needed */
+export type ModelConfigurationOptions = Omit<
+	ModelConfigurationInput,
+	"common" | "sampling"
+> &
+	Partial<Pick<ModelConfigurationInput, "common" | "sampling">>;
+
+/**
+ * Build a model configuration without a configuration file, with the same
+ * defaults and validation as the models of a file.
+ */
+export function createModelConfiguration({
+	common = {},
+	sampling = {},
+	...options
+}: ModelConfigurationOptions): ModelConfiguration {
+	return ModelConfigurationSchema.parse({ ...options, common, sampling });
+}
+
+/**
+ * Warn about models stacking several aggressive penalties, as they all act on
+ * repeated tokens and end up degrading the output.
+ */
+function warnAboutOverlappingPenalties(config: ConfigFile): void {
+	for (const [modelName, { sampling }] of Object.entries(config.models)) {
+		const aggressivePenalties = [
+			sampling.repeatPenalty > 1.1 && "repeatPenalty",
+			sampling.presencePenalty > 0.5 && "presencePenalty",
+			sampling.frequencyPenalty > 0.5 && "frequencyPenalty",
+		].filter(Boolean);
+		if (aggressivePenalties.length > 1) {
+			console.warn(
+				`Model "${modelName}" combines several aggressive penalties (${aggressivePenalties.join(", ")}), consider using a penaltyProfile instead`,
+			);
+		}
+	}
+}
+
+/**
+ * Reject, or only warn about with `strictModelFiles` off, the model files that
+ * cannot be found, which would otherwise only be noticed when the model is
+ * first requested.
+ * @throws ConfigError naming the first missing file and its model
+ */
+function checkModelFiles(config: ConfigFile): void {
+	for (const [modelName, model] of Object.entries(config.models)) {
+		const files = [model.modelFilePath, model.multimodalProjectorFilePath];
+		for (const file of files) {
+			if (file && !existsSync(file)) {
+				const message = `Model "${modelName}" file "${file}" does not exist`;
+				if (config.strictModelFiles) {
+					throw new ConfigError(message);
+				}
+				console.warn(message);
+			}
+		}
+	}
+}
+
+/**
+ * Gather the API keys of the configuration with the ones of its file and
+ * environment variable, so that they can be kept out of the configuration.
+ */
+async function readApiKeys(auth: AuthConfiguration): Promise<Set<string>> {
+	const apiKeys = new Set(auth.apiKeys);
+	if (auth.apiKeysFile !== undefined) {
+		const raw = await readFile(auth.apiKeysFile, { encoding: "utf8" });
+		for (const line of raw.split(/\r?\n/)) {
+			if (line.trim()) {
+				apiKeys.add(line.trim());
+			}
+		}
+	}
+	if (auth.apiKeysEnv !== undefined) {
+		const raw = process.env[auth.apiKeysEnv];
+		if (raw === undefined) {
+			console.warn(`API keys variable ${auth.apiKeysEnv} is not set`);
+		}
+		for (const key of raw?.split(",") ?? []) {
+			if (key.trim()) {
+				apiKeys.add(key.trim());
+			}
+		}
+	}
+	return apiKeys;
+}
+
+async function readJsonFile(configPath: string): Promise<RawConfig> {
+	const raw = await readFile(configPath, { encoding: "utf8" });
+	let json: unknown;
+	try {
+		json = JSON.parse(raw);
+	} catch (err) {
+		throw new Error(
+			`Failed to parse JSON config at "${configPath}": ${(err as Error).message}`,
+		);
+	}
+	if (typeof json !== "object" || json === null || Array.isArray(json)) {
+		throw new Error(`JSON config at "${configPath}" is not an object`);
+	}
+	return json as RawConfig;
+}
+
+/**
+ * Read the models of the files included by a configuration, and of the files
+ * they include, resolving paths from the including file. Later files override
+ * the models of earlier ones. Every file is migrated from its own version.
+ * @param includingPaths Absolute paths of the files being read, for cycles
+ */
+async function readIncludedModels(
+	include: unknown,
+	includingPaths: string[],
+): Promise<RawConfig> {
+	const models: RawConfig = {};
+	if (!Array.isArray(include)) {
+		return models;
+	}
+	const directory = path.dirname(includingPaths[includingPaths.length - 1]);
+	for (const file of include) {
+		const includePath = path.resolve(directory, String(file));
+		if (includingPaths.includes(includePath)) {
+			throw new Error(
+				`Config include cycle: ${[...includingPaths, includePath].join(" -> ")}`,
+			);
+		}
+		// Each file is migrated from its own version before being merged
+		const json = migrateConfig(await readJsonFile(includePath)) as RawConfig;
+		if (typeof json.version === "number" && json.version > CONFIG_VERSION) {
+			throw new Error(
+				`JSON config at "${includePath}" was written by a newer version of the router`,
+			);
+		}
+		Object.assign(
+			models,
+			await readIncludedModels(json.include, [...includingPaths, includePath]),
+			json.models,
+		);
+	}
+	return models;
+}
+
+// Suffix of the files of a model split in several shards
+const SHARD_PATTERN = /-(\d{5})-of-\d{5}$/;
+
+/**
+ * Find the GGUF files of a directory and its subdirectories, as models with
+ * the default settings named after their file. Only the first shard of a split
+ * model is kept, and multimodal projectors are left out.
+ * @param declaredModels Models of the configuration, which are not discovered
+ * and whose ports are not given to the discovered ones
+ */
+async function discoverModels(
+	directory: string,
+	firstPort: number,
+	declaredModels: RawConfig,
+): Promise<RawConfig> {
+	let files: string[];
+	try {
+		files = await readdir(directory, { recursive: true });
+	} catch (error) {
+		console.warn(
+			`Failed to scan the models directory "${directory}": ${(error as Error).message}`,
+		);
+		return {};
+	}
+
+	const usedPorts = new Set(
+		Object.values(declaredModels).map(
+			(model) => (model as { network?: { port?: unknown } }).network?.port,
+		),
+	);
+	const models: RawConfig = {};
+	let port = firstPort;
+	for (const file of files.filter((file) => file.endsWith(".gguf")).sort()) {
+		const fileName = path.basename(file, ".gguf");
+		const shard = SHARD_PATTERN.exec(fileName);
+		if ((shard && shard[1] !== "00001") || fileName.startsWith("mmproj")) {
+			continue;
+		}
+		// Dots are not allowed in model names
+		const modelName = fileName.replace(SHARD_PATTERN, "").replaceAll(".", "-");
+		if (Object.hasOwn(declaredModels, modelName)) {
+			continue;
+		}
+		if (Object.hasOwn(models, modelName)) {
+			console.warn(
+				`Discovered model "${modelName}" is found twice, ignoring "${file}"`,
+			);
+			continue;
+		}
+		while (usedPorts.has(port)) {
+			port++;
+		}
+		models[modelName] = {
+			modelFilePath: path.join(directory, file),
+			common: {},
+			sampling: {},
+			network: { port },
+		};
+		port++;
+	}
+	return models;
+}
+
 export class ConfigRepository {
 	public getConfig(): ConfigFile {
 		return this.#config;
 	}
 	#config: ConfigFile;
 	readonly #configPath: string;
-
-	private constructor(config: ConfigFile, configPath: string) {
+	#apiKeys: ReadonlySet<string>;
+	// Models read from included files or discovered, left out of the saved file
+	// if unchanged
+	#includedModels: Record<string, ModelConfiguration>;
+
+	private constructor(
+		config: ConfigFile,
+		configPath: string,
+		apiKeys: ReadonlySet<string>,
+		includedModels: Record<string, ModelConfiguration> = {},
+	) {
 		this.#config = config;
 		this.#configPath = configPath;
+		this.#apiKeys = apiKeys;
+		this.#includedModels = includedModels;
 	}
 
 	/**
@@ -165,7 +739,9 @@ export class ConfigRepository {
 	 * @throws Error if validation or file write fails
 	 */
 	public async reloadFromJson(json: unknown): Promise<void> {
-		const parsed = ConfigFileSchema.safeParse(json);
+		const parsed = ConfigFileSchema.safeParse(
+			applyModelDefaults(migrateConfig(json)),
+		);
 		if (!parsed.success) {
 			const issues = parsed.error.issues
 				.map((i) => `${i.path.join(".")}: ${i.message}`)
@@ -173,29 +749,78 @@ export class ConfigRepository {
 			throw new Error(`Config validation error:\n${issues}`);
 		}
 
+		warnAboutOverlappingPenalties(parsed.data);
+		checkModelFiles(parsed.data);
+		const apiKeys = await readApiKeys(parsed.data.auth);
+
 		// Save to file first
-		await writeFile(this.#configPath, JSON.stringify(parsed.data, null, 2), {
-			encoding: "utf8",
-		});
+		const { defaults } = parsed.data;
+		const models = Object.fromEntries(
+			Object.entries(parsed.data.models)
+				.filter(
+					([modelName, model]) =>
+						!isDeepStrictEqual(model, this.#includedModels[modelName]),
+				)
+				.map(([modelName, model]) => [
+					modelName,
+					defaults ? omitInheritedValues(model, defaults) : model,
+				]),
+		);
+		const serialized = JSON.stringify(
+			{ ...parsed.data, models },
+			(key, value) =>
+				key === "contextSize" && typeof value === "number"
+					? formatContextSize(value)
+					: value,
+			2,
+		);
+		await writeFile(this.#configPath, serialized, { encoding: "utf8" });
 
 		// Then update in-memory config
 		this.#config = parsed.data;
+		this.#apiKeys = apiKeys;
+	}
+
+	/**
+	 * Read the configuration file again, with its included files and API
+	 * keys, without writing it back
+	 * @throws Error if the file cannot be read or validated
+	 */
+	public async reloadFromFile(): Promise<void> {
+		const reloaded = await ConfigRepository.createFromFile(this.#configPath);
+		this.#config = reloaded.#config;
+		this.#apiKeys = reloaded.#apiKeys;
+		this.#includedModels = reloaded.#includedModels;
 	}
 
 	public static async createFromFile(
 		configPath: string = "./config.json",
 	): Promise<ConfigRepository> {
-		const raw = await readFile(configPath, { encoding: "utf8" });
-		let json: unknown;
-		try {
-			json = JSON.parse(raw);
-		} catch (err) {
-			throw new Error(
-				`Failed to parse JSON config at "${configPath}": ${(err as Error).message}`,
-			);
-		}
-
-		const parsed = ConfigFileSchema.safeParse(json);
+		const json = migrateConfig(await readJsonFile(configPath)) as RawConfig;
+		const includedModels = await readIncludedModels(json.include, [
+			path.resolve(configPath),
+		]);
+		const ownModels = (json.models ?? {}) as RawConfig;
+		const declaredModels = { ...includedModels, ...ownModels };
+
+		// Invalid settings are reported by the validation of the whole file
+		const autodiscovery = AutodiscoverySchema.safeParse(json);
+		const discoveredModels =
+			autodiscovery.success && autodiscovery.data.modelsAutodiscover
+				? await discoverModels(
+						path.resolve(
+							path.dirname(configPath),
+							autodiscovery.data.modelsDirectory,
+						),
+						autodiscovery.data.discoveredModelsPort,
+						declaredModels,
+					)
+				: {};
+		const models = { ...discoveredModels, ...declaredModels };
+
+		const parsed = ConfigFileSchema.safeParse(
+			applyModelDefaults({ ...json, models }),
+		);
 		if (!parsed.success) {
 			const issues = parsed.error.issues
 				.map((i) => `${i.path.join(".")}: ${i.message}`)
@@ -203,7 +828,23 @@ export class ConfigRepository {
 			throw new Error(`Config validation error in "${configPath}":\n${issues}`);
 		}
 
-		return new ConfigRepository(parsed.data, configPath);
+		warnAboutOverlappingPenalties(parsed.data);
+		checkModelFiles(parsed.data);
+
+		const parsedIncludedModels = Object.fromEntries(
+			Object.entries(parsed.data.models).filter(
+				([modelName]) =>
+					(Object.hasOwn(includedModels, modelName) ||
+						Object.hasOwn(discoveredModels, modelName)) &&
+					!Object.hasOwn(ownModels, modelName),
+			),
+		);
+		return new ConfigRepository(
+			parsed.data,
+			configPath,
+			await readApiKeys(parsed.data.auth),
+			structuredClone(parsedIncludedModels),
+		);
 	}
 
 	public getSystemConfiguration(): SystemConfiguration {
@@ -214,6 +855,33 @@ export class ConfigRepository {
 		return this.#config.server;
 	}
 
+	/**
+	 * Keys accepted by the API, which is open when there are none
+	 */
+	public getApiKeys(): ReadonlySet<string> {
+		return this.#apiKeys;
+	}
+
+	public getGpuDevice(): number | undefined {
+		return this.#config.gpuDevice;
+	}
+
+	public getVramHeadroom(): number {
+		return this.#config.vramHeadroom;
+	}
+
+	public getVramPollTtl(): number {
+		return this.#config.vramPollTtl;
+	}
+
+	public getUsageConfiguration(): UsageConfiguration {
+		return this.#config.usage;
+	}
+
+	public getVramLogConfiguration(): VramLogConfiguration {
+		return this.#config.vramLog;
+	}
+
 	public getAvailableModelNames(): string[] {
 		return Object.keys(this.#config.models);
 	}
@@ -226,6 +894,34 @@ export class ConfigRepository {
 		return this.#config.unloadDuration;
 	}
 
+	public getStartupTimeout(): number {
+		return this.#config.startupTimeout;
+	}
+
+	public getShutdownTimeout(): number {
+		return this.#config.shutdownTimeout;
+	}
+
+	public getStreamIdleTimeout(): number {
+		return this.#config.streamIdleTimeout;
+	}
+
+	public getStartupLogLines(): number {
+		return this.#config.startupLogLines;
+	}
+
+	public getLoadMode(): LoadMode {
+		return this.#config.loadMode;
+	}
+
+	public getLoadingEvents(): boolean {
+		return this.#config.loadingEvents;
+	}
+
+	public getMaxConcurrentRequests(): number | undefined {
+		return this.#config.maxConcurrentRequests;
+	}
+
 	public getConcurrentModels(): number {
 		return this.#config.concurrentModels;
 	}
@@ -0,0 +1,53 @@
+import {
+	type EstimateParameters,
+	GgufParserRepository,
+	type GgufParserJson,
+} from "#src/repositories/ggufParserRepository.ts";
+
+/**
+ * Stands in for gguf-parser without reading the model files: every model is
+ * estimated to need no memory, so that it always fits in a dry run.
+ */
+export class DryRunGgufParserRepository extends GgufParserRepository {
+	constructor() {
+		// The binary is never run
+		super("gguf-parser");
+	}
+
+	public override async getMemoryEstimate(
+		params: EstimateParameters,
+	): Promise<GgufParserJson> {
+		const memory = {
+			handleLayers: 0,
+			handleLastLayer: 0,
+			handleOutputLayer: false,
+			remote: false,
+			position: 0,
+			uma: 0,
+			nonuma: 0,
+		};
+		return {
+			estimate: {
+				items: [
+					{
+						offloadLayers: 0,
+						fullOffloaded: true,
+						ram: memory,
+						vrams: [memory],
+					},
+				],
+				type: "model",
+				architecture: "dry-run",
+				// Unknown, the configured size is used when there is one
+				contextSize: params.contextSize ?? 0,
+				flashAttention: params.flashAttention ?? false,
+				noMMap: params.noMmap ?? false,
+				embeddingOnly: false,
+				reranking: false,
+				distributable: false,
+				logicalBatchSize: params.batchSize ?? 2048,
+				physicalBatchSize: params.ubatchSize ?? 512,
+			},
+		};
+	}
+}
@@ -0,0 +1,141 @@
+import { type ServerType, serve } from "@hono/node-server";
+import { Hono } from "hono";
+import type { ModelConfiguration } from "#src/repositories/configRepository.ts";
+import {
+	type LlamaServerHandle,
+	type LlamaServerRuntime,
+	LlamaServerStopError,
+} from "#src/repositories/llamaServerRepository.ts";
+
+export const DRY_RUN_CONTENT = "This is a dry run response.";
+
+// Length of the stub embeddings
+const DRY_RUN_EMBEDDING_SIZE = 8;
+
+/**
+ * Stands in for llama-server without spawning any process: each model gets an
+ * in-process stub listening on its port that answers chat completions with a
+ * fixed message, and embeddings and reranks with zeros, to try configurations
+ * and routing on any machine.
+ */
+export class DryRunLlamaServerRepository implements LlamaServerRuntime {
+	readonly #servers = new Map<number, ServerType>();
+	#nextPid = 1;
+
+	public async start(opts: ModelConfiguration): Promise<LlamaServerHandle> {
+		const { host, port } = opts.network;
+		const server = await new Promise<ServerType>((resolve, reject) => {
+			const server = serve(
+				{ fetch: createStubApp().fetch, hostname: host, port },
+				() => resolve(server),
+			);
+			server.once("error", reject);
+		});
+
+		const pid = this.#nextPid++;
+		this.#servers.set(pid, server);
+		console.log(`[dry run] Started a stub server on ${host}:${port}`);
+		return { pid };
+	}
+
+	public async stop(pid: number): Promise<void> {
+		const server = this.#servers.get(pid);
+		if (!server) {
+			throw new LlamaServerStopError(
+				`No known stub server with pid ${pid}`,
+				pid,
+			);
+		}
+
+		this.#servers.delete(pid);
+		await new Promise<void>((resolve) => server.close(() => resolve()));
+		console.log(`[dry run] Stopped the stub server ${pid}`);
+	}
+
+	public onProcessCrash(): void {
+		// Stub servers never crash
+	}
+
+	public async adopt(): Promise<LlamaServerHandle | null> {
+		// Stub servers end with the process
+		return null;
+	}
+}
+
+function createStubApp(): Hono {
+	const app = new Hono();
+
+	app.get("/health", (c) => c.json({ status: "ok" }));
+
+	app.post("/v1/chat/completions", async (c) => {
+		const request = await c.req.json();
+		const id = `chatcmpl-dry-run-${Date.now()}`;
+		const created = Math.floor(Date.now() / 1000);
+
+		if (request.stream) {
+			const chunk = {
+				id,
+				object: "chat.completion.chunk",
+				created,
+				model: request.model,
+				choices: [
+					{
+						index: 0,
+						delta: { role: "assistant", content: DRY_RUN_CONTENT },
+						finish_reason: "stop",
+					},
+				],
+			};
+			c.header("Content-Type", "text/event-stream");
+			return c.body(`data: ${JSON.stringify(chunk)}\n\ndata: [DONE]\n\n`);
+		}
+
+		return c.json({
+			id,
+			object: "chat.completion",
+			created,
+			model: request.model,
+			choices: [
+				{
+					index: 0,
+					message: { role: "assistant", content: DRY_RUN_CONTENT },
+					finish_reason: "stop",
+				},
+			],
+			usage: { prompt_tokens: 0, completion_tokens: 0, total_tokens: 0 },
+		});
+	});
+
+	app.post("/v1/embeddings", async (c) => {
+		const request = await c.req.json();
+		const inputs = Array.isArray(request.input)
+			? request.input
+			: [request.input];
+		return c.json({
+			object: "list",
+			model: request.model,
+			data: inputs.map((_input: unknown, index: number) => ({
+				object: "embedding",
+				index,
+				embedding: new Array(DRY_RUN_EMBEDDING_SIZE).fill(0),
+			})),
+			usage: { prompt_tokens: 0, total_tokens: 0 },
+		});
+	});
+
+	app.post("/v1/rerank", async (c) => {
+		const request = await c.req.json();
+		const documents: unknown[] = request.documents ?? [];
+		return c.json({
+			object: "list",
+			model: request.model,
+			results: documents.map((_document, index) => ({
+				index,
+				relevance_score: 0,
+			})),
+			usage: { prompt_tokens: 0, total_tokens: 0 },
+		});
+	});
+
+	return app;
+}
@@ -0,0 +1,31 @@
+import {
+	type RocmSmiQueryOptions,
+	RocmSmiRepository,
+	type RocmSmiVramInfo,
+} from "#src/repositories/rocmSmiRepository.ts";
+
+// VRAM of the stub GPUs, large enough for any model
+const DRY_RUN_VRAM_BYTES = 1024 ** 5;
+
+/**
+ * Stands in for rocm-smi on machines without a GPU: every device is reported
+ * as a large, empty GPU.
+ */
+export class DryRunRocmSmiRepository extends RocmSmiRepository {
+	constructor() {
+		// The binary is never run
+		super("rocm-smi");
+	}
+
+	public override async getVramInfo(
+		opts: RocmSmiQueryOptions = {},
+	): Promise<RocmSmiVramInfo[]> {
+		return [
+			{
+				card: `card${opts.device ?? 0}`,
+				totalBytes: DRY_RUN_VRAM_BYTES,
+				usedBytes: 0,
+			},
+		];
+	}
+}
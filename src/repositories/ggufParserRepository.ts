@@ -27,12 +27,26 @@ export type EstimateParameters = {
 	cacheTypeK?: CacheType;
 	/** Cache type for Values (`--ctv`) */
 	cacheTypeV?: CacheType;
+	/** Logical batch size (`--batch-size`) */
+	batchSize?: number;
+	/** Physical batch size (`--ubatch-size`) */
+	ubatchSize?: number;
 };
 
 export type GgufParserJson = {
+	metadata?: MetadataPayload;
 	estimate: EstimatePayload;
 };
 
+export type MetadataPayload = {
+	/** The `general.name` of the model, when present in the file */
+	name?: string;
+	/** The `general.architecture` of the model, such as "llama" */
+	architecture?: string;
+	/** Dominant quantization of the tensors, such as "Q4_K_M" */
+	fileType?: string;
+};
+
 export type EstimatePayload = {
 	items: EstimateItem[];
 	type: string;
@@ -113,6 +127,8 @@ export class GgufParserRepository extends BaseCliCommandRepository {
 			flashAttention = false,
 			cacheTypeK = "f16",
 			cacheTypeV = "f16",
+			batchSize,
+			ubatchSize,
 		} = params;
 
 		const args: string[] = [];
@@ -130,10 +146,15 @@ export class GgufParserRepository extends BaseCliCommandRepository {
 		args.push("--ctk", cacheTypeK);
 		args.push("--ctv", cacheTypeV);
 
+		// The compute buffers grow with the batch sizes
+		if (batchSize !== undefined)
+			args.push("--batch-size", batchSize.toString());
+		if (ubatchSize !== undefined)
+			args.push("--ubatch-size", ubatchSize.toString());
+
 		args.push(
 			"--json",
 			"--skip-architecture",
-			"--skip-metadata",
 			"--skip-tokenizer",
 		);
 
@@ -1,4 +1,5 @@
 import { type ChildProcessByStdio, spawn } from "node:child_process";
+import { readdir, readFile } from "node:fs/promises";
 import * as os from "node:os";
 import * as path from "node:path";
 import type { Readable } from "node:stream";
@@ -12,6 +13,13 @@ export type LlamaServerHandle = {
 	pid: number;
 };
 
+export type LlamaServerStartOptions = {
+	/** Maximum time to wait for the server, disabled when 0 or negative */
+	timeoutMs?: number;
+	/** Number of log lines kept to explain a failed startup */
+	logLines?: number;
+};
+
 /**
  * Thrown when the llama‑server binary cannot be started or exits before it
  * reports that it is listening.
@@ -45,13 +53,92 @@ export class LlamaServerStopError extends Error {
 	}
 }
 
+/**
+ * Thrown when the llama‑server process does not report that it is listening
+ * within the startup timeout. The process is killed before this is thrown.
+ */
+export class LlamaServerStartTimeoutError extends LlamaServerStartError {
+	constructor(message: string, command: string, stderr: string) {
+		super(message, command, stderr);
+		this.name = "LlamaServerStartTimeoutError";
+	}
+}
+
+/**
+ * Operations used to run the servers of the models, implemented by
+ * `LlamaServerRepository` with local processes. Other runtimes, or a fake
+ * one in tests, can be given to `LlamaProxyService` instead.
+ */
+export interface LlamaServerRuntime {
+	start(
+		opts: ModelConfiguration,
+		startOptions?: LlamaServerStartOptions,
+	): Promise<LlamaServerHandle>;
+	stop(pid: number): Promise<void>;
+	onProcessCrash(pid: number, handler: (pid: number) => void): void;
+	/**
+	 * Take over the server of a model left running by a previous router, or
+	 * null when there is none. With a `parallel` of auto, `contextSize` is the
+	 * context size of one slot.
+	 */
+	adopt(opts: ModelConfiguration): Promise<LlamaServerHandle | null>;
+}
+
+// Milliseconds between two checks that an adopted server is still running
+const ADOPTED_PROCESS_POLL_MS = 1_000;
+
+// Milliseconds to wait for a server to stop before killing it
+const STOP_TIMEOUT_MS = 30_000;
+
+/**
+ * Time the process started at, in clock ticks since boot, or null once it
+ * exited. Unlike the pid, it tells apart a process that reused the pid.
+ */
+async function readStartTime(pid: number): Promise<string | null> {
+	try {
+		const stat = await readFile(`/proc/${pid}/stat`, "utf8");
+		// The command name can hold spaces, the fields after it start at state
+		return stat.slice(stat.lastIndexOf(")") + 2).split(" ")[19];
+	} catch {
+		return null;
+	}
+}
+
+/**
+ * Command line of every process that can be read, by pid. Empty on systems
+ * without /proc.
+ */
+async function listCommandLines(): Promise<Map<number, string[]>> {
+	const commandLines = new Map<number, string[]>();
+	let entries: string[];
+	try {
+		entries = await readdir("/proc");
+	} catch {
+		return commandLines;
+	}
+	for (const entry of entries.filter((entry) => /^\d+$/.test(entry))) {
+		try {
+			const cmdline = await readFile(`/proc/${entry}/cmdline`, "utf8");
+			commandLines.set(Number(entry), cmdline.split("\0").slice(0, -1));
+		} catch {
+			// The process exited meanwhile
+		}
+	}
+	return commandLines;
+}
+
 type ChildProcessWithoutStdin = ChildProcessByStdio<null, Readable, Readable>;
 
-export class LlamaServerRepository {
+export class LlamaServerRepository implements LlamaServerRuntime {
 	readonly #binaryPath: string;
 
 	readonly #processes = new Map<number, ChildProcessWithoutStdin>();
 	readonly #crashHandlers = new Map<number, (pid: number) => void>();
+	// Servers of a previous router, which are not children of this one
+	readonly #adoptedProcesses = new Map<
+		number,
+		{ startTime: string; watcher: NodeJS.Timeout }
+	>();
 
 	constructor(binaryPath: string) {
 		if (!binaryPath) {
@@ -64,7 +151,16 @@ export class LlamaServerRepository {
 		this.#crashHandlers.set(pid, handler);
 	}
 
-	public async start(opts: ModelConfiguration): Promise<LlamaServerHandle> {
+	/**
+	 * Spawn a llama‑server and wait until it reports that it is listening.
+	 * @param opts The model configuration to start the server with
+	 * @param startOptions How long to wait and how many log lines to keep
+	 */
+	public async start(
+		opts: ModelConfiguration,
+		startOptions: LlamaServerStartOptions = {},
+	): Promise<LlamaServerHandle> {
+		const { timeoutMs = 0, logLines = 20 } = startOptions;
 		const args = this.#buildArgs(opts);
 		const commandStr = `${this.#binaryPath} ${args
 			.map(this.#escapeArg)
@@ -72,6 +168,7 @@ export class LlamaServerRepository {
 
 		const child: ChildProcessWithoutStdin = spawn(this.#binaryPath, args, {
 			stdio: ["ignore", "pipe", "pipe"],
+			env: this.#buildEnv(opts),
 		});
 
 		if (child.pid === undefined) {
@@ -82,43 +179,72 @@ export class LlamaServerRepository {
 			);
 		}
 
-		this.#processes.set(child.pid, child);
+		const pid = child.pid;
+		this.#processes.set(pid, child);
 
-		let stdoutBuffer = "";
+		let stderrBuffer = "";
+		const startupLogs: string[] = [];
+		const getStartupLogs = () =>
+			[...startupLogs, stderrBuffer].filter(Boolean).join("\n");
 
 		const readyPromise = new Promise<void>((resolve, reject) => {
 			const onData = (chunk: string) => {
-				stdoutBuffer += chunk;
-				const lines = stdoutBuffer.split(/\r?\n/);
+				stderrBuffer += chunk;
+				const lines = stderrBuffer.split(/\r?\n/);
 				for (const line of lines) {
 					if (line.includes("main: server is listening on")) {
-						child.stdout.off("data", onData);
-						child.off("exit", onExit);
+						clearTimeout(startupTimeout);
+						child.stderr.off("data", onData);
+						child.off("close", onExit);
+						// Only needed to explain a failed startup
+						stderrBuffer = "";
+						startupLogs.length = 0;
 						resolve();
 						return;
 					}
 				}
-				if (!stdoutBuffer.endsWith("\n")) {
-					stdoutBuffer = lines[lines.length - 1];
+				startupLogs.push(...lines.slice(0, -1));
+				startupLogs.splice(0, startupLogs.length - logLines);
+				if (!stderrBuffer.endsWith("\n")) {
+					stderrBuffer = lines[lines.length - 1];
 				} else {
-					stdoutBuffer = "";
+					stderrBuffer = "";
 				}
 			};
 
 			const onExit = (code: number | null, signal: string | null) => {
-				child.stdout.off("data", onData);
+				clearTimeout(startupTimeout);
+				child.stderr.off("data", onData);
+				this.#processes.delete(pid);
 				reject(
 					new LlamaServerStartError(
 						`llama‑server exited before reporting that it was listening (code=${code}, signal=${signal})`,
 						commandStr,
-						child.stderr.readable ? "" : "",
+						getStartupLogs(),
 					),
 				);
 			};
 
+			const onTimeout = () => {
+				child.stderr.off("data", onData);
+				child.off("close", onExit);
+				child.kill("SIGKILL");
+				this.#processes.delete(pid);
+				reject(
+					new LlamaServerStartTimeoutError(
+						`llama‑server did not report that it was listening within ${timeoutMs} ms`,
+						commandStr,
+						getStartupLogs(),
+					),
+				);
+			};
+			const startupTimeout =
+				timeoutMs > 0 ? setTimeout(onTimeout, timeoutMs) : undefined;
+
 			child.stderr.setEncoding("utf8");
 			child.stderr.on("data", onData);
-			child.on("exit", onExit);
+			// Wait for "close" rather than "exit" so that stderr is fully read
+			child.on("close", onExit);
 		});
 
 		await readyPromise;
@@ -138,10 +264,94 @@ export class LlamaServerRepository {
 			}
 		});
 
-		return { pid: child.pid };
+		return { pid };
+	}
+
+	/**
+	 * Find the llama-server of a model left running by a previous router, such
+	 * as after a crash, from the command lines in /proc. Only a server started
+	 * with the arguments the model would be started with is adopted. One left
+	 * on the model's port with other arguments is not stopped, as the router
+	 * did not start it, so loading the model fails until it is stopped.
+	 */
+	public async adopt(
+		opts: ModelConfiguration,
+	): Promise<LlamaServerHandle | null> {
+		const port = opts.network.port.toString();
+		const commandLines = await listCommandLines();
+		for (const [pid, [binaryPath, ...processArgs]] of commandLines) {
+			const portIndex = processArgs.indexOf("--port");
+			if (
+				binaryPath !== this.#binaryPath ||
+				portIndex === -1 ||
+				processArgs[portIndex + 1] !== port
+			) {
+				continue;
+			}
+
+			const args = this.#buildAdoptedArgs(opts, processArgs);
+			const startTime = await readStartTime(pid);
+			if (args?.join("\0") !== processArgs.join("\0") || !startTime) {
+				console.warn(
+					`Not adopting llama-server ${pid}, left on port ${port} with other arguments, stop it to load ${opts.modelFilePath}`,
+				);
+				return null;
+			}
+
+			// No exit event for a process that is not a child, poll it instead
+			const watcher = setInterval(async () => {
+				if ((await readStartTime(pid)) !== startTime) {
+					console.error(`Adopted llama-server process ${pid} exited`);
+					clearInterval(watcher);
+					this.#adoptedProcesses.delete(pid);
+					this.#crashHandlers.get(pid)?.(pid);
+					this.#crashHandlers.delete(pid);
+				}
+			}, ADOPTED_PROCESS_POLL_MS);
+			watcher.unref();
+			this.#adoptedProcesses.set(pid, { startTime, watcher });
+			return { pid };
+		}
+		return null;
+	}
+
+	/**
+	 * Arguments a server left running should have been started with. An
+	 * automatic slot count is the one of the server, with the context of one
+	 * slot multiplied by it, as `LlamaProxyService` launches it.
+	 */
+	#buildAdoptedArgs(
+		opts: ModelConfiguration,
+		processArgs: string[],
+	): string[] | null {
+		if (opts.common.parallel !== "auto") {
+			return this.#buildArgs(opts);
+		}
+		const parallelIndex = processArgs.indexOf("--parallel");
+		const parallel = Number(processArgs[parallelIndex + 1]);
+		if (parallelIndex === -1 || !Number.isInteger(parallel) || parallel < 1) {
+			return null;
+		}
+		return this.#buildArgs({
+			...opts,
+			common: {
+				...opts.common,
+				parallel,
+				contextSize: opts.common.contextSize * parallel,
+			},
+		});
 	}
 
 	public async stop(pid: number): Promise<void> {
+		const adopted = this.#adoptedProcesses.get(pid);
+		if (adopted) {
+			clearInterval(adopted.watcher);
+			this.#adoptedProcesses.delete(pid);
+			this.#crashHandlers.delete(pid);
+			await this.#stopProcess(pid, adopted.startTime);
+			return;
+		}
+
 		const child = this.#processes.get(pid);
 		if (!child) {
 			throw new LlamaServerStopError(
@@ -169,13 +379,50 @@ export class LlamaServerRepository {
 					child.kill("SIGKILL");
 				} catch {}
 			}
-		}, 30_000);
+		}, STOP_TIMEOUT_MS);
 
 		await waitForExit;
 
 		this.#processes.delete(pid);
 	}
 
+	/**
+	 * Stop an adopted process, which can only be polled until it exits. The
+	 * start time checks that the pid was not reused by another process.
+	 */
+	async #stopProcess(pid: number, startTime: string): Promise<void> {
+		const isRunning = async () => (await readStartTime(pid)) === startTime;
+		if (!(await isRunning())) {
+			return;
+		}
+		try {
+			process.kill(pid);
+		} catch {
+			return;
+		}
+		const deadline = Date.now() + STOP_TIMEOUT_MS;
+		while (await isRunning()) {
+			if (Date.now() > deadline) {
+				try {
+					process.kill(pid, "SIGKILL");
+				} catch {}
+				return;
+			}
+			await new Promise((resolve) => setTimeout(resolve, 100));
+		}
+	}
+
+	/**
+	 * Restrict the server to the GPU of the model, so that models pinned to
+	 * different GPUs do not share the same VRAM
+	 */
+	#buildEnv(opts: ModelConfiguration): NodeJS.ProcessEnv {
+		if (opts.gpuDevice === undefined) {
+			return process.env;
+		}
+		return { ...process.env, HIP_VISIBLE_DEVICES: opts.gpuDevice.toString() };
+	}
+
 	#buildArgs(opts: ModelConfiguration): string[] {
 		const {
 			modelFilePath,
@@ -183,8 +430,9 @@ export class LlamaServerRepository {
 			common,
 			network,
 			sampling,
-			embeddings,
+			task,
 			pooling,
+			bakeSamplingParams,
 		} = opts;
 		const args: string[] = [];
 
@@ -192,10 +440,13 @@ export class LlamaServerRepository {
 		if (multimodalProjectorFilePath) {
 			args.push("--mmproj", multimodalProjectorFilePath);
 		}
-		if (embeddings) {
+		if (task === "embedding") {
 			args.push("--embeddings");
 			args.push("--pooling", pooling);
 		}
+		if (task === "rerank") {
+			args.push("--reranking");
+		}
 
 		// network
 		args.push("--host", network.host);
@@ -208,20 +459,48 @@ export class LlamaServerRepository {
 		args.push("--ctx-size", common.contextSize.toString());
 		args.push("--threads", common.threads.toString());
 		args.push("--n-gpu-layers", common.nGpuLayers.toString());
+		if (typeof common.parallel === "number") {
+			args.push("--parallel", common.parallel.toString());
+		}
+		if (common.contBatching !== undefined) {
+			args.push(common.contBatching ? "--cont-batching" : "--no-cont-batching");
+		}
+		if (common.batchSize !== undefined) {
+			args.push("--batch-size", common.batchSize.toString());
+		}
+		if (common.ubatchSize !== undefined) {
+			args.push("--ubatch-size", common.ubatchSize.toString());
+		}
+		if (common.ropeScaling !== undefined) {
+			args.push("--rope-scaling", common.ropeScaling);
+		}
+		if (common.ropeFreqBase !== undefined) {
+			args.push("--rope-freq-base", common.ropeFreqBase.toString());
+		}
+		if (common.ropeFreqScale !== undefined) {
+			args.push("--rope-freq-scale", common.ropeFreqScale.toString());
+		}
 		if (common.noMmap) {
 			args.push("--no-mmap");
 		}
+		if (common.mlock) {
+			args.push("--mlock");
+		}
 		if (common.jinja) {
 			args.push("--jinja");
 		}
 
-		// sampling
-		args.push("--temp", sampling.temperature.toString());
-		args.push("--top-k", sampling.topK.toString());
-		args.push("--top-p", sampling.topP.toString());
-		args.push("--min-p", sampling.minP.toString());
-		args.push("--repeat-penalty", sampling.repeatPenalty.toString());
-		args.push("--mirostat", sampling.mirostat.toString());
+		// sampling, only used to generate text
+		if (bakeSamplingParams && task === "chat") {
+			args.push("--temp", sampling.temperature.toString());
+			args.push("--top-k", sampling.topK.toString());
+			args.push("--top-p", sampling.topP.toString());
+			args.push("--min-p", sampling.minP.toString());
+			args.push("--repeat-penalty", sampling.repeatPenalty.toString());
+			args.push("--presence-penalty", sampling.presencePenalty.toString());
+			args.push("--frequency-penalty", sampling.frequencyPenalty.toString());
+			args.push("--mirostat", sampling.mirostat.toString());
+		}
 
 		return args;
 	}
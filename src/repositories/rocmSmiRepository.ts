@@ -6,16 +6,48 @@ export type RocmSmiVramInfo = {
 	usedBytes: number;
 };
 
-export type RocmSmiRawResult = Record<
-	string,
-	{
-		"VRAM Total Memory (B)"?: string;
-		"VRAM Total Used Memory (B)"?: string;
-	}
->;
+export type RocmSmiRawResult = Record<string, Record<string, unknown>>;
+
+/**
+ * VRAM of several cards taken as one, for a model spread across all of them
+ */
+export function sumVramInfos(vramInfos: RocmSmiVramInfo[]): RocmSmiVramInfo {
+	return {
+		card: vramInfos.map(({ card }) => card).join("+"),
+		totalBytes: vramInfos.reduce((sum, info) => sum + info.totalBytes, 0),
+		usedBytes: vramInfos.reduce((sum, info) => sum + info.usedBytes, 0),
+	};
+}
+
+const TOTAL_KEY = "VRAM Total Memory (B)";
+const USED_KEY = "VRAM Total Used Memory (B)";
+
+/**
+ * Form of a field name shared by its spellings across ROCm releases, which
+ * differ in casing, separators and units, such as "vram Total Memory (B)"
+ */
+function normalizeKey(key: string): string {
+	return key
+		.toLowerCase()
+		.replace(/\((b|bytes)\)/g, "")
+		.replace(/[^a-z]/g, "");
+}
+
+/** Number of bytes of a field, whatever its spelling */
+function readBytes(
+	data: Record<string, unknown>,
+	expectedKey: string,
+): number | undefined {
+	const normalizedKey = normalizeKey(expectedKey);
+	const key = Object.keys(data).find((k) => normalizeKey(k) === normalizedKey);
+	const bytes = key === undefined ? NaN : Number(data[key]);
+	return Number.isNaN(bytes) ? undefined : bytes;
+}
 
 export type RocmSmiQueryOptions = {
 	device?: number;
+	/** Poll rocm-smi even if a recent result is cached */
+	forceRefresh?: boolean;
 };
 
 export class RocmSmiError extends Error {
@@ -30,14 +62,76 @@ export class RocmSmiError extends Error {
 	}
 }
 
+type CachedVramInfo = {
+	polledAt: number;
+	vramInfos: Promise<RocmSmiVramInfo[]>;
+};
+
 export class RocmSmiRepository extends BaseCliCommandRepository {
+	readonly #cacheTtlMs: number;
+	readonly #cache = new Map<string, CachedVramInfo>();
+	// Entries without VRAM already warned about, to only warn once for each
+	readonly #skippedEntries = new Set<string>();
+
+	/**
+	 * @param cacheTtlMs How long a poll is reused for, 0 to always poll
+	 */
+	constructor(binaryPath: string, cacheTtlMs: number = 0) {
+		super(binaryPath);
+		this.#cacheTtlMs = cacheTtlMs;
+	}
+
 	public async getVramInfo(
 		opts: RocmSmiQueryOptions = {},
 	): Promise<RocmSmiVramInfo[]> {
+		const key = opts.device?.toString() ?? "all";
+		const cached = this.#cache.get(key);
+		if (
+			!opts.forceRefresh &&
+			cached &&
+			Date.now() - cached.polledAt < this.#cacheTtlMs
+		) {
+			return cached.vramInfos;
+		}
+
+		// Cache the pending poll so that concurrent callers share it
+		const entry = { polledAt: Date.now(), vramInfos: this.#poll(opts) };
+		this.#cache.set(key, entry);
+		entry.vramInfos.catch(() => {
+			if (this.#cache.get(key) === entry) {
+				this.#cache.delete(key);
+			}
+		});
+		return entry.vramInfos;
+	}
+
+	/**
+	 * Drop the cached poll of a device, and of all devices, so that the next
+	 * query sees the memory allocated since. Without a device, memory may have
+	 * been allocated on any of them and every poll is dropped.
+	 */
+	public invalidate(device?: number): void {
+		if (device === undefined) {
+			this.#cache.clear();
+			return;
+		}
+		this.#cache.delete(device.toString());
+		this.#cache.delete("all");
+	}
+
+	async #poll(opts: RocmSmiQueryOptions): Promise<RocmSmiVramInfo[]> {
 		const args = this.#buildArgs(opts);
 		const commandStr = `${this.binaryPath} ${args.map(this.escapeArg).join(" ")}`;
 
-		const { stdout, stderr, exitCode } = await this.spawnAsync(args);
+		const { stdout, stderr, exitCode } = await this.spawnAsync(args).catch(
+			(error: Error) => {
+				throw new RocmSmiError(
+					`Failed to run rocm-smi: ${error.message}`,
+					commandStr,
+					"",
+				);
+			},
+		);
 
 		if (exitCode !== 0) {
 			throw new RocmSmiError(
@@ -58,18 +152,29 @@ export class RocmSmiRepository extends BaseCliCommandRepository {
 			);
 		}
 
-		return Object.entries(raw).map(([card, data]) => {
-			const totalStr = data["VRAM Total Memory (B)"];
-			const usedStr = data["VRAM Total Used Memory (B)"];
-			const total = totalStr ? Number(totalStr) : NaN;
-			const used = usedStr ? Number(usedStr) : NaN;
-
-			return {
-				card,
-				totalBytes: total,
-				usedBytes: used,
-			};
-		});
+		const vramInfos: RocmSmiVramInfo[] = [];
+		for (const [card, data] of Object.entries(raw)) {
+			const totalBytes = readBytes(data, TOTAL_KEY);
+			const usedBytes = readBytes(data, USED_KEY);
+			if (totalBytes !== undefined && usedBytes !== undefined) {
+				vramInfos.push({ card, totalBytes, usedBytes });
+			} else if (!this.#skippedEntries.has(card)) {
+				// Such as the "system" entry of recent releases
+				this.#skippedEntries.add(card);
+				console.warn(
+					`rocm-smi entry "${card}" has no VRAM, expected "${TOTAL_KEY}" and "${USED_KEY}", found: ${Object.keys(data).join(", ")}`,
+				);
+			}
+		}
+
+		if (vramInfos.length === 0) {
+			throw new RocmSmiError(
+				`rocm-smi did not report the VRAM of any card, expected "${TOTAL_KEY}" and "${USED_KEY}"`,
+				commandStr,
+				stdout,
+			);
+		}
+		return vramInfos;
 	}
 
 	#buildArgs(opts: RocmSmiQueryOptions): string[] {
@@ -0,0 +1,73 @@
+import { readFile, rename, writeFile } from "node:fs/promises";
+import { z } from "zod";
+import type { ModelUsage } from "#src/services/usageService.ts";
+
+export type UsageFile = Record<string, ModelUsage>;
+
+const CounterSchema = z.number().int().nonnegative();
+
+const UsageFileSchema = z.record(
+	z.string(),
+	z.object({
+		requests: CounterSchema,
+		promptTokens: CounterSchema,
+		completionTokens: CounterSchema,
+	}),
+);
+
+/**
+ * Keep the usage counters in a JSON file so that they survive restarts
+ */
+export class UsageRepository {
+	readonly #filePath: string;
+
+	constructor(filePath: string) {
+		this.#filePath = filePath;
+	}
+
+	/**
+	 * Read the saved counters, none when the file does not exist yet or is
+	 * corrupt
+	 */
+	public async load(): Promise<UsageFile> {
+		let raw: string;
+		try {
+			raw = await readFile(this.#filePath, "utf8");
+		} catch (error) {
+			if ((error as NodeJS.ErrnoException).code === "ENOENT") {
+				return {};
+			}
+			throw error;
+		}
+
+		let json: unknown;
+		try {
+			json = JSON.parse(raw);
+		} catch (error) {
+			console.warn(
+				`Usage file "${this.#filePath}" is not valid JSON, starting from zero: ${(error as Error).message}`,
+			);
+			return {};
+		}
+		const parsed = UsageFileSchema.safeParse(json);
+		if (!parsed.success) {
+			const issues = parsed.error.issues
+				.map((i) => `${i.path.join(".")}: ${i.message}`)
+				.join(", ");
+			console.warn(
+				`Usage file "${this.#filePath}" is corrupt, starting from zero: ${issues}`,
+			);
+			return {};
+		}
+		return parsed.data;
+	}
+
+	public async save(usage: UsageFile): Promise<void> {
+		// Write a whole new file so that a crash never leaves a truncated one
+		const temporaryPath = `${this.#filePath}.tmp`;
+		await writeFile(temporaryPath, JSON.stringify(usage, null, "\t"), {
+			encoding: "utf8",
+		});
+		await rename(temporaryPath, this.#filePath);
+	}
+}
@@ -0,0 +1,148 @@
+import { HTTPException } from "hono/http-exception";
+import type { ContentfulStatusCode } from "hono/utils/http-status";
+import {
+	LlamaServerStartError,
+	LlamaServerStartTimeoutError,
+} from "#src/repositories/llamaServerRepository.ts";
+import { RequestTimeoutError } from "#src/server/requestTimeout.ts";
+import {
+	InsufficientMemoryError,
+	ModelLoadingError,
+	NotSupportedError,
+	ResponseTooLargeError,
+	ServerBusyError,
+	StreamIdleTimeoutError,
+	TooManyRequestsError,
+	UpstreamError,
+} from "#src/services/llamaProxyService.ts";
+import { ModelNotFoundError } from "#src/services/modelFitService.ts";
+
+/**
+ * Error body following the OpenAI API error schema.
+ */
+export type ApiError = {
+	error: {
+		message: string;
+		type: string;
+		code: string | null;
+	};
+};
+
+export function createApiError(
+	message: string,
+	status: number,
+	code: string | null = null,
+): ApiError {
+	return {
+		error: {
+			message,
+			type: status < 500 ? "invalid_request_error" : "server_error",
+			code,
+		},
+	};
+}
+
+function getErrorCode(error: unknown): string | null {
+	if (error instanceof ModelNotFoundError) {
+		return "model_not_found";
+	}
+	if (error instanceof InsufficientMemoryError) {
+		return "insufficient_memory";
+	}
+	if (error instanceof ModelLoadingError) {
+		return "model_loading";
+	}
+	if (error instanceof NotSupportedError) {
+		return "not_supported";
+	}
+	if (error instanceof ResponseTooLargeError) {
+		return "response_too_large";
+	}
+	if (error instanceof ServerBusyError) {
+		return "server_busy";
+	}
+	if (error instanceof TooManyRequestsError) {
+		return "too_many_requests";
+	}
+	if (error instanceof StreamIdleTimeoutError) {
+		return "stream_idle_timeout";
+	}
+	if (error instanceof RequestTimeoutError) {
+		return "request_timeout";
+	}
+	if (error instanceof UpstreamError) {
+		return "upstream_error";
+	}
+	if (error instanceof LlamaServerStartTimeoutError) {
+		return "model_startup_timeout";
+	}
+	if (error instanceof LlamaServerStartError) {
+		return "model_startup_failed";
+	}
+	return null;
+}
+
+/**
+ * HTTP status matching an error raised while serving a model.
+ */
+export function getErrorStatus(error: unknown): ContentfulStatusCode {
+	if (error instanceof ModelNotFoundError) {
+		return 404;
+	}
+	if (error instanceof NotSupportedError) {
+		return 400;
+	}
+	if (error instanceof TooManyRequestsError) {
+		return 429;
+	}
+	// Client errors are passed on, server errors are the gateway's
+	if (error instanceof UpstreamError && error.status < 500) {
+		return error.status as ContentfulStatusCode;
+	}
+	if (
+		error instanceof InsufficientMemoryError ||
+		error instanceof ModelLoadingError ||
+		error instanceof ServerBusyError
+	) {
+		return 503;
+	}
+	if (
+		error instanceof LlamaServerStartTimeoutError ||
+		error instanceof StreamIdleTimeoutError ||
+		error instanceof RequestTimeoutError
+	) {
+		return 504;
+	}
+	if (
+		error instanceof LlamaServerStartError ||
+		error instanceof ResponseTooLargeError ||
+		error instanceof UpstreamError
+	) {
+		return 502;
+	}
+	return 500;
+}
+
+/**
+ * Convert any error thrown by a handler into an OpenAI shaped response. The
+ * code is taken from the cause of an `HTTPException` when there is one.
+ */
+export function toApiError(error: Error): {
+	status: ContentfulStatusCode;
+	body: ApiError;
+	headers: Record<string, string>;
+} {
+	const status =
+		error instanceof HTTPException ? error.status : getErrorStatus(error);
+	const cause = error instanceof HTTPException ? error.cause : error;
+	const message = error.message || "Internal server error";
+	const headers: Record<string, string> = {};
+	if (cause instanceof ModelLoadingError) {
+		headers["Retry-After"] = cause.retryAfterSeconds.toString();
+	}
+	return {
+		status,
+		body: createApiError(message, status, getErrorCode(cause)),
+		headers,
+	};
+}
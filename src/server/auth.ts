@@ -0,0 +1,40 @@
+import type { MiddlewareHandler } from "hono";
+import type { ConfigRepository } from "#src/repositories/configRepository.ts";
+import { createApiError } from "#src/server/apiError.ts";
+
+/**
+ * Whether a request holds one of the API keys, given as a bearer token or, as
+ * Anthropic clients do, in the `x-api-key` header. Any request does when there
+ * are no keys.
+ */
+export function hasValidApiKey(
+	apiKeys: ReadonlySet<string>,
+	getHeader: (name: string) => string | undefined,
+): boolean {
+	if (apiKeys.size === 0) {
+		return true;
+	}
+	const authorization = getHeader("Authorization");
+	const apiKey = authorization?.startsWith("Bearer ")
+		? authorization.slice("Bearer ".length)
+		: getHeader("x-api-key");
+	return apiKey !== undefined && apiKeys.has(apiKey);
+}
+
+/**
+ * Reject the requests without one of the configured API keys
+ */
+export function apiKeyAuth(
+	configRepository: ConfigRepository,
+): MiddlewareHandler {
+	return async (c, next) => {
+		const apiKeys = configRepository.getApiKeys();
+		if (!hasValidApiKey(apiKeys, (name) => c.req.header(name))) {
+			return c.json(
+				createApiError("Invalid API key", 401, "invalid_api_key"),
+				401,
+			);
+		}
+		return next();
+	};
+}
@@ -0,0 +1,12 @@
+/**
+ * Subset of the chat completion responses of llama-server read by the API
+ * adapters, streamed chunks carrying a `delta` instead of a `message`.
+ */
+export type ChatCompletion = {
+	choices: {
+		message?: { content?: string | null };
+		delta?: { content?: string | null };
+		finish_reason?: string | null;
+	}[];
+	usage?: { prompt_tokens: number; completion_tokens: number };
+};
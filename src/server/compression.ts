@@ -0,0 +1,21 @@
+import type { MiddlewareHandler } from "hono";
+import { compress } from "hono/compress";
+
+const STREAMING_CONTENT_TYPES = ["text/event-stream", "application/x-ndjson"];
+
+/**
+ * Compress the responses with an encoding the client accepts, except streams
+ * as the compressor would hold their chunks back until it has enough data.
+ */
+export function compression(): MiddlewareHandler {
+	const compressResponse = compress();
+	return async (c, next) => {
+		await next();
+		const contentType = c.res.headers.get("Content-Type") ?? "";
+		if (STREAMING_CONTENT_TYPES.some((type) => contentType.startsWith(type))) {
+			return;
+		}
+		// The response is already there, compress only has to transform it
+		await compressResponse(c, async () => {});
+	};
+}
@@ -0,0 +1,343 @@
+import { randomUUID } from "node:crypto";
+import type { HttpBindings } from "@hono/node-server";
+import type { Context } from "hono";
+import { HTTPException } from "hono/http-exception";
+import { streamSSE } from "hono/streaming";
+import { toApiError } from "#src/server/apiError.ts";
+import type { ChatCompletion } from "#src/server/chatCompletion.ts";
+import { readSseData } from "#src/server/sse.ts";
+import type { LlamaProxyService } from "#src/services/llamaProxyService.ts";
+
+type AnthropicContentBlock =
+	| { type: "text"; text: string }
+	| {
+			type: "image";
+			source: { type: "base64"; media_type: string; data: string };
+	  };
+
+type AnthropicMessage = {
+	role: "user" | "assistant";
+	content: string | AnthropicContentBlock[];
+};
+
+type AnthropicMessagesRequest = {
+	model: string;
+	system?: string | { type: "text"; text: string }[];
+	messages: AnthropicMessage[];
+	max_tokens: number;
+	temperature?: number;
+	top_p?: number;
+	top_k?: number;
+	stop_sequences?: string[];
+	stream?: boolean;
+};
+
+function toChatCompletionContent(content: AnthropicMessage["content"]) {
+	if (typeof content === "string") {
+		return content;
+	}
+	return content.map((block) =>
+		block.type === "text"
+			? { type: "text", text: block.text }
+			: {
+					type: "image_url",
+					image_url: {
+						url: `data:${block.source.media_type};base64,${block.source.data}`,
+					},
+				},
+	);
+}
+
+function toChatCompletionRequest(request: AnthropicMessagesRequest) {
+	const system =
+		typeof request.system === "string"
+			? request.system
+			: request.system?.map((block) => block.text).join("\n");
+	return {
+		model: request.model,
+		messages: [
+			...(system ? [{ role: "system", content: system }] : []),
+			...request.messages.map(({ role, content }) => ({
+				role,
+				content: toChatCompletionContent(content),
+			})),
+		],
+		max_tokens: request.max_tokens,
+		temperature: request.temperature,
+		top_p: request.top_p,
+		top_k: request.top_k,
+		// Always streamed to match the stop sequences here, as llama-server
+		// does not tell whether it stopped on one of them
+		stream: true,
+		stream_options: { include_usage: true },
+	};
+}
+
+function toStopReason(
+	finishReason: string | null | undefined,
+	stopSequence: string | null,
+) {
+	if (stopSequence !== null) {
+		return "stop_sequence";
+	}
+	return finishReason === "length" ? "max_tokens" : "end_turn";
+}
+
+const ANTHROPIC_ERROR_TYPES: Record<number, string> = {
+	400: "invalid_request_error",
+	401: "authentication_error",
+	403: "permission_error",
+	404: "not_found_error",
+	413: "request_too_large",
+	429: "rate_limit_error",
+	503: "overloaded_error",
+	504: "timeout_error",
+};
+
+/**
+ * Error body following the Anthropic API error schema, with the status of
+ * the OpenAI shaped error
+ */
+function toAnthropicError(error: Error) {
+	const { status, body, headers } = toApiError(error);
+	const type =
+		ANTHROPIC_ERROR_TYPES[status] ??
+		(status < 500 ? "invalid_request_error" : "api_error");
+	return {
+		status,
+		body: { type: "error", error: { type, message: body.error.message } },
+		headers,
+	};
+}
+
+/**
+ * Cut a streamed text at the first of the stop sequences. The end of the
+ * text that could be the start of a stop sequence is held back until the
+ * next delta tells whether it is.
+ */
+class StopSequenceMatcher {
+	readonly #stopSequences: string[];
+	#pending = "";
+	#stopSequence: string | null = null;
+
+	constructor(stopSequences: string[]) {
+		this.#stopSequences = stopSequences.filter((sequence) => sequence);
+	}
+
+	/**
+	 * Stop sequence the text was cut at, if any
+	 */
+	get stopSequence(): string | null {
+		return this.#stopSequence;
+	}
+
+	/**
+	 * Text that can be sent once `delta` is added, nothing after a stop
+	 * sequence
+	 */
+	push(delta: string): string {
+		if (this.#stopSequence !== null) {
+			return "";
+		}
+		this.#pending += delta;
+
+		let stopIndex = -1;
+		for (const sequence of this.#stopSequences) {
+			const index = this.#pending.indexOf(sequence);
+			if (index !== -1 && (stopIndex === -1 || index < stopIndex)) {
+				stopIndex = index;
+				this.#stopSequence = sequence;
+			}
+		}
+		if (stopIndex !== -1) {
+			const text = this.#pending.slice(0, stopIndex);
+			this.#pending = "";
+			return text;
+		}
+
+		let heldLength = 0;
+		for (const sequence of this.#stopSequences) {
+			for (let length = sequence.length - 1; length > heldLength; length--) {
+				if (this.#pending.endsWith(sequence.slice(0, length))) {
+					heldLength = length;
+					break;
+				}
+			}
+		}
+		const text = this.#pending.slice(0, this.#pending.length - heldLength);
+		this.#pending = this.#pending.slice(text.length);
+		return text;
+	}
+
+	/**
+	 * Text held back when the completion ends without a stop sequence
+	 */
+	flush(): string {
+		const text = this.#pending;
+		this.#pending = "";
+		return text;
+	}
+}
+
+/**
+ * Translate the Anthropic Messages API to chat completions, for clients that
+ * only speak the former.
+ */
+export class AnthropicController {
+	readonly #llamaProxyService: LlamaProxyService;
+
+	constructor(llamaProxyService: LlamaProxyService) {
+		this.#llamaProxyService = llamaProxyService;
+	}
+
+	async messages(c: Context<{ Bindings: HttpBindings }>) {
+		const abortController = new AbortController();
+		c.env.outgoing.on("close", () => {
+			abortController.abort();
+		});
+
+		let request: AnthropicMessagesRequest;
+		let response: ReadableStream<Uint8Array<ArrayBuffer>> | null;
+		try {
+			request = await c.req.json();
+			response = await this.#llamaProxyService.chatCompletion(
+				request.model,
+				abortController.signal,
+				JSON.stringify(toChatCompletionRequest(request)),
+			);
+			if (!response) {
+				throw new HTTPException(500);
+			}
+		} catch (e) {
+			if (!(e instanceof Error)) {
+				throw e;
+			}
+			return this.#respondWithError(c, e);
+		}
+		const completion = response;
+
+		const message = {
+			id: `msg_${randomUUID().replaceAll("-", "")}`,
+			type: "message",
+			role: "assistant",
+			model: request.model,
+		};
+
+		const matcher = new StopSequenceMatcher(request.stop_sequences ?? []);
+		let finishReason: string | null | undefined;
+		let usage: ChatCompletion["usage"];
+		let deltaCount = 0;
+		// Text deltas of the completion, up to the first stop sequence
+		async function* readText() {
+			for await (const data of readSseData(completion)) {
+				if (data === "[DONE]") {
+					break;
+				}
+				const chunk: ChatCompletion = JSON.parse(data);
+				const choice = chunk.choices[0];
+				usage = chunk.usage ?? usage;
+				finishReason = choice?.finish_reason ?? finishReason;
+				const delta = choice?.delta?.content;
+				if (!delta) {
+					continue;
+				}
+				deltaCount++;
+				const text = matcher.push(delta);
+				if (text) {
+					yield text;
+				}
+				if (matcher.stopSequence !== null) {
+					break;
+				}
+			}
+			if (matcher.stopSequence !== null) {
+				// Nothing after the stop sequence is sent, stop the generation
+				abortController.abort();
+				return;
+			}
+			const text = matcher.flush();
+			if (text) {
+				yield text;
+			}
+		}
+		// llama-server streams a token per delta, the usage only comes last
+		const getUsage = () => ({
+			input_tokens: usage?.prompt_tokens ?? 0,
+			output_tokens: usage?.completion_tokens ?? deltaCount,
+		});
+
+		if (!request.stream) {
+			let text = "";
+			try {
+				for await (const delta of readText()) {
+					text += delta;
+				}
+			} catch (e) {
+				if (!(e instanceof Error)) {
+					throw e;
+				}
+				return this.#respondWithError(c, e);
+			}
+			return c.json({
+				...message,
+				content: [{ type: "text", text }],
+				stop_reason: toStopReason(finishReason, matcher.stopSequence),
+				stop_sequence: matcher.stopSequence,
+				usage: getUsage(),
+			});
+		}
+
+		return streamSSE(c, async (stream) => {
+			const writeEvent = (event: string, data: Record<string, unknown>) =>
+				stream.writeSSE({
+					event,
+					data: JSON.stringify({ type: event, ...data }),
+				});
+
+			await writeEvent("message_start", {
+				message: {
+					...message,
+					content: [],
+					stop_reason: null,
+					stop_sequence: null,
+					usage: { input_tokens: 0, output_tokens: 0 },
+				},
+			});
+			await writeEvent("content_block_start", {
+				index: 0,
+				content_block: { type: "text", text: "" },
+			});
+
+			try {
+				for await (const text of readText()) {
+					await writeEvent("content_block_delta", {
+						index: 0,
+						delta: { type: "text_delta", text },
+					});
+				}
+			} catch (e) {
+				if (!(e instanceof Error)) {
+					throw e;
+				}
+				console.error(`[${c.req.method}] ${c.req.url} - ${e.message}`);
+				await writeEvent("error", { error: toAnthropicError(e).body.error });
+				return;
+			}
+
+			await writeEvent("content_block_stop", { index: 0 });
+			await writeEvent("message_delta", {
+				delta: {
+					stop_reason: toStopReason(finishReason, matcher.stopSequence),
+					stop_sequence: matcher.stopSequence,
+				},
+				usage: { output_tokens: getUsage().output_tokens },
+			});
+			await writeEvent("message_stop", {});
+		});
+	}
+	#respondWithError(c: Context<{ Bindings: HttpBindings }>, error: Error) {
+		console.error(`[${c.req.method}] ${c.req.url} - ${error.message}`);
+		const { status, body, headers } = toAnthropicError(error);
+		return c.json(body, status, headers);
+	}
+}
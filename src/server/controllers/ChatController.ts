@@ -1,17 +1,29 @@
+import { randomUUID } from "node:crypto";
 import type { HttpBindings } from "@hono/node-server";
 import type { Context } from "hono";
 import { HTTPException } from "hono/http-exception";
 import { stream } from "hono/streaming";
+import { WebSocket } from "ws";
+import type { ConfigRepository } from "#src/repositories/configRepository.ts";
+import { toApiError } from "#src/server/apiError.ts";
+import { readSseData } from "#src/server/sse.ts";
+import { CloseCode, sendMessage } from "#src/server/webSocket.ts";
 import {
-	InsufficientMemoryError,
 	type LlamaProxyService,
+	ResponseTooLargeError,
+	StreamIdleTimeoutError,
 } from "#src/services/llamaProxyService.ts";
 
 export class ChatController {
 	readonly #llamaProxyService: LlamaProxyService;
+	readonly #configRepository: ConfigRepository;
 
-	constructor(llamaProxyService: LlamaProxyService) {
+	constructor(
+		llamaProxyService: LlamaProxyService,
+		configRepository: ConfigRepository,
+	) {
 		this.#llamaProxyService = llamaProxyService;
+		this.#configRepository = configRepository;
 	}
 
 	async getChatCompletions(c: Context<{ Bindings: HttpBindings }>) {
@@ -20,8 +32,13 @@ export class ChatController {
 			const model = request.model;
 			const isStreamingRequest = request.stream;
 			const abortController = new AbortController();
+			const requestId = randomUUID();
+			c.header("x-request-id", requestId);
+			console.log(
+				`[${requestId}] Chat completion with ${model} (stream: ${isStreamingRequest})`,
+			);
 			if (isStreamingRequest) {
-				return this.#stream(c, model, abortController, request);
+				return this.#stream(c, model, abortController, request, requestId);
 			} else {
 				c.header("Content-Type", "application/json");
 				c.env.outgoing.on("close", () => {
@@ -31,6 +48,7 @@ export class ChatController {
 					model,
 					abortController.signal,
 					request,
+					requestId,
 				);
 				return c.body(response);
 			}
@@ -42,44 +60,152 @@ export class ChatController {
 		model: string,
 		abortController: AbortController,
 		request: unknown,
+		requestId: string,
 	) {
-		try {
-			const response = await this.#proxy(
-				model,
-				abortController.signal,
-				request,
-			);
+		const reportLoading = this.#shouldReportLoading(model);
+		if (reportLoading) {
+			// Only the errors raised while loading are sent as events
+			this.#llamaProxyService.checkRequest(model, "chat");
+		}
+		// Sent right after the checks, so that they still hold
+		const pendingResponse = this.#proxy(
+			model,
+			abortController.signal,
+			request,
+			requestId,
+		);
+		// Errors keep their status unless the stream starts before the load
+		const loadedResponse = reportLoading ? null : await pendingResponse;
+		// Otherwise awaited once the stream has started
+		pendingResponse.catch(() => {});
 
-			c.header("Content-Type", "text/event-stream");
-			return stream(c, async (stream) => {
+		c.header("Content-Type", "text/event-stream");
+		return stream(c, async (stream) => {
+			stream.onAbort(() => {
+				console.log(`[${requestId}] Client disconnected`);
+				abortController.abort();
+			});
+			let response = loadedResponse;
+			if (!response) {
 				const interval = setInterval(() => {
-					stream.write(": model is loading\r\n\r\n");
+					stream.write(": model is loading\n\n");
 				}, 1_000);
-				stream.onAbort(() => {
-					abortController.abort();
+				try {
+					response = await pendingResponse;
+				} catch (e) {
+					if (!(e instanceof Error)) {
+						throw e;
+					}
+					console.error(`[${requestId}] ${e.message}`);
+					await stream.write(`data: ${JSON.stringify(toApiError(e).body)}\n\n`);
+					return;
+				} finally {
 					clearInterval(interval);
-				});
-				clearInterval(interval);
-				await stream.pipe(response);
-			});
-		} catch (e) {
-			if (e instanceof InsufficientMemoryError) {
-				throw new HTTPException(500, { message: "Insufficient memory" });
-			} else {
-				throw e;
+				}
 			}
-		}
+			try {
+				for await (const chunk of response) {
+					await stream.write(chunk);
+				}
+			} catch (e) {
+				if (
+					!(e instanceof ResponseTooLargeError) &&
+					!(e instanceof StreamIdleTimeoutError)
+				) {
+					throw e;
+				}
+				console.error(`[${requestId}] ${e.message}`);
+				await stream.write(`data: ${JSON.stringify(toApiError(e).body)}\n\n`);
+			}
+		});
+	}
+
+	/**
+	 * Stream a chat completion over a WebSocket, for clients that cannot read
+	 * SSE. The first message is the request, then the data of every event is
+	 * sent as a text message up to `[DONE]`, and errors as an error body.
+	 */
+	relayWebSocket(socket: WebSocket) {
+		const abortController = new AbortController();
+		const requestId = randomUUID();
+		socket.on("close", () => {
+			abortController.abort();
+		});
+		// Protocol errors, which ws answers with a close frame
+		socket.on("error", (error) => {
+			console.error(`[${requestId}] ${error.message}`);
+		});
+		// Only the first message is a request
+		socket.once("message", async (message, isBinary) => {
+			if (isBinary) {
+				socket.close(
+					CloseCode.unsupportedData,
+					"Only text messages are supported",
+				);
+				return;
+			}
+			try {
+				let request: { model?: unknown };
+				try {
+					request = JSON.parse(message.toString());
+				} catch (e) {
+					throw new HTTPException(400, { message: "Invalid JSON", cause: e });
+				}
+				if (typeof request?.model !== "string") {
+					throw new HTTPException(400, { message: "Missing model" });
+				}
+				console.log(
+					`[${requestId}] Chat completion with ${request.model} (WebSocket)`,
+				);
+				const response = await this.#proxy(
+					request.model,
+					abortController.signal,
+					{ ...request, stream: true },
+					requestId,
+				);
+				for await (const data of readSseData(response)) {
+					await sendMessage(socket, data);
+					if (data === "[DONE]") {
+						break;
+					}
+				}
+				socket.close(CloseCode.normal);
+			} catch (e) {
+				const error = e instanceof Error ? e : new Error(String(e));
+				console.error(`[${requestId}] ${error.message}`);
+				// Nothing more to tell a client that left
+				if (socket.readyState === WebSocket.OPEN) {
+					socket.send(JSON.stringify(toApiError(error).body));
+					socket.close(CloseCode.internalError);
+				}
+			}
+		});
+	}
+
+	/**
+	 * Whether to open the stream and send loading events before the model is
+	 * started, in which case errors can only be sent as events
+	 */
+	#shouldReportLoading(model: string): boolean {
+		return (
+			this.#configRepository.getLoadingEvents() &&
+			this.#configRepository.getLoadMode() === "block" &&
+			this.#configRepository.getModelConfiguration(model) !== null &&
+			!this.#llamaProxyService.getLoadedModels().includes(model)
+		);
 	}
 
 	async #proxy(
 		model: string,
 		abortSignal: AbortSignal,
 		request: unknown,
+		requestId: string,
 	): Promise<ReadableStream<Uint8Array<ArrayBuffer>>> {
 		const response = await this.#llamaProxyService.chatCompletion(
 			model,
 			abortSignal,
 			JSON.stringify(request),
+			{ "X-Request-Id": requestId },
 		);
 		if (!response) {
 			throw new HTTPException(500);
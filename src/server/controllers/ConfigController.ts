@@ -1,5 +1,6 @@
 import type { Context } from "hono";
 
+import { createApiError } from "#src/server/apiError.ts";
 import type { ConfigService } from "#src/services/configService.ts";
 
 export class ConfigController {
@@ -23,10 +24,7 @@ export class ConfigController {
 			});
 		} catch (error) {
 			return c.json(
-				{
-					success: false,
-					error: (error as Error).message,
-				},
+				createApiError((error as Error).message, 400, "invalid_config"),
 				400,
 			);
 		}
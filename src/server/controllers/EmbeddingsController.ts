@@ -1,3 +1,4 @@
+import { randomUUID } from "node:crypto";
 import type { HttpBindings } from "@hono/node-server";
 import type { Context } from "hono";
 import { HTTPException } from "hono/http-exception";
@@ -15,6 +16,9 @@ export class EmbeddingsController {
 		if ("model" in request) {
 			const model = request.model;
 			const abortController = new AbortController();
+			const requestId = randomUUID();
+			c.header("x-request-id", requestId);
+			console.log(`[${requestId}] Embeddings with ${model}`);
 			c.header("Content-Type", "application/json");
 			c.env.outgoing.on("close", () => {
 				abortController.abort();
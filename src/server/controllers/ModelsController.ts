@@ -1,23 +1,67 @@
 import type { Context } from "hono";
+import type { LlamaProxyService } from "#src/services/llamaProxyService.ts";
 import type { ModelsService } from "#src/services/modelsService.ts";
+import type { UsageService } from "#src/services/usageService.ts";
 
 export class ModelsController {
 	readonly #modelService: ModelsService;
+	readonly #llamaProxyService: LlamaProxyService;
+	readonly #usageService: UsageService;
 
-	constructor(modelService: ModelsService) {
+	constructor(
+		modelService: ModelsService,
+		llamaProxyService: LlamaProxyService,
+		usageService: UsageService,
+	) {
 		this.#modelService = modelService;
+		this.#llamaProxyService = llamaProxyService;
+		this.#usageService = usageService;
 	}
 
 	async getModels(c: Context) {
-		const models = this.#modelService.getModels();
+		const tag = c.req.query("tag");
+		const models = (await this.#modelService.getModels()).filter(
+			(model) => tag === undefined || model.tags.includes(tag),
+		);
+		// The status is opt-in to keep the default listing cheap
+		const withStatus = c.req.query("status") === "true";
+		const loadedModels = new Set(
+			withStatus ? this.#llamaProxyService.getLoadedModels() : [],
+		);
 		return c.json({
 			object: "list",
 			data: models.map((model) => ({
 				object: "model",
 				id: model.id,
-				createdAt: Date.now(),
+				display_name: model.displayName,
+				architecture: model.architecture,
+				quantization: model.quantization,
+				created: model.created,
 				owned_by: model.owner,
+				capabilities: {
+					vision: model.capabilities.vision,
+					tools: model.capabilities.tools,
+					json_mode: model.capabilities.jsonMode,
+				},
+				tags: model.tags,
+				...(withStatus && this.#getStatus(model.id, loadedModels)),
 			})),
 		});
 	}
+
+	#getStatus(modelId: string, loadedModels: Set<string>) {
+		const lastUsed = this.#llamaProxyService.getLastUsed(modelId);
+		const usage = this.#usageService.getUsage(modelId);
+		return {
+			loaded: loadedModels.has(modelId),
+			last_used: lastUsed === undefined ? null : Math.floor(lastUsed / 1000),
+			load_duration_ms:
+				this.#llamaProxyService.getLoadDuration(modelId) ?? null,
+			usage: {
+				requests: usage?.requests ?? 0,
+				prompt_tokens: usage?.promptTokens ?? 0,
+				completion_tokens: usage?.completionTokens ?? 0,
+			},
+		};
+	}
 }
@@ -0,0 +1,222 @@
+import type { HttpBindings } from "@hono/node-server";
+import type { Context } from "hono";
+import { HTTPException } from "hono/http-exception";
+import { stream } from "hono/streaming";
+import type { ChatCompletion } from "#src/server/chatCompletion.ts";
+import { readSseData } from "#src/server/sse.ts";
+import type { LlamaProxyService } from "#src/services/llamaProxyService.ts";
+import type { ModelsService } from "#src/services/modelsService.ts";
+
+type OllamaMessage = {
+	role: string;
+	content: string;
+	/** Base64 encoded images */
+	images?: string[];
+};
+
+type OllamaChatRequest = {
+	model: string;
+	messages: OllamaMessage[];
+	stream?: boolean;
+	format?: "json" | Record<string, unknown>;
+	options?: Record<string, unknown>;
+};
+
+/** Ollama options and the chat completion parameters they map to */
+const OPTION_PARAMETERS: Record<string, string> = {
+	temperature: "temperature",
+	top_k: "top_k",
+	top_p: "top_p",
+	min_p: "min_p",
+	seed: "seed",
+	stop: "stop",
+	num_predict: "max_tokens",
+	repeat_penalty: "repeat_penalty",
+	presence_penalty: "presence_penalty",
+	frequency_penalty: "frequency_penalty",
+};
+
+/**
+ * Media type of a base64 encoded image, from its first bytes. Ollama does not
+ * send it, PNG is assumed for unknown formats.
+ */
+function getImageMediaType(image: string) {
+	// 16 characters decode to the 12 bytes a WebP header takes
+	const header = Buffer.from(image.slice(0, 16), "base64");
+	if (header[0] === 0xff && header[1] === 0xd8 && header[2] === 0xff) {
+		return "image/jpeg";
+	}
+	if (
+		header.toString("latin1", 0, 4) === "RIFF" &&
+		header.toString("latin1", 8, 12) === "WEBP"
+	) {
+		return "image/webp";
+	}
+	return "image/png";
+}
+
+function toChatCompletionMessage({ role, content, images }: OllamaMessage) {
+	if (!images?.length) {
+		return { role, content };
+	}
+	return {
+		role,
+		content: [
+			{ type: "text", text: content },
+			...images.map((image) => ({
+				type: "image_url",
+				image_url: { url: `data:${getImageMediaType(image)};base64,${image}` },
+			})),
+		],
+	};
+}
+
+function toResponseFormat(format: OllamaChatRequest["format"]) {
+	if (format === undefined) {
+		return undefined;
+	}
+	if (format === "json") {
+		return { type: "json_object" };
+	}
+	return { type: "json_schema", json_schema: { schema: format } };
+}
+
+function toChatCompletionRequest(request: OllamaChatRequest, stream: boolean) {
+	const parameters: Record<string, unknown> = {};
+	for (const [option, value] of Object.entries(request.options ?? {})) {
+		const parameter = OPTION_PARAMETERS[option];
+		if (parameter) {
+			parameters[parameter] = value;
+		}
+	}
+	return {
+		...parameters,
+		model: request.model,
+		messages: request.messages.map(toChatCompletionMessage),
+		response_format: toResponseFormat(request.format),
+		stream,
+	};
+}
+
+function toOllamaChatResponse(
+	model: string,
+	content: string,
+	done: boolean,
+	doneReason?: string | null,
+	usage?: ChatCompletion["usage"],
+) {
+	return {
+		model,
+		created_at: new Date().toISOString(),
+		message: { role: "assistant", content },
+		done,
+		...(done && {
+			done_reason: doneReason ?? "stop",
+			prompt_eval_count: usage?.prompt_tokens,
+			eval_count: usage?.completion_tokens,
+		}),
+	};
+}
+
+/**
+ * Translate the Ollama API to the OpenAI compatible routes, for clients that
+ * only speak the former.
+ */
+export class OllamaController {
+	readonly #modelsService: ModelsService;
+	readonly #llamaProxyService: LlamaProxyService;
+
+	constructor(
+		modelsService: ModelsService,
+		llamaProxyService: LlamaProxyService,
+	) {
+		this.#modelsService = modelsService;
+		this.#llamaProxyService = llamaProxyService;
+	}
+
+	async getTags(c: Context) {
+		const models = await this.#modelsService.getModels();
+		return c.json({
+			models: models.map((model) => ({
+				name: model.id,
+				model: model.id,
+				details: { format: "gguf" },
+			})),
+		});
+	}
+
+	async chat(c: Context<{ Bindings: HttpBindings }>) {
+		const request: OllamaChatRequest = await c.req.json();
+		// Ollama streams unless told otherwise
+		const isStreamingRequest = request.stream ?? true;
+		const abortController = new AbortController();
+		c.env.outgoing.on("close", () => {
+			abortController.abort();
+		});
+
+		const response = await this.#llamaProxyService.chatCompletion(
+			request.model,
+			abortController.signal,
+			JSON.stringify(toChatCompletionRequest(request, isStreamingRequest)),
+		);
+		if (!response) {
+			throw new HTTPException(500);
+		}
+
+		if (!isStreamingRequest) {
+			const completion: ChatCompletion = await new Response(response).json();
+			const choice = completion.choices[0];
+			return c.json(
+				toOllamaChatResponse(
+					request.model,
+					choice?.message?.content ?? "",
+					true,
+					choice?.finish_reason,
+					completion.usage,
+				),
+			);
+		}
+
+		c.header("Content-Type", "application/x-ndjson");
+		return stream(c, async (stream) => {
+			let doneReason: string | null | undefined;
+			let usage: ChatCompletion["usage"];
+			try {
+				for await (const data of readSseData(response)) {
+					if (data === "[DONE]") {
+						break;
+					}
+					const chunk: ChatCompletion = JSON.parse(data);
+					const choice = chunk.choices[0];
+					usage = chunk.usage ?? usage;
+					doneReason = choice?.finish_reason ?? doneReason;
+					const content = choice?.delta?.content;
+					if (content) {
+						const message = toOllamaChatResponse(
+							request.model,
+							content,
+							false,
+						);
+						await stream.write(`${JSON.stringify(message)}\n`);
+					}
+				}
+			} catch (e) {
+				if (!(e instanceof Error)) {
+					throw e;
+				}
+				// Ollama reports the errors of a stream as a line of their own
+				console.error(`[${c.req.method}] ${c.req.url} - ${e.message}`);
+				await stream.write(`${JSON.stringify({ error: e.message })}\n`);
+				return;
+			}
+			const last = toOllamaChatResponse(
+				request.model,
+				"",
+				true,
+				doneReason,
+				usage,
+			);
+			await stream.write(`${JSON.stringify(last)}\n`);
+		});
+	}
+}
@@ -0,0 +1,28 @@
+import type { MiddlewareHandler } from "hono";
+import { bodyLimit } from "hono/body-limit";
+import type { ConfigRepository } from "#src/repositories/configRepository.ts";
+import { createApiError } from "#src/server/apiError.ts";
+
+/**
+ * Reject the requests whose body is larger than the configured limit, before
+ * reading all of it.
+ */
+export function requestBodyLimit(
+	configRepository: ConfigRepository,
+): MiddlewareHandler {
+	return (c, next) => {
+		const { maxRequestBodyBytes } = configRepository.getServerConfiguration();
+		return bodyLimit({
+			maxSize: maxRequestBodyBytes,
+			onError: (c) =>
+				c.json(
+					createApiError(
+						`Request body exceeds the ${maxRequestBodyBytes} B limit`,
+						413,
+						"request_too_large",
+					),
+					413,
+				),
+		})(c, next);
+	};
+}
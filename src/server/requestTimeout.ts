@@ -0,0 +1,47 @@
+import type { MiddlewareHandler } from "hono";
+import type { ConfigRepository } from "#src/repositories/configRepository.ts";
+
+export class RequestTimeoutError extends Error {}
+
+/**
+ * Fail the requests that are not answered within the configured timeout,
+ * including the time spent loading their model. Streaming requests are left
+ * alone as they legitimately stay open for as long as the generation.
+ *
+ * @param isStreaming Tells from the JSON body of a request whether it streams,
+ * the requests of routes without one never do
+ */
+export function requestTimeout(
+	configRepository: ConfigRepository,
+	isStreaming?: (request: { stream?: unknown }) => boolean,
+): MiddlewareHandler {
+	return async (c, next) => {
+		const { requestTimeout } = configRepository.getServerConfiguration();
+		if (requestTimeout <= 0) {
+			return next();
+		}
+		if (isStreaming) {
+			// The parsed body is kept for the handler, which reports bad ones
+			const request = await c.req.json().catch(() => null);
+			if (isStreaming(request ?? {})) {
+				return next();
+			}
+		}
+
+		let timer: NodeJS.Timeout | undefined;
+		const timedOut = new Promise<never>((_, reject) => {
+			timer = setTimeout(() => {
+				reject(
+					new RequestTimeoutError(
+						`Request was not answered within ${requestTimeout} seconds`,
+					),
+				);
+			}, requestTimeout * 1000);
+		});
+		try {
+			await Promise.race([next(), timedOut]);
+		} finally {
+			clearTimeout(timer);
+		}
+	};
+}
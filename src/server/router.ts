@@ -1,11 +1,21 @@
 import type { HttpBindings } from "@hono/node-server";
 import { Hono } from "hono";
 import { cors } from "hono/cors";
+import { logger } from "hono/logger";
+import type { ConfigRepository } from "#src/repositories/configRepository.ts";
+import { apiKeyAuth } from "#src/server/auth.ts";
+import { compression } from "#src/server/compression.ts";
+import type { AnthropicController } from "#src/server/controllers/AnthropicController.ts";
 import type { ChatController } from "#src/server/controllers/ChatController.ts";
 import type { ConfigController } from "#src/server/controllers/ConfigController.ts";
 import type { EmbeddingsController } from "#src/server/controllers/EmbeddingsController.ts";
 import type { ModelFitsController } from "#src/server/controllers/ModelFitsController.ts";
 import type { ModelsController } from "#src/server/controllers/ModelsController.ts";
+import type { OllamaController } from "#src/server/controllers/OllamaController.ts";
+import type { RerankController } from "#src/server/controllers/RerankController.ts";
+import { requestBodyLimit } from "#src/server/requestBodyLimit.ts";
+import { requestTimeout } from "#src/server/requestTimeout.ts";
+import type { WebSocketHandler } from "#src/server/webSocket.ts";
 
 export class Router {
 	readonly #app: Hono<{ Bindings: HttpBindings }>;
@@ -14,6 +24,10 @@ export class Router {
 	readonly #configController: ConfigController;
 	readonly #chatController: ChatController;
 	readonly #embeddingsController: EmbeddingsController;
+	readonly #ollamaController: OllamaController;
+	readonly #anthropicController: AnthropicController;
+	readonly #rerankController: RerankController;
+	readonly #webSocketRoutes = new Map<string, WebSocketHandler>();
 
 	constructor(
 		modelsController: ModelsController,
@@ -21,6 +35,11 @@ export class Router {
 		chatController: ChatController,
 		embeddingsController: EmbeddingsController,
 		configController: ConfigController,
+		ollamaController: OllamaController,
+		anthropicController: AnthropicController,
+		rerankController: RerankController,
+		configRepository: ConfigRepository,
+		basePath: string = "/v1",
 	) {
 		this.#app = new Hono<{ Bindings: HttpBindings }>();
 		this.#modelsController = modelsController;
@@ -28,19 +47,57 @@ export class Router {
 		this.#chatController = chatController;
 		this.#embeddingsController = embeddingsController;
 		this.#configController = configController;
-		this.#registerRoutes();
+		this.#ollamaController = ollamaController;
+		this.#anthropicController = anthropicController;
+		this.#rerankController = rerankController;
+		this.#registerRoutes(configRepository, basePath);
 	}
 
-	#registerRoutes() {
+	#registerRoutes(configRepository: ConfigRepository, basePath: string) {
+		// Middlewares only run for the routes registered after them
+		this.#app.use("*", logger());
 		this.#app.use("/*", cors());
+		this.#app.use("*", compression());
+		this.#app.use("*", apiKeyAuth(configRepository));
 
-		this.#app.get("/v1/models", (c) => this.#modelsController.getModels(c));
-		this.#app.post("/v1/chat/completions", (c) =>
+		// Streaming requests are exempted from the timeout
+		const timeout = requestTimeout(configRepository);
+		const timeoutUnlessStreaming = requestTimeout(
+			configRepository,
+			(request) => request.stream === true,
+		);
+
+		const api = new Hono<{ Bindings: HttpBindings }>();
+		api.use("*", requestBodyLimit(configRepository));
+		api.get("/models", timeout, (c) => this.#modelsController.getModels(c));
+		api.post("/chat/completions", timeoutUnlessStreaming, (c) =>
 			this.#chatController.getChatCompletions(c),
 		);
-		this.#app.post("/v1/embeddings", (c) =>
+		api.post("/embeddings", timeout, (c) =>
 			this.#embeddingsController.getEmbeddings(c),
 		);
+		api.post("/rerank", timeout, (c) => this.#rerankController.getRerank(c));
+		// Anthropic compatible route
+		api.post("/messages", timeoutUnlessStreaming, (c) =>
+			this.#anthropicController.messages(c),
+		);
+		this.#app.route(basePath, api);
+		// Answered by the server, as upgrades do not go through Hono
+		this.#webSocketRoutes.set(`${basePath}/chat/completions/ws`, (connection) =>
+			this.#chatController.relayWebSocket(connection),
+		);
+
+		// Ollama compatible routes, which stream unless told otherwise
+		this.#app.use("/api/*", requestBodyLimit(configRepository));
+		this.#app.get("/api/tags", timeout, (c) =>
+			this.#ollamaController.getTags(c),
+		);
+		this.#app.post(
+			"/api/chat",
+			requestTimeout(configRepository, (request) => request.stream !== false),
+			(c) => this.#ollamaController.chat(c),
+		);
+
 		this.#app.get("/modelFits", (c) =>
 			this.#modelFitsController.getModelFits(c),
 		);
@@ -51,4 +108,11 @@ export class Router {
 	getApp(): Hono<{ Bindings: HttpBindings }> {
 		return this.#app;
 	}
+
+	/**
+	 * Handlers of the WebSocket upgrades, by path
+	 */
+	getWebSocketRoutes(): ReadonlyMap<string, WebSocketHandler> {
+		return this.#webSocketRoutes;
+	}
 }
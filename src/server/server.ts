@@ -1,73 +1,157 @@
-import { type HttpBindings, serve } from "@hono/node-server";
+import { lstatSync, readFileSync, unlinkSync } from "node:fs";
+import type { IncomingMessage, Server as HttpServer } from "node:http";
+import { createServer } from "node:https";
+import type { Duplex } from "node:stream";
+import {
+	createAdaptorServer,
+	type HttpBindings,
+	type ServerType,
+	serve,
+} from "@hono/node-server";
 import type { Hono } from "hono";
-import { HTTPException } from "hono/http-exception";
-import { logger } from "hono/logger";
+import { WebSocketServer } from "ws";
 import type { ConfigRepository } from "#src/repositories/configRepository.ts";
+import { createApiError, toApiError } from "#src/server/apiError.ts";
+import { hasValidApiKey } from "#src/server/auth.ts";
+import { rejectUpgrade, type WebSocketHandler } from "#src/server/webSocket.ts";
 
 export class Server {
 	readonly #app: Hono<{ Bindings: HttpBindings }>;
 	readonly #configRepository: ConfigRepository;
+	readonly #webSocketRoutes: ReadonlyMap<string, WebSocketHandler>;
+	#httpServer: ServerType | null = null;
 
+	/**
+	 * @param webSocketRoutes Handlers of the WebSocket upgrades, by path
+	 */
 	constructor(
 		app: Hono<{ Bindings: HttpBindings }>,
 		configRepository: ConfigRepository,
+		webSocketRoutes: ReadonlyMap<string, WebSocketHandler> = new Map(),
 	) {
 		this.#app = app;
 		this.#configRepository = configRepository;
-		this.#setupMiddleware();
+		this.#webSocketRoutes = webSocketRoutes;
 		this.#setupErrorHandling();
 	}
 
-	#setupMiddleware() {
-		// Add logging middleware
-		this.#app.use("*", logger());
-	}
-
 	#setupErrorHandling() {
 		// Global error handler
 		this.#app.onError((err, c) => {
 			console.error(`[${c.req.method}] ${c.req.url} - ${err.message}`);
 
-			if (err instanceof HTTPException) {
-				return c.json(
-					{
-						error: err.message,
-					},
-					err.status,
-				);
-			}
-
-			return c.json(
-				{
-					error: err.message,
-				},
-				500,
-			);
+			const { status, body, headers } = toApiError(err);
+			return c.json(body, status, headers);
 		});
 
 		// 404 handler
 		this.#app.notFound((c) => {
-			return c.json(
-				{
-					notFound: true,
-				},
-				404,
-			);
+			return c.json(createApiError("Not found", 404, "not_found"), 404);
 		});
 	}
 
 	run() {
-		const { port, hostname } = this.#configRepository.getServerConfiguration();
-		serve(
-			{
-				fetch: this.#app.fetch,
-				hostname,
-				port,
-			},
-			(info) => {
-				const address = `http://${info.address}:${info.port}`;
+		const {
+			hostname = "0.0.0.0",
+			port = 8080,
+			socketPath,
+			tls,
+		} = this.#configRepository.getServerConfiguration();
+		const options = tls
+			? {
+					fetch: this.#app.fetch,
+					createServer,
+					serverOptions: {
+						cert: readFileSync(tls.certPath),
+						key: readFileSync(tls.keyPath),
+					},
+				}
+			: {
+					fetch: this.#app.fetch,
+				};
+
+		if (socketPath) {
+			// Remove the socket left behind by a previous run
+			if (lstatSync(socketPath, { throwIfNoEntry: false })?.isSocket()) {
+				unlinkSync(socketPath);
+			}
+			this.#httpServer = createAdaptorServer(options).listen(socketPath, () => {
+				console.log("🌐 Server listening on", socketPath);
+			});
+		} else {
+			this.#httpServer = serve({ ...options, hostname, port }, (info) => {
+				const protocol = tls ? "https" : "http";
+				const address = `${protocol}://${info.address}:${info.port}`;
 				console.log("🌐 Server listening on", address);
-			},
+			});
+		}
+		// Both the HTTP and HTTPS servers emit the upgrade requests
+		(this.#httpServer as HttpServer).on("upgrade", (request, socket, head) =>
+			this.handleUpgrade(request, socket, head),
 		);
 	}
+
+	/**
+	 * Open the WebSocket of an upgrade request to one of the WebSocket routes,
+	 * which bypass the middlewares of the HTTP routes and check the API key
+	 * themselves
+	 */
+	handleUpgrade(request: IncomingMessage, socket: Duplex, head: Buffer): void {
+		const { pathname } = new URL(request.url ?? "/", "http://localhost");
+		const handler = this.#webSocketRoutes.get(pathname);
+		if (!handler) {
+			rejectUpgrade(socket, 404, createApiError("Not found", 404, "not_found"));
+			return;
+		}
+		const apiKeys = this.#configRepository.getApiKeys();
+		const getHeader = (name: string) => {
+			const value = request.headers[name.toLowerCase()];
+			return Array.isArray(value) ? value[0] : value;
+		};
+		if (!hasValidApiKey(apiKeys, getHeader)) {
+			rejectUpgrade(
+				socket,
+				401,
+				createApiError("Invalid API key", 401, "invalid_api_key"),
+			);
+			return;
+		}
+
+		const { maxRequestBodyBytes } =
+			this.#configRepository.getServerConfiguration();
+		// Created for each upgrade so that the limit follows the reloads of the
+		// configuration. It answers the invalid handshakes and frames itself.
+		const webSocketServer = new WebSocketServer({
+			noServer: true,
+			clientTracking: false,
+			maxPayload: maxRequestBodyBytes,
+		});
+		webSocketServer.handleUpgrade(request, socket, head, (webSocket) =>
+			handler(webSocket),
+		);
+	}
+
+	/**
+	 * Stop accepting connections and wait for the ongoing requests, at most
+	 * for the shutdown timeout after which their connections are closed
+	 */
+	async shutdown(): Promise<void> {
+		const httpServer = this.#httpServer;
+		if (!httpServer) {
+			return;
+		}
+		const timeoutSeconds = this.#configRepository.getShutdownTimeout();
+		await new Promise<void>((resolve) => {
+			httpServer.close(() => resolve());
+			if (timeoutSeconds > 0) {
+				setTimeout(() => {
+					console.warn(
+						`Requests still ongoing after ${timeoutSeconds} s, exiting anyway`,
+					);
+					(httpServer as HttpServer).closeAllConnections();
+					resolve();
+				}, timeoutSeconds * 1000).unref();
+			}
+		});
+	}
 }
@@ -0,0 +1,25 @@
+/**
+ * Read the data of each event of a Server-Sent Events stream, such as the
+ * streamed chat completions of llama-server. Characters split across chunks
+ * are decoded once complete.
+ */
+export async function* readSseData(
+	body: ReadableStream<Uint8Array>,
+): AsyncGenerator<string> {
+	let buffer = "";
+	for await (const text of body.pipeThrough(new TextDecoderStream())) {
+		buffer += text;
+		const lines = buffer.split("\n");
+		// Keep the last line until it is complete
+		buffer = lines.pop() ?? "";
+		for (const line of lines) {
+			if (line.startsWith("data:")) {
+				yield line.slice("data:".length).trim();
+			}
+		}
+	}
+	// The stream may end without a final newline
+	if (buffer.startsWith("data:")) {
+		yield buffer.slice("data:".length).trim();
+	}
+}
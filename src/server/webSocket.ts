@@ -0,0 +1,47 @@
+import { STATUS_CODES } from "node:http";
+import type { Duplex } from "node:stream";
+import type { WebSocket } from "ws";
+
+/** Status codes of the close frames sent by the routes */
+export const CloseCode = {
+	normal: 1000,
+	unsupportedData: 1003,
+	internalError: 1011,
+} as const;
+
+/**
+ * Handle of the routes answered over a WebSocket rather than HTTP
+ */
+export type WebSocketHandler = (socket: WebSocket) => void;
+
+/**
+ * Answer an upgrade request with an HTTP error and close its socket
+ */
+export function rejectUpgrade(
+	socket: Duplex,
+	status: number,
+	body: unknown,
+): void {
+	const json = JSON.stringify(body);
+	socket.end(
+		[
+			`HTTP/1.1 ${status} ${STATUS_CODES[status]}`,
+			"Connection: close",
+			"Content-Type: application/json",
+			`Content-Length: ${Buffer.byteLength(json)}`,
+			"",
+			json,
+		].join("\r\n"),
+	);
+}
+
+/**
+ * Send a text message and wait until it is written to the socket, so that a
+ * slow client holds the sender back instead of the messages piling up in
+ * memory
+ */
+export function sendMessage(socket: WebSocket, message: string): Promise<void> {
+	return new Promise((resolve, reject) => {
+		socket.send(message, (error) => (error ? reject(error) : resolve()));
+	});
+}
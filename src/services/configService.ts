@@ -1,4 +1,7 @@
-import type { ConfigRepository } from "#src/repositories/configRepository.ts";
+import type {
+	ConfigFile,
+	ConfigRepository,
+} from "#src/repositories/configRepository.ts";
 
 export class ConfigService {
 	readonly #configRepository: ConfigRepository;
@@ -7,11 +10,30 @@ export class ConfigService {
 		this.#configRepository = configRepository;
 	}
 
-	public getConfig() {
-		return this.#configRepository.getConfig();
+	/**
+	 * Configuration without its `auth` section, which holds the API keys
+	 */
+	public getConfig(): Omit<ConfigFile, "auth"> {
+		const { auth: _auth, ...config } = this.#configRepository.getConfig();
+		return config;
 	}
 
+	/**
+	 * Replace the configuration, keeping its `auth` section. It can only be
+	 * changed in the configuration file, so that a key cannot add others.
+	 * @throws Error if the JSON holds an `auth` section or is invalid
+	 */
 	public async reloadConfig(json: unknown): Promise<void> {
-		await this.#configRepository.reloadFromJson(json);
+		if (typeof json !== "object" || json === null) {
+			await this.#configRepository.reloadFromJson(json);
+			return;
+		}
+		if ("auth" in json) {
+			throw new Error(
+				"auth cannot be changed through /config, edit the configuration file instead",
+			);
+		}
+		const { auth } = this.#configRepository.getConfig();
+		await this.#configRepository.reloadFromJson({ ...json, auth });
 	}
 }
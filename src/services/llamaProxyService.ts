@@ -1,81 +1,368 @@
-import type { ConfigRepository } from "#src/repositories/configRepository.ts";
-import type { LlamaServerRepository } from "#src/repositories/llamaServerRepository.ts";
+import type {
+	ConfigRepository,
+	ModelConfiguration,
+	ModelTask,
+} from "#src/repositories/configRepository.ts";
+import {
+	LlamaServerStartError,
+	type LlamaServerRuntime,
+} from "#src/repositories/llamaServerRepository.ts";
 import {
 	type ModelFitService,
 	ModelNotFoundError,
 } from "#src/services/modelFitService.ts";
+import type { UsageService } from "#src/services/usageService.ts";
 
 export class InsufficientMemoryError extends Error {}
 export class NotSupportedError extends Error {}
+export class ResponseTooLargeError extends Error {}
+export class StreamIdleTimeoutError extends Error {}
+export class ServerBusyError extends Error {}
+export class TooManyRequestsError extends Error {}
+
+/**
+ * Raised when the llama-server answers with a non-success status.
+ */
+export class UpstreamError extends Error {
+	/** HTTP status returned by the llama-server */
+	public readonly status: number;
+
+	constructor(message: string, status: number) {
+		super(message);
+		this.status = status;
+	}
+}
+
+/**
+ * Raised in the "retry" load mode while the requested model is loading.
+ */
+export class ModelLoadingError extends Error {
+	public readonly retryAfterSeconds: number;
+
+	constructor(message: string, retryAfterSeconds: number) {
+		super(message);
+		this.retryAfterSeconds = retryAfterSeconds;
+	}
+}
+
+// Delay suggested to clients in the "retry" load mode
+const LOAD_RETRY_AFTER_SECONDS = 5;
+
+// Seconds the error of a failed load is returned before loading again
+const LOAD_ERROR_COOLDOWN_SECONDS = 30;
+
+/** llama-server route of each kind of request */
+type Resource = "chat/completions" | "embeddings" | "rerank";
+
+/** Number of least recently used models considered for an eviction */
+const MAX_EVICTION_CANDIDATES = 16;
+
+export type EvictionCandidate = {
+	name: string;
+	vramBytes: number;
+};
+
+/**
+ * Pick the models to unload to free at least `deficitBytes` while keeping as
+ * much as possible loaded: the set freeing the least VRAM, then the one with
+ * the fewest models. Candidates are given least recently used first, which
+ * is also the order of the result. When no set frees enough, all considered
+ * candidates are returned.
+ */
+export function selectModelsToEvict(
+	candidates: EvictionCandidate[],
+	deficitBytes: number,
+): string[] {
+	if (deficitBytes <= 0) {
+		return [];
+	}
+
+	const considered = candidates.slice(0, MAX_EVICTION_CANDIDATES);
+	let best: EvictionCandidate[] = considered;
+	let bestBytes = Number.POSITIVE_INFINITY;
+	for (let mask = 1; mask < 1 << considered.length; mask++) {
+		const subset = considered.filter((_, index) => mask & (1 << index));
+		const bytes = subset.reduce((sum, { vramBytes }) => sum + vramBytes, 0);
+		if (
+			bytes >= deficitBytes &&
+			(bytes < bestBytes ||
+				(bytes === bestBytes && subset.length < best.length))
+		) {
+			best = subset;
+			bestBytes = bytes;
+		}
+	}
+	return best.map(({ name }) => name);
+}
 
 export class LlamaProxyService {
 	readonly #configRepository: ConfigRepository;
-	readonly #llamaServerRepository: LlamaServerRepository;
+	readonly #llamaServerRuntime: LlamaServerRuntime;
 	readonly #modelFitService: ModelFitService;
+	readonly #usageService: UsageService;
 
 	readonly #models = new Map<string, number>();
-	readonly #ongoingRequests = new Set<string>();
+	readonly #loadingModels = new Map<string, Promise<void>>();
+	// Requests whose response is not fully sent yet, per model
+	readonly #ongoingRequests = new Map<string, number>();
+	// Requests whose response is not fully sent yet, for all models
+	#activeRequests = 0;
 	readonly #lastUsed = new Map<string, number>();
+	readonly #loadDurations = new Map<string, number>();
+	// Error of the last failed background load, with the configuration it
+	// was tried with and when it failed
+	readonly #loadErrors = new Map<
+		string,
+		{ error: unknown; modelConfig: ModelConfiguration; failedAt: number }
+	>();
 	readonly #unloadTimers = new Map<string, NodeJS.Timeout>(); // Track unload timers
 
 	constructor(
 		configRepository: ConfigRepository,
-		llamaServerRepository: LlamaServerRepository,
+		llamaServerRuntime: LlamaServerRuntime,
 		modelFitService: ModelFitService,
+		usageService: UsageService,
 	) {
 		this.#configRepository = configRepository;
-		this.#llamaServerRepository = llamaServerRepository;
+		this.#llamaServerRuntime = llamaServerRuntime;
 		this.#modelFitService = modelFitService;
+		this.#usageService = usageService;
+	}
+
+	public getLoadedModels(): string[] {
+		return Array.from(this.#models.keys());
+	}
+
+	/**
+	 * Timestamp in milliseconds of the last request to a loaded model
+	 */
+	public getLastUsed(modelName: string): number | undefined {
+		return this.#lastUsed.get(modelName);
+	}
+
+	/**
+	 * Milliseconds the last successful start of a model took, kept after it
+	 * is unloaded to help tune the startup timeout
+	 */
+	public getLoadDuration(modelName: string): number | undefined {
+		return this.#loadDurations.get(modelName);
+	}
+
+	/**
+	 * Take over the servers left running by a previous router, such as after a
+	 * crash, which would otherwise hold the port and VRAM of their model. The
+	 * adopted models count as just used, to not be the first ones evicted.
+	 */
+	public async adoptRunningModels(): Promise<void> {
+		for (const modelName of this.#configRepository.getAvailableModelNames()) {
+			const modelConfig =
+				this.#configRepository.getModelConfiguration(modelName);
+			if (!modelConfig || this.#models.has(modelName)) {
+				continue;
+			}
+			let adoptedConfig = modelConfig;
+			if (modelConfig.common.parallel === "auto") {
+				// The slot count is read from the server, which was started with the
+				// context size of one slot times the slot count
+				const contextSize =
+					await this.#modelFitService.resolveContextSize(modelName);
+				adoptedConfig = {
+					...modelConfig,
+					common: { ...modelConfig.common, contextSize },
+				};
+			}
+			const handle = await this.#llamaServerRuntime.adopt(adoptedConfig);
+			if (!handle) {
+				continue;
+			}
+			console.log(
+				`Adopted ${modelName}, already running as PID ${handle.pid}`,
+			);
+			this.#models.set(modelName, handle.pid);
+			this.#lastUsed.set(modelName, Date.now());
+			this.#resetUnloadTimer(modelName);
+			this.#watchCrash(modelName, handle.pid);
+		}
+	}
+
+	/**
+	 * Stop the llama-server of every loaded model
+	 */
+	public async unloadAll(): Promise<void> {
+		await Promise.all(
+			Array.from(this.#models.keys(), (modelName) =>
+				this.#unloadModel(modelName),
+			),
+		);
 	}
 
 	public async chatCompletion(
 		modelName: string,
 		abortSignal: AbortSignal,
 		body?: BodyInit | null,
+		headers: Record<string, string> = {},
 	): Promise<ReadableStream<Uint8Array<ArrayBuffer>> | null> {
-		this.#ongoingRequests.add(modelName);
-		return this.#forwardRequest(
+		return this.#forwardTaskRequest(
 			modelName,
+			"chat",
 			"chat/completions",
 			abortSignal,
 			body,
-		).finally(() => {
-			this.#ongoingRequests.delete(modelName);
-		});
+			headers,
+		);
 	}
 
 	public async embeddings(
 		modelName: string,
 		abortSignal: AbortSignal,
 		body?: BodyInit | null,
+		headers: Record<string, string> = {},
 	): Promise<ReadableStream<Uint8Array<ArrayBuffer>> | null> {
-		// Ensure model supports embeddings
+		return this.#forwardTaskRequest(
+			modelName,
+			"embedding",
+			"embeddings",
+			abortSignal,
+			body,
+			headers,
+		);
+	}
+
+	public async rerank(
+		modelName: string,
+		abortSignal: AbortSignal,
+		body?: BodyInit | null,
+		headers: Record<string, string> = {},
+	): Promise<ReadableStream<Uint8Array<ArrayBuffer>> | null> {
+		return this.#forwardTaskRequest(
+			modelName,
+			"rerank",
+			"rerank",
+			abortSignal,
+			body,
+			headers,
+		);
+	}
+
+	/**
+	 * Throw the error a request would be rejected with before its model is
+	 * loaded, for a model of another task or over a concurrency limit, so that
+	 * it can be answered before a response is started. Requests sent right
+	 * after, without awaiting anything, pass these checks.
+	 */
+	public checkRequest(modelName: string, task: ModelTask): void {
+		// Ensure the model is launched for this kind of request
 		const modelConfig = this.#configRepository.getModelConfiguration(modelName);
 		if (!modelConfig) {
 			throw new ModelNotFoundError(
 				"modelConfig is missing a valid configuration object",
 			);
 		}
-		if (!modelConfig.embeddings) {
-			throw new NotSupportedError("This server does not support embeddings.");
+		if (modelConfig.task !== task) {
+			throw new NotSupportedError(
+				`${modelName} is a ${modelConfig.task} model and does not support ${task} requests`,
+			);
 		}
 
-		this.#ongoingRequests.add(modelName);
-		return this.#forwardRequest(
-			modelName,
-			"embeddings",
-			abortSignal,
-			body,
-		).finally(() => {
-			this.#ongoingRequests.delete(modelName);
+		const ongoing = this.#ongoingRequests.get(modelName) ?? 0;
+		const { maxConcurrent } = modelConfig;
+		if (maxConcurrent !== undefined && ongoing >= maxConcurrent) {
+			throw new TooManyRequestsError(
+				`${modelName} is already serving ${ongoing} requests`,
+			);
+		}
+		const maxRequests = this.#configRepository.getMaxConcurrentRequests();
+		if (maxRequests !== undefined && this.#activeRequests >= maxRequests) {
+			throw new ServerBusyError(
+				`${this.#activeRequests} requests are already being served`,
+			);
+		}
+	}
+
+	async #forwardTaskRequest(
+		modelName: string,
+		task: ModelTask,
+		resource: Resource,
+		abortSignal: AbortSignal,
+		body: BodyInit | null | undefined,
+		headers: Record<string, string>,
+	): Promise<ReadableStream<Uint8Array<ArrayBuffer>> | null> {
+		this.checkRequest(modelName, task);
+
+		const ongoing = this.#ongoingRequests.get(modelName) ?? 0;
+		this.#activeRequests++;
+		this.#ongoingRequests.set(modelName, ongoing + 1);
+		const release = () => {
+			this.#activeRequests--;
+			const remaining = (this.#ongoingRequests.get(modelName) ?? 0) - 1;
+			if (remaining > 0) {
+				this.#ongoingRequests.set(modelName, remaining);
+			} else {
+				this.#ongoingRequests.delete(modelName);
+			}
+		};
+		try {
+			const response = await this.#forwardRequest(
+				modelName,
+				resource,
+				abortSignal,
+				body,
+				headers,
+			);
+			return this.#onStreamEnd(response, release);
+		} catch (error) {
+			release();
+			throw error;
+		}
+	}
+
+	/**
+	 * Call `callback` once when the body is fully read, errored or cancelled
+	 */
+	#onStreamEnd(
+		body: ReadableStream<Uint8Array<ArrayBuffer>> | null,
+		callback: () => void,
+	): ReadableStream<Uint8Array<ArrayBuffer>> | null {
+		if (!body) {
+			callback();
+			return null;
+		}
+		let ended = false;
+		const end = () => {
+			if (!ended) {
+				ended = true;
+				callback();
+			}
+		};
+		const reader = body.getReader();
+		return new ReadableStream({
+			async pull(controller) {
+				try {
+					const { done, value } = await reader.read();
+					if (done) {
+						end();
+						controller.close();
+					} else {
+						controller.enqueue(value);
+					}
+				} catch (error) {
+					end();
+					controller.error(error);
+				}
+			},
+			cancel(reason) {
+				end();
+				return reader.cancel(reason);
+			},
 		});
 	}
 
 	async #forwardRequest(
 		modelName: string,
-		resource: "chat/completions" | "embeddings",
+		resource: Resource,
 		abortSignal: AbortSignal,
-		body?: BodyInit | null,
+		body: BodyInit | null | undefined,
+		headers: Record<string, string>,
 	): Promise<ReadableStream<Uint8Array<ArrayBuffer>> | null> {
 		const modelConfig = this.#configRepository.getModelConfiguration(modelName);
 		if (!modelConfig) {
@@ -85,50 +372,49 @@ export class LlamaProxyService {
 		}
 
 		if (!this.#models.has(modelName)) {
-			// Check concurrent model limit first
-			const maxConcurrent = this.#configRepository.getConcurrentModels();
-			if (maxConcurrent > 0 && this.#models.size >= maxConcurrent) {
-				console.log(
-					`Concurrent model limit (${maxConcurrent}) reached, unloading least recently used models`,
-				);
-				await this.#unloadModelsUntilCount(maxConcurrent - 1);
-			}
-
-			// Check if model will fit in memory
-			let fitResult = await this.#modelFitService.willModelFit(modelName);
-			if (!fitResult.fits) {
-				const candidates = this.#getUnloadableCandidates();
-
-				for (const candidateName of candidates) {
-					await this.#unloadModel(candidateName);
-					fitResult = await this.#modelFitService.willModelFit(modelName);
-					if (fitResult.fits) {
-						break;
-					}
+			const retry = this.#configRepository.getLoadMode() === "retry";
+			// Concurrent requests for a cold model share the same load
+			let loading = this.#loadingModels.get(modelName);
+			if (!loading) {
+				// Retrying a load that failed would answer 503 forever, its error
+				// is returned instead until the configuration changes or for a
+				// cooldown, after which the next request loads the model again
+				const loadError = this.#loadErrors.get(modelName);
+				if (
+					retry &&
+					loadError?.modelConfig === modelConfig &&
+					Date.now() - loadError.failedAt < LOAD_ERROR_COOLDOWN_SECONDS * 1000
+				) {
+					throw loadError.error;
+				}
+				loading = this.#loadModel(modelName, modelConfig).finally(() => {
+					this.#loadingModels.delete(modelName);
+				});
+				this.#loadingModels.set(modelName, loading);
+				if (retry) {
+					// Nobody awaits the load, report its failure here
+					loading.then(
+						() => {
+							this.#loadErrors.delete(modelName);
+						},
+						(error) => {
+							console.error(`Failed to load ${modelName}: ${error.message}`);
+							this.#loadErrors.set(modelName, {
+								error,
+								modelConfig,
+								failedAt: Date.now(),
+							});
+						},
+					);
 				}
 			}
-
-			if (!fitResult.fits) {
-				throw new InsufficientMemoryError(
-					`${modelName} needs ${fitResult.requiredVramBytes} B but only ${fitResult.freeVramBytes} B available after attempting to unload other models`,
+			if (retry) {
+				throw new ModelLoadingError(
+					`${modelName} is loading, retry later`,
+					LOAD_RETRY_AFTER_SECONDS,
 				);
 			}
-
-			console.log(`Loading ${modelName}`);
-			const llamaServerHandle =
-				await this.#llamaServerRepository.start(modelConfig);
-			this.#models.set(modelName, llamaServerHandle.pid);
-
-			// Register crash handler to clean up state when process dies unexpectedly
-			this.#llamaServerRepository.onProcessCrash(
-				llamaServerHandle.pid,
-				(pid: number) => {
-					console.error(
-						`Model ${modelName} (PID ${pid}) crashed, cleaning up state`,
-					);
-					this.#cleanModelState(modelName);
-				},
-			);
+			await loading;
 		}
 
 		this.#resetUnloadTimer(modelName);
@@ -138,14 +424,248 @@ export class LlamaProxyService {
 		const originServer = `http://${modelConfig.network.host}:${modelConfig.network.port}`;
 		const response = await fetch(`${originServer}/v1/${resource}`, {
 			method: "POST",
+			// Extra headers such as an Authorization for the llama-server
 			headers: {
 				"Content-Type": "application/json",
 				Accept: "application/json",
+				...headers,
 			},
 			signal: abortSignal,
 			body,
 		});
-		return response.body;
+		if (!response.ok) {
+			const text = await response.text();
+			throw new UpstreamError(
+				`${modelName} responded with ${response.status}: ${text}`,
+				response.status,
+			);
+		}
+
+		if (!response.body) {
+			return null;
+		}
+		const idleTimeout = this.#configRepository.getStreamIdleTimeout();
+		let body =
+			idleTimeout > 0
+				? this.#limitIdleTime(response.body, modelName, idleTimeout)
+				: response.body;
+
+		const contentType = response.headers.get("Content-Type") ?? "";
+		const isEventStream = contentType.startsWith("text/event-stream");
+		const { maxResponseBytes } = modelConfig;
+		if (maxResponseBytes !== null) {
+			const contentLength = Number(response.headers.get("Content-Length"));
+			if (contentLength > maxResponseBytes) {
+				await body.cancel();
+				throw new ResponseTooLargeError(
+					`${modelName} response of ${contentLength} B exceeds the ${maxResponseBytes} B limit`,
+				);
+			}
+			body = this.#limitResponseSize(body, modelName, maxResponseBytes);
+			if (!isEventStream) {
+				// A chunked response has no length, read it whole so that it is
+				// rejected before its status is sent
+				body = await this.#bufferResponse(body);
+			}
+		}
+
+		return this.#usageService.trackUsage(modelName, body, isEventStream);
+	}
+
+	/**
+	 * Read a whole response, rejecting with the error of the stream
+	 */
+	async #bufferResponse(
+		body: ReadableStream<Uint8Array<ArrayBuffer>>,
+	): Promise<ReadableStream<Uint8Array<ArrayBuffer>>> {
+		const chunks: Uint8Array<ArrayBuffer>[] = [];
+		for await (const chunk of body) {
+			chunks.push(chunk);
+		}
+		return ReadableStream.from(chunks);
+	}
+
+	/**
+	 * Error the stream and close the connection when the llama-server sends
+	 * nothing for `timeoutSeconds`
+	 */
+	#limitIdleTime(
+		body: ReadableStream<Uint8Array<ArrayBuffer>>,
+		modelName: string,
+		timeoutSeconds: number,
+	): ReadableStream<Uint8Array<ArrayBuffer>> {
+		const reader = body.getReader();
+		return new ReadableStream({
+			async pull(controller) {
+				let timeout: NodeJS.Timeout | undefined;
+				const idle = new Promise<never>((_, reject) => {
+					timeout = setTimeout(() => {
+						reject(
+							new StreamIdleTimeoutError(
+								`${modelName} sent nothing for ${timeoutSeconds} s`,
+							),
+						);
+					}, timeoutSeconds * 1000);
+				});
+				try {
+					const { done, value } = await Promise.race([reader.read(), idle]);
+					if (done) {
+						controller.close();
+					} else {
+						controller.enqueue(value);
+					}
+				} catch (error) {
+					controller.error(error);
+					await reader.cancel(error).catch(() => {});
+				} finally {
+					clearTimeout(timeout);
+				}
+			},
+			cancel(reason) {
+				return reader.cancel(reason);
+			},
+		});
+	}
+
+	/**
+	 * Error the stream once more than `maxBytes` have been forwarded
+	 */
+	#limitResponseSize(
+		body: ReadableStream<Uint8Array<ArrayBuffer>>,
+		modelName: string,
+		maxBytes: number,
+	): ReadableStream<Uint8Array<ArrayBuffer>> {
+		let forwardedBytes = 0;
+		return body.pipeThrough(
+			new TransformStream<Uint8Array<ArrayBuffer>, Uint8Array<ArrayBuffer>>({
+				transform(chunk, controller) {
+					forwardedBytes += chunk.byteLength;
+					if (forwardedBytes > maxBytes) {
+						controller.error(
+							new ResponseTooLargeError(
+								`${modelName} response exceeds the ${maxBytes} B limit`,
+							),
+						);
+						return;
+					}
+					controller.enqueue(chunk);
+				},
+			}),
+		);
+	}
+
+	async #loadModel(
+		modelName: string,
+		modelConfig: ModelConfiguration,
+	): Promise<void> {
+		// Check concurrent model limit first
+		const maxConcurrent = this.#configRepository.getConcurrentModels();
+		if (maxConcurrent > 0 && this.#models.size >= maxConcurrent) {
+			console.log(
+				`Concurrent model limit (${maxConcurrent}) reached, unloading least recently used models`,
+			);
+			await this.#unloadModelsUntilCount(maxConcurrent - 1);
+		}
+
+		// Check if model will fit in memory
+		let fitResult = await this.#modelFitService.reserveIfFits(modelName);
+		if (!fitResult.fits) {
+			// Unloading models from another GPU would not make room
+			const candidates = this.#getUnloadableCandidates().filter((name) =>
+				this.#modelFitService.sharesDevice(name, modelName),
+			);
+			const selected = selectModelsToEvict(
+				await Promise.all(
+					candidates.map(async (name) => ({
+						name,
+						vramBytes: await this.#modelFitService.estimateRequiredVram(name),
+					})),
+				),
+				fitResult.requiredVramBytes - fitResult.freeVramBytes,
+			);
+			// Estimates are approximate, fall back to the others in LRU order
+			const evictionOrder = [
+				...selected,
+				...candidates.filter((name) => !selected.includes(name)),
+			];
+
+			for (const candidateName of evictionOrder) {
+				await this.#unloadModel(candidateName);
+				// The last VRAM poll predates the unload
+				fitResult = await this.#modelFitService.reserveIfFits(modelName, true);
+				if (fitResult.fits) {
+					break;
+				}
+			}
+		}
+
+		if (!fitResult.fits) {
+			throw new InsufficientMemoryError(
+				`${modelName} needs ${fitResult.requiredVramBytes} B but only ${fitResult.freeVramBytes} B available after attempting to unload other models`,
+			);
+		}
+
+		// Until the server has allocated its VRAM, it is only accounted for by the
+		// reservation taken with the fit check
+		try {
+			let serverConfig = modelConfig;
+			if (modelConfig.common.parallel === "auto") {
+				const parallel =
+					await this.#modelFitService.estimateParallelSlots(modelName);
+				const contextSize =
+					await this.#modelFitService.resolveContextSize(modelName);
+				console.log(`Using ${parallel} parallel slots for ${modelName}`);
+				// The fit check only reserved the VRAM of one slot
+				await this.#modelFitService.reserveParallelSlots(modelName, parallel);
+				serverConfig = {
+					...modelConfig,
+					common: {
+						...modelConfig.common,
+						parallel,
+						contextSize: contextSize * parallel,
+					},
+				};
+			}
+
+			const metadata = await this.#modelFitService.getModelMetadata(modelName);
+			console.log(
+				`Loading ${modelName}${metadata?.name ? ` (${metadata.name})` : ""}`,
+			);
+			const startedAt = performance.now();
+			const llamaServerHandle = await this.#llamaServerRuntime
+				.start(serverConfig, {
+					timeoutMs: this.#configRepository.getStartupTimeout() * 1000,
+					logLines: this.#configRepository.getStartupLogLines(),
+				})
+				.catch((error) => {
+					if (error instanceof LlamaServerStartError && error.stderr) {
+						console.error(
+							`Failed to start ${modelName}, last llama-server logs:\n${error.stderr}`,
+						);
+					}
+					throw error;
+				});
+			this.#models.set(modelName, llamaServerHandle.pid);
+			const loadDuration = Math.round(performance.now() - startedAt);
+			this.#loadDurations.set(modelName, loadDuration);
+			console.log(`Loaded ${modelName} in ${loadDuration} ms`);
+
+			this.#watchCrash(modelName, llamaServerHandle.pid);
+		} finally {
+			this.#modelFitService.releaseReservation(modelName);
+		}
+	}
+
+	/**
+	 * Register crash handler to clean up state when process dies unexpectedly
+	 */
+	#watchCrash(modelName: string, pid: number): void {
+		this.#llamaServerRuntime.onProcessCrash(pid, (pid: number) => {
+			console.error(
+				`Model ${modelName} (PID ${pid}) crashed, cleaning up state`,
+			);
+			this.#cleanModelState(modelName);
+		});
 	}
 
 	#resetUnloadTimer(modelName: string): void {
@@ -172,7 +692,7 @@ export class LlamaProxyService {
 			console.log(`Unloading ${modelName}`);
 
 			try {
-				await this.#llamaServerRepository.stop(pid);
+				await this.#llamaServerRuntime.stop(pid);
 			} catch (error) {
 				console.error(`Failed to stop model ${modelName}:`, error);
 			} finally {
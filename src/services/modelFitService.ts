@@ -6,11 +6,13 @@ import type {
 	EstimateParameters,
 	GgufParserJson,
 	GgufParserRepository,
+	MetadataPayload,
 } from "#src/repositories/ggufParserRepository.ts";
-import type {
-	RocmSmiQueryOptions,
-	RocmSmiRepository,
-	RocmSmiVramInfo,
+import {
+	type RocmSmiQueryOptions,
+	type RocmSmiRepository,
+	type RocmSmiVramInfo,
+	sumVramInfos,
 } from "#src/repositories/rocmSmiRepository.ts";
 
 export type ModelFitResult = {
@@ -21,13 +23,48 @@ export type ModelFitResult = {
 	message: string;
 };
 
+export type OversizedModel = {
+	modelName: string;
+	requiredVramBytes: number;
+	totalVramBytes: number;
+};
+
 export class ModelNotFoundError extends Error {}
 
+/** Context size used when 0 is configured but the model does not define one */
+export const DEFAULT_CONTEXT_SIZE = 4096;
+
+/** Upper bound of slots picked by the "auto" parallel mode */
+export const MAX_AUTO_PARALLEL_SLOTS = 32;
+
+/**
+ * Compute the largest number of slots whose KV cache fits in the budget
+ * alongside the model weights, always keeping at least one slot.
+ */
+export function computeParallelSlots(
+	weightBytes: number,
+	perSlotBytes: number,
+	budgetBytes: number,
+): number {
+	if (perSlotBytes <= 0) {
+		return MAX_AUTO_PARALLEL_SLOTS;
+	}
+	const slots = Math.floor((budgetBytes - weightBytes) / perSlotBytes);
+	return Math.min(Math.max(slots, 1), MAX_AUTO_PARALLEL_SLOTS);
+}
+
 export class ModelFitService {
 	readonly #ggufParserRepository: GgufParserRepository;
 	readonly #rocmSmiRepository: RocmSmiRepository;
 	readonly #configRepository: ConfigRepository;
 	readonly #ggufCache = new Map<string, GgufParserJson>();
+	// VRAM of one more slot of each model, from the estimate for two contexts
+	readonly #perSlotCache = new Map<string, number>();
+	// VRAM of the models being started, not yet reported by rocm-smi
+	readonly #reservations = new Map<string, number>();
+	// File of the models whose metadata could not be read, which is not read
+	// again until the file changes or the model is loaded
+	readonly #metadataFailures = new Map<string, string>();
 
 	constructor(
 		ggufParserRepository: GgufParserRepository,
@@ -39,21 +76,54 @@ export class ModelFitService {
 		this.#configRepository = configRepository;
 	}
 
-	public async willModelFit(
+	public async willModelFit(modelName: string): Promise<ModelFitResult> {
+		return this.#checkFit(modelName, false, false);
+	}
+
+	/**
+	 * Check whether a model fits and, if it does, reserve its estimated VRAM
+	 * until `releaseReservation` is called. Other fit checks subtract it from
+	 * the free VRAM, as it is only reported by rocm-smi once allocated.
+	 */
+	public async reserveIfFits(
+		modelName: string,
+		forceRefresh: boolean = false,
+	): Promise<ModelFitResult> {
+		return this.#checkFit(modelName, true, forceRefresh);
+	}
+
+	/**
+	 * Stop reserving the VRAM of a model, once it is loaded or failed to. A
+	 * cached poll may predate its allocation, so the next check polls again.
+	 */
+	public releaseReservation(modelName: string): void {
+		this.#reservations.delete(modelName);
+		this.#rocmSmiRepository.invalidate(this.getDeviceIndex(modelName));
+	}
+
+	async #checkFit(
 		modelName: string,
-		deviceIndex: number = 0,
+		reserve: boolean,
+		forceRefresh: boolean,
 	): Promise<ModelFitResult> {
 		const ggufJson = await this.#getOrCacheGgufJson(modelName);
 		const requiredVramBytes = this.#extractRequiredVram(ggufJson);
-		const freeVramBytes = await this.#getFreeVram(deviceIndex);
+		const deviceIndex = this.getDeviceIndex(modelName);
+		const vramInfo = await this.#getVramInfo(deviceIndex, forceRefresh);
+		const polledFreeVramBytes = vramInfo.totalBytes - vramInfo.usedBytes;
+		const details = `GPU ${vramInfo.card}: ${polledFreeVramBytes.toLocaleString()} B total`;
 
+		// No await from here, so that the check and the reservation are atomic
+		const freeVramBytes =
+			polledFreeVramBytes - this.#getReservedVram(modelName);
 		const fits = requiredVramBytes <= freeVramBytes;
+		if (fits && reserve) {
+			this.#reservations.set(modelName, requiredVramBytes);
+		}
 		const message = fits
 			? "✅ Model fits in the available VRAM."
 			: "❌ Model does NOT fit in the available VRAM.";
 
-		const details = await this.#buildDetails(deviceIndex, freeVramBytes);
-
 		return {
 			fits,
 			requiredVramBytes,
@@ -63,6 +133,150 @@ export class ModelFitService {
 		};
 	}
 
+	/**
+	 * Estimate how many parallel slots of the configured context size fit in
+	 * the currently free VRAM. The cost of one slot is derived from the
+	 * difference between estimates for one and two contexts.
+	 */
+	public async estimateParallelSlots(modelName: string): Promise<number> {
+		const singleSlotBytes = await this.estimateRequiredVram(modelName);
+		const perSlotBytes = await this.#estimatePerSlotVram(modelName);
+		const vramInfo = await this.#getVramInfo(this.getDeviceIndex(modelName));
+		const freeVramBytes =
+			vramInfo.totalBytes -
+			vramInfo.usedBytes -
+			this.#getReservedVram(modelName);
+
+		return computeParallelSlots(
+			singleSlotBytes - perSlotBytes,
+			perSlotBytes,
+			freeVramBytes,
+		);
+	}
+
+	/**
+	 * Reserve the VRAM of a model started with several slots instead of the
+	 * estimate for one slot reserved by `reserveIfFits`, until
+	 * `releaseReservation` is called
+	 */
+	public async reserveParallelSlots(
+		modelName: string,
+		parallel: number,
+	): Promise<void> {
+		const singleSlotBytes = await this.estimateRequiredVram(modelName);
+		const perSlotBytes = await this.#estimatePerSlotVram(modelName);
+		this.#reservations.set(
+			modelName,
+			singleSlotBytes + (parallel - 1) * Math.max(perSlotBytes, 0),
+		);
+	}
+
+	async #estimatePerSlotVram(modelName: string): Promise<number> {
+		const cached = this.#perSlotCache.get(modelName);
+		if (cached !== undefined) {
+			return cached;
+		}
+
+		const modelConfig = this.#getModelConfigurationOrThrow(modelName);
+		const singleSlotBytes = await this.estimateRequiredVram(modelName);
+		const contextSize = await this.resolveContextSize(modelName);
+		const doubleSlotBytes = this.#extractRequiredVram(
+			await this.#ggufParserRepository.getMemoryEstimate({
+				...this.#buildEstimateParameters(modelConfig),
+				contextSize: contextSize * 2,
+			}),
+		);
+		const perSlotBytes = doubleSlotBytes - singleSlotBytes;
+		this.#perSlotCache.set(modelName, perSlotBytes);
+		return perSlotBytes;
+	}
+
+	/**
+	 * Context size the model runs with. A configured size of 0 means the
+	 * context length the model was trained with, read from the GGUF file.
+	 */
+	public async resolveContextSize(modelName: string): Promise<number> {
+		const modelConfig = this.#getModelConfigurationOrThrow(modelName);
+		if (modelConfig.common.contextSize > 0) {
+			return modelConfig.common.contextSize;
+		}
+
+		const ggufJson = await this.#getOrCacheGgufJson(modelName);
+		if (!ggufJson.estimate.contextSize) {
+			console.warn(
+				`No context length found in ${modelName} metadata, falling back to ${DEFAULT_CONTEXT_SIZE}`,
+			);
+			return DEFAULT_CONTEXT_SIZE;
+		}
+		return ggufJson.estimate.contextSize;
+	}
+
+	/**
+	 * Find the models needing more VRAM than the device has in total, which can
+	 * never be loaded even with every other model unloaded. Models that cannot
+	 * be estimated are skipped.
+	 */
+	public async getOversizedModels(): Promise<OversizedModel[]> {
+		const vramInfos = new Map<number | undefined, RocmSmiVramInfo>();
+
+		const oversizedModels: OversizedModel[] = [];
+		for (const modelName of this.#configRepository.getAvailableModelNames()) {
+			let requiredVramBytes: number;
+			try {
+				requiredVramBytes = await this.estimateRequiredVram(modelName);
+			} catch {
+				continue;
+			}
+			const deviceIndex = this.getDeviceIndex(modelName);
+			let vramInfo = vramInfos.get(deviceIndex);
+			if (!vramInfo) {
+				vramInfo = await this.#getVramInfo(deviceIndex);
+				vramInfos.set(deviceIndex, vramInfo);
+			}
+			if (requiredVramBytes > vramInfo.totalBytes) {
+				oversizedModels.push({
+					modelName,
+					requiredVramBytes,
+					totalVramBytes: vramInfo.totalBytes,
+				});
+			}
+		}
+		return oversizedModels;
+	}
+
+	public async estimateRequiredVram(modelName: string): Promise<number> {
+		const ggufJson = await this.#getOrCacheGgufJson(modelName);
+		return this.#extractRequiredVram(ggufJson);
+	}
+
+	/**
+	 * Read the GGUF metadata of a model, or null if the file cannot be parsed
+	 */
+	public async getModelMetadata(
+		modelName: string,
+	): Promise<MetadataPayload | null> {
+		const modelFilePath =
+			this.#configRepository.getModelConfiguration(modelName)?.modelFilePath;
+		if (
+			modelFilePath !== undefined &&
+			this.#metadataFailures.get(modelName) === modelFilePath
+		) {
+			return null;
+		}
+		try {
+			const ggufJson = await this.#getOrCacheGgufJson(modelName);
+			return ggufJson.metadata ?? null;
+		} catch (error) {
+			console.error(
+				`Failed to read metadata of ${modelName}, skipped until its file changes: ${(error as Error).message}`,
+			);
+			if (modelFilePath !== undefined) {
+				this.#metadataFailures.set(modelName, modelFilePath);
+			}
+			return null;
+		}
+	}
+
 	#getModelConfigurationOrThrow(modelName: string) {
 		const modelConfig = this.#configRepository.getModelConfiguration(modelName);
 		if (!modelConfig) {
@@ -79,11 +293,16 @@ export class ModelFitService {
 		return {
 			modelFilePath: modelConfig.modelFilePath,
 			mmprojFilePath: modelConfig.multimodalProjectorFilePath,
-			contextSize: modelConfig.common.contextSize,
+			// Leaving the context size unset makes gguf-parser use the trained one
+			contextSize: modelConfig.common.contextSize || undefined,
+			// With mmap, weights are paged in on demand and the RAM estimate is an
+			// upper bound of what will actually be resident
 			noMmap: modelConfig.common.noMmap,
 			flashAttention: modelConfig.common.flashAttention,
 			cacheTypeK: modelConfig.common.cacheType,
 			cacheTypeV: modelConfig.common.cacheType,
+			batchSize: modelConfig.common.batchSize,
+			ubatchSize: modelConfig.common.ubatchSize,
 		};
 	}
 
@@ -104,6 +323,7 @@ export class ModelFitService {
 		}
 
 		this.#ggufCache.set(modelName, fresh);
+		this.#metadataFailures.delete(modelName);
 		return fresh;
 	}
 
@@ -120,31 +340,76 @@ export class ModelFitService {
 			);
 		}
 
-		return firstVramInfo.nonuma;
+		// The estimate already covers the weights, KV cache and compute buffers
+		const headroom = this.#configRepository.getVramHeadroom();
+		return Math.ceil(firstVramInfo.nonuma * (1 + headroom));
 	}
 
-	async #getFreeVram(deviceIndex: number): Promise<number> {
-		const rocmOpts: RocmSmiQueryOptions = { device: deviceIndex };
-		const vramInfos: RocmSmiVramInfo[] =
-			await this.#rocmSmiRepository.getVramInfo(rocmOpts);
+	/**
+	 * GPU a model runs on and is measured against, the configured one unless
+	 * the model is pinned to another. Undefined when it is measured against all
+	 * of them.
+	 */
+	public getDeviceIndex(modelName: string): number | undefined {
+		return (
+			this.#configRepository.getModelConfiguration(modelName)?.gpuDevice ??
+			this.#configRepository.getGpuDevice()
+		);
+	}
 
-		if (vramInfos.length === 0) {
-			throw new Error(
-				`rocm‑smi did not return any VRAM info for device ${deviceIndex}`,
-			);
-		}
+	/**
+	 * Whether two models can take VRAM from each other, being measured against
+	 * the same GPU or one of them against all of them
+	 */
+	public sharesDevice(modelName: string, otherModelName: string): boolean {
+		const deviceIndex = this.getDeviceIndex(modelName);
+		const otherDeviceIndex = this.getDeviceIndex(otherModelName);
+		return (
+			deviceIndex === undefined ||
+			otherDeviceIndex === undefined ||
+			deviceIndex === otherDeviceIndex
+		);
+	}
 
-		return vramInfos[0].totalBytes - vramInfos[0].usedBytes;
+	/**
+	 * Sum of the VRAM reserved by the other models being started
+	 */
+	#getReservedVram(excludedModelName: string): number {
+		let reservedBytes = 0;
+		for (const [modelName, bytes] of this.#reservations) {
+			if (
+				modelName !== excludedModelName &&
+				this.sharesDevice(modelName, excludedModelName)
+			) {
+				reservedBytes += bytes;
+			}
+		}
+		return reservedBytes;
 	}
 
-	async #buildDetails(
-		deviceIndex: number,
-		totalBytes: number,
-	): Promise<string> {
-		const rocmOpts: RocmSmiQueryOptions = { device: deviceIndex };
+	/**
+	 * VRAM of a GPU. rocm-smi is asked for that device only, but the card is
+	 * still picked by name in case it lists several of them. Without a device,
+	 * the VRAM of every card is summed.
+	 */
+	async #getVramInfo(
+		deviceIndex: number | undefined,
+		forceRefresh: boolean = false,
+	): Promise<RocmSmiVramInfo> {
+		const rocmOpts: RocmSmiQueryOptions = { device: deviceIndex, forceRefresh };
 		const vramInfos = await this.#rocmSmiRepository.getVramInfo(rocmOpts);
-		const gpuInfo = vramInfos[0];
+		if (deviceIndex === undefined && vramInfos.length > 0) {
+			return sumVramInfos(vramInfos);
+		}
 
-		return `GPU ${gpuInfo.card}: ${totalBytes.toLocaleString()} B total`;
+		const vramInfo =
+			vramInfos.find(({ card }) => card === `card${deviceIndex}`) ??
+			vramInfos[0];
+		if (!vramInfo) {
+			throw new Error(
+				`rocm‑smi did not return any VRAM info for device ${deviceIndex}`,
+			);
+		}
+		return vramInfo;
 	}
 }
@@ -1,12 +1,47 @@
-import type { ConfigRepository } from "#src/repositories/configRepository.ts";
+import { stat } from "node:fs/promises";
+import type {
+	ConfigRepository,
+	ModelCapabilities,
+} from "#src/repositories/configRepository.ts";
+import type { ModelFitService } from "#src/services/modelFitService.ts";
+
+const NO_CAPABILITIES: ModelCapabilities = {
+	vision: false,
+	tools: false,
+	jsonMode: false,
+};
+
+// Creation time of the models whose file cannot be read, in seconds
+const PROCESS_START_TIME = Math.floor(Date.now() / 1000);
 
 export class Model {
 	readonly #id: string;
 	readonly #owner: string;
+	readonly #displayName: string;
+	readonly #created: number;
+	readonly #capabilities: ModelCapabilities;
+	readonly #tags: string[];
+	readonly #architecture?: string;
+	readonly #quantization?: string;
 
-	constructor(id: string, owner: string) {
+	constructor(
+		id: string,
+		owner: string,
+		displayName: string,
+		created: number,
+		capabilities: ModelCapabilities,
+		tags: string[],
+		architecture?: string,
+		quantization?: string,
+	) {
 		this.#id = id;
 		this.#owner = owner;
+		this.#displayName = displayName;
+		this.#created = created;
+		this.#capabilities = capabilities;
+		this.#tags = tags;
+		this.#architecture = architecture;
+		this.#quantization = quantization;
 	}
 
 	get id(): string {
@@ -16,18 +51,80 @@ export class Model {
 	get owner(): string {
 		return this.#owner;
 	}
+
+	get displayName(): string {
+		return this.#displayName;
+	}
+
+	/**
+	 * Unix timestamp in seconds, stable across listings
+	 */
+	get created(): number {
+		return this.#created;
+	}
+
+	get capabilities(): ModelCapabilities {
+		return this.#capabilities;
+	}
+
+	get tags(): string[] {
+		return this.#tags;
+	}
+
+	get architecture(): string | undefined {
+		return this.#architecture;
+	}
+
+	get quantization(): string | undefined {
+		return this.#quantization;
+	}
 }
 
 export class ModelsService {
 	readonly #configRepository: ConfigRepository;
+	readonly #modelFitService: ModelFitService;
 
-	constructor(configRepository: ConfigRepository) {
+	constructor(
+		configRepository: ConfigRepository,
+		modelFitService: ModelFitService,
+	) {
 		this.#configRepository = configRepository;
+		this.#modelFitService = modelFitService;
+	}
+
+	async getModels(): Promise<Model[]> {
+		return Promise.all(
+			this.#configRepository.getAvailableModelNames().map(async (id) => {
+				const metadata = await this.#modelFitService.getModelMetadata(id);
+				const modelConfig = this.#configRepository.getModelConfiguration(id);
+				// Metadata is left out when the GGUF file cannot be read
+				return new Model(
+					id,
+					modelConfig?.owner ?? this.#configRepository.getModelOwnerName(),
+					metadata?.name || id,
+					await this.#getCreated(modelConfig?.modelFilePath),
+					modelConfig?.capabilities ?? NO_CAPABILITIES,
+					modelConfig?.tags ?? [],
+					metadata?.architecture,
+					metadata?.fileType,
+				);
+			}),
+		);
 	}
 
-	getModels(): Model[] {
-		return this.#configRepository
-			.getAvailableModelNames()
-			.map((id) => new Model(id, this.#configRepository.getModelOwnerName()));
+	/**
+	 * Modification time of the model file, or the start of the process when
+	 * the file cannot be read
+	 */
+	async #getCreated(modelFilePath: string | undefined): Promise<number> {
+		if (!modelFilePath) {
+			return PROCESS_START_TIME;
+		}
+		try {
+			const { mtimeMs } = await stat(modelFilePath);
+			return Math.floor(mtimeMs / 1000);
+		} catch {
+			return PROCESS_START_TIME;
+		}
 	}
 }
@@ -0,0 +1,179 @@
+import type { UsageRepository } from "#src/repositories/usageRepository.ts";
+
+/** Token usage reported by llama-server in its OpenAI compatible responses */
+export type TokenUsage = {
+	prompt_tokens: number;
+	completion_tokens: number;
+};
+
+/** Requests served and tokens processed by a model */
+export type ModelUsage = {
+	requests: number;
+	promptTokens: number;
+	completionTokens: number;
+};
+
+/**
+ * Read the usage of a JSON response or stream chunk. Most chunks of a stream
+ * do not mention it and are not parsed, malformed ones are ignored.
+ */
+function parseUsage(json: string): TokenUsage | undefined {
+	if (!json.includes('"usage"')) {
+		return undefined;
+	}
+	try {
+		const { usage } = JSON.parse(json);
+		if (typeof usage?.prompt_tokens !== "number") {
+			return undefined;
+		}
+		return {
+			prompt_tokens: usage.prompt_tokens,
+			completion_tokens: usage.completion_tokens ?? 0,
+		};
+	} catch {
+		return undefined;
+	}
+}
+
+// Characters kept from the end of a JSON response to find its usage in, which
+// llama-server writes last as it sorts the keys of its responses
+const JSON_TAIL_LENGTH = 4096;
+
+// The usage object of a JSON response, with its nested token details
+const USAGE_PATTERN = /"usage"\s*:\s*\{(?:[^{}]|\{[^{}]*\})*\}/g;
+
+/**
+ * Read the usage of a JSON response from its last characters, without
+ * keeping the whole response in memory
+ */
+function parseTrailingUsage(tail: string): TokenUsage | undefined {
+	const usage = tail.match(USAGE_PATTERN)?.at(-1);
+	return usage ? parseUsage(`{${usage}}`) : undefined;
+}
+
+export class UsageService {
+	readonly #usageRepository: UsageRepository | null;
+	readonly #saveIntervalSeconds: number;
+	readonly #usage = new Map<string, ModelUsage>();
+	#interval: NodeJS.Timeout | undefined;
+
+	/**
+	 * @param usageRepository Where the counters are kept, only in memory when
+	 * null
+	 * @param saveIntervalSeconds Time between two saves of the counters
+	 */
+	constructor(
+		usageRepository: UsageRepository | null = null,
+		saveIntervalSeconds: number = 60,
+	) {
+		this.#usageRepository = usageRepository;
+		this.#saveIntervalSeconds = saveIntervalSeconds;
+	}
+
+	public getUsage(modelName: string): ModelUsage | undefined {
+		return this.#usage.get(modelName);
+	}
+
+	public record(modelName: string, usage: TokenUsage): void {
+		this.#add(modelName, {
+			requests: 0,
+			promptTokens: usage.prompt_tokens,
+			completionTokens: usage.completion_tokens,
+		});
+	}
+
+	/**
+	 * Restore the counters saved by a previous run
+	 */
+	public async load(): Promise<void> {
+		if (!this.#usageRepository) {
+			return;
+		}
+		const saved = await this.#usageRepository.load();
+		for (const [modelName, usage] of Object.entries(saved)) {
+			this.#add(modelName, usage);
+		}
+	}
+
+	public async save(): Promise<void> {
+		await this.#usageRepository?.save(Object.fromEntries(this.#usage));
+	}
+
+	public start(): void {
+		if (!this.#usageRepository || this.#interval) {
+			return;
+		}
+
+		this.#interval = setInterval(() => {
+			this.save().catch((error) => {
+				console.error("Failed to save the usage counters:", error);
+			});
+		}, this.#saveIntervalSeconds * 1000);
+	}
+
+	public stop(): void {
+		clearInterval(this.#interval);
+		this.#interval = undefined;
+	}
+
+	/**
+	 * Pass a response body through unchanged while looking for its usage, at
+	 * the end of a JSON response or in the last stream chunk reporting it. The
+	 * usage is recorded once the body is fully read.
+	 */
+	public trackUsage(
+		modelName: string,
+		body: ReadableStream<Uint8Array<ArrayBuffer>>,
+		isEventStream: boolean,
+	): ReadableStream<Uint8Array<ArrayBuffer>> {
+		this.#add(modelName, { requests: 1, promptTokens: 0, completionTokens: 0 });
+		const decoder = new TextDecoder();
+		let buffer = "";
+		let usage: TokenUsage | undefined;
+		const readLine = (line: string) => {
+			if (line.startsWith("data:")) {
+				usage = parseUsage(line.slice("data:".length)) ?? usage;
+			}
+		};
+
+		return body.pipeThrough(
+			new TransformStream<Uint8Array<ArrayBuffer>, Uint8Array<ArrayBuffer>>({
+				transform: (chunk, controller) => {
+					controller.enqueue(chunk);
+					buffer += decoder.decode(chunk, { stream: true });
+					if (isEventStream) {
+						const lines = buffer.split("\n");
+						// Keep the last line until it is complete
+						buffer = lines.pop() ?? "";
+						for (const line of lines) {
+							readLine(line);
+						}
+					} else {
+						buffer = buffer.slice(-JSON_TAIL_LENGTH);
+					}
+				},
+				flush: () => {
+					buffer += decoder.decode();
+					if (isEventStream) {
+						readLine(buffer);
+					} else {
+						usage = parseTrailingUsage(buffer);
+					}
+					if (usage) {
+						this.record(modelName, usage);
+					}
+				},
+			}),
+		);
+	}
+
+	#add(modelName: string, usage: ModelUsage): void {
+		const current = this.#usage.get(modelName);
+		this.#usage.set(modelName, {
+			requests: (current?.requests ?? 0) + usage.requests,
+			promptTokens: (current?.promptTokens ?? 0) + usage.promptTokens,
+			completionTokens:
+				(current?.completionTokens ?? 0) + usage.completionTokens,
+		});
+	}
+}
@@ -0,0 +1,81 @@
+import type { ConfigRepository } from "#src/repositories/configRepository.ts";
+import {
+	type RocmSmiRepository,
+	sumVramInfos,
+} from "#src/repositories/rocmSmiRepository.ts";
+import type { LlamaProxyService } from "#src/services/llamaProxyService.ts";
+import type { ModelFitService } from "#src/services/modelFitService.ts";
+
+/**
+ * Periodically log the VRAM state and the loaded models, giving a timeline
+ * to debug out of memory errors without a metrics stack.
+ */
+export class VramLoggerService {
+	readonly #configRepository: ConfigRepository;
+	readonly #rocmSmiRepository: RocmSmiRepository;
+	readonly #llamaProxyService: LlamaProxyService;
+	readonly #modelFitService: ModelFitService;
+	#interval: NodeJS.Timeout | undefined;
+
+	constructor(
+		configRepository: ConfigRepository,
+		rocmSmiRepository: RocmSmiRepository,
+		llamaProxyService: LlamaProxyService,
+		modelFitService: ModelFitService,
+	) {
+		this.#configRepository = configRepository;
+		this.#rocmSmiRepository = rocmSmiRepository;
+		this.#llamaProxyService = llamaProxyService;
+		this.#modelFitService = modelFitService;
+	}
+
+	public start(): void {
+		const { interval } = this.#configRepository.getVramLogConfiguration();
+		if (interval <= 0 || this.#interval) {
+			return;
+		}
+
+		this.#interval = setInterval(() => {
+			this.logVramState().catch((error) => {
+				console.error("Failed to log VRAM state:", error);
+			});
+		}, interval * 1000);
+	}
+
+	public stop(): void {
+		clearInterval(this.#interval);
+		this.#interval = undefined;
+	}
+
+	public async logVramState(
+		deviceIndex: number | undefined = this.#configRepository.getGpuDevice(),
+	): Promise<void> {
+		const { level } = this.#configRepository.getVramLogConfiguration();
+		const vramInfos = await this.#rocmSmiRepository.getVramInfo({
+			device: deviceIndex,
+		});
+		// Every card is summed without a configured GPU, as in the fit checks
+		const vramInfo =
+			deviceIndex === undefined && vramInfos.length > 0
+				? sumVramInfos(vramInfos)
+				: vramInfos[0];
+		if (!vramInfo) {
+			throw new Error(
+				`rocm‑smi did not return any VRAM info for device ${deviceIndex}`,
+			);
+		}
+
+		const models = await Promise.all(
+			this.#llamaProxyService.getLoadedModels().map(async (modelName) => {
+				const requiredVramBytes =
+					await this.#modelFitService.estimateRequiredVram(modelName);
+				return `${modelName} (~${requiredVramBytes.toLocaleString()} B)`;
+			}),
+		);
+
+		const freeBytes = vramInfo.totalBytes - vramInfo.usedBytes;
+		console[level](
+			`VRAM ${vramInfo.card}: ${vramInfo.totalBytes.toLocaleString()} B total, ${vramInfo.usedBytes.toLocaleString()} B used, ${freeBytes.toLocaleString()} B free; loaded models: ${models.join(", ") || "none"}`,
+		);
+	}
+}
@@ -0,0 +1,82 @@
+import assert from "node:assert";
+import { mkdtemp, writeFile } from "node:fs/promises";
+import { tmpdir } from "node:os";
+import path from "node:path";
+import { test } from "node:test";
+import type { HttpBindings } from "@hono/node-server";
+import { createApplication, createDryRunApplication } from "#src/lib.ts";
+import { ConfigRepository } from "#src/repositories/configRepository.ts";
+import { DRY_RUN_CONTENT } from "#src/repositories/dryRunLlamaServerRepository.ts";
+import { DryRunLlamaServerRepository } from "#src/repositories/dryRunLlamaServerRepository.ts";
+
+async function createConfigRepository() {
+	const directory = await mkdtemp(path.join(tmpdir(), "config-"));
+	const configPath = path.join(directory, "config.json");
+	const config = {
+		owner: "bob",
+		strictModelFiles: false,
+		system: {
+			llamaServer: "/bin/false",
+			ggufParser: "/bin/false",
+			rocmSmi: "/bin/false",
+		},
+		server: {},
+		models: {
+			"model-a": {
+				modelFilePath: "/models/model-a.gguf",
+				common: {},
+				sampling: {},
+				network: { port: 18_082 },
+			},
+		},
+	};
+	await writeFile(configPath, JSON.stringify(config), { encoding: "utf8" });
+	return ConfigRepository.createFromFile(configPath);
+}
+
+test("createApplication", async (t) => {
+	await t.test("should serve the configured models", async () => {
+		// Arrange
+		const configRepository = await createConfigRepository();
+		const { router } = createApplication(
+			configRepository,
+			new DryRunLlamaServerRepository(),
+		);
+
+		// Act
+		const response = await router.getApp().request("/v1/models");
+		const body = await response.json();
+
+		// Assert
+		assert.strictEqual(response.status, 200);
+		assert.deepStrictEqual(
+			body.data.map((model: { id: string }) => model.id),
+			["model-a"],
+		);
+	});
+});
+
+test("createDryRunApplication", async (t) => {
+	await t.test("should answer without any model file or GPU", async () => {
+		// Arrange
+		const configRepository = await createConfigRepository();
+		const { router, llamaProxyService } =
+			createDryRunApplication(configRepository);
+		const app = router.getApp();
+		const request = { model: "model-a", stream: false, messages: [] };
+		const bindings = { outgoing: { on: () => {} } } as unknown as HttpBindings;
+
+		// Act
+		const response = await app.request(
+			"/v1/chat/completions",
+			{ method: "POST", body: JSON.stringify(request) },
+			bindings,
+		);
+		const body = await response.json();
+		await llamaProxyService.unloadAll();
+
+		// Assert
+		assert.strictEqual(response.status, 200);
+		assert.strictEqual(body.choices[0].message.content, DRY_RUN_CONTENT);
+	});
+});
@@ -0,0 +1,99 @@
+import assert from "node:assert";
+import { chmod, mkdtemp, writeFile } from "node:fs/promises";
+import { tmpdir } from "node:os";
+import path from "node:path";
+import { test } from "node:test";
+import { runEstimateCommand } from "#src/cli/estimateCommand.ts";
+
+function givenMemoryInfo(nonuma: number) {
+	return {
+		handleLayers: 0,
+		handleLastLayer: 0,
+		handleOutputLayer: false,
+		remote: false,
+		position: 0,
+		uma: 0,
+		nonuma,
+	};
+}
+
+/**
+ * Write a gguf-parser that prints an estimate spread over two GPUs
+ */
+async function createFakeGgufParser() {
+	const estimate = {
+		estimate: {
+			type: "model",
+			architecture: "llama",
+			contextSize: 8192,
+			flashAttention: false,
+			noMMap: false,
+			embeddingOnly: false,
+			reranking: false,
+			distributable: true,
+			logicalBatchSize: 2048,
+			physicalBatchSize: 512,
+			items: [
+				{
+					offloadLayers: 33,
+					fullOffloaded: true,
+					ram: givenMemoryInfo(200),
+					vrams: [givenMemoryInfo(300), givenMemoryInfo(400)],
+				},
+			],
+		},
+	};
+	const directory = await mkdtemp(path.join(tmpdir(), "gguf-parser-"));
+	const binaryPath = path.join(directory, "gguf-parser");
+	await writeFile(
+		binaryPath,
+		`#!/bin/sh\ncat <<'EOF'\n${JSON.stringify(estimate)}\nEOF\n`,
+		{ encoding: "utf8" },
+	);
+	await chmod(binaryPath, 0o755);
+	return binaryPath;
+}
+
+test("runEstimateCommand", async (t) => {
+	await t.test("should print the memory needed by the model", async () => {
+		// Arrange
+		const binaryPath = await createFakeGgufParser();
+		const log = t.mock.method(console, "log", () => {});
+
+		// Act
+		await runEstimateCommand([
+			"/models/model.gguf",
+			"--context",
+			"8k",
+			"--kv-quant",
+			"q8_0",
+			"--gguf-parser",
+			binaryPath,
+		]);
+
+		// Assert
+		assert.strictEqual(log.mock.callCount(), 2);
+		assert.strictEqual(
+			log.mock.calls[0].arguments[0],
+			"/models/model.gguf (ctx 8192, kv q8_0): 700 B VRAM + 200 B RAM",
+		);
+		const details = JSON.parse(log.mock.calls[1].arguments[0]);
+		assert.strictEqual(details.architecture, "llama");
+		assert.strictEqual(details.offloadLayers, 33);
+		assert.strictEqual(details.vrams.length, 2);
+	});
+
+	await t.test("should reject an unknown cache type", async () => {
+		// Act
+		const estimate = runEstimateCommand([
+			"/models/model.gguf",
+			"--kv-quant",
+			"q3_0",
+			"--gguf-parser",
+			"/bin/false",
+		]);
+
+		// Assert
+		await assert.rejects(estimate, /Invalid --kv-quant value "q3_0"/);
+	});
+});
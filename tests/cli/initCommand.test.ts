@@ -0,0 +1,62 @@
+import assert from "node:assert";
+import { mkdtemp, readFile, writeFile } from "node:fs/promises";
+import { tmpdir } from "node:os";
+import path from "node:path";
+import { test } from "node:test";
+import { runInitCommand } from "#src/cli/initCommand.ts";
+import { ConfigRepository } from "#src/repositories/configRepository.ts";
+
+async function givenConfigPath() {
+	const directory = await mkdtemp(path.join(tmpdir(), "init-"));
+	return path.join(directory, "config.json");
+}
+
+test("runInitCommand", async (t) => {
+	t.beforeEach(() => {
+		t.mock.method(console, "log", () => {});
+		t.mock.method(console, "warn", () => {});
+	});
+
+	await t.test("should write a valid configuration", async () => {
+		// Arrange
+		const configPath = await givenConfigPath();
+
+		// Act
+		await runInitCommand([configPath]);
+
+		// Assert
+		// The template points to model files that do not exist here
+		const template = JSON.parse(await readFile(configPath, "utf8"));
+		await writeFile(
+			configPath,
+			JSON.stringify({ ...template, strictModelFiles: false }),
+		);
+		const repository = await ConfigRepository.createFromFile(configPath);
+		assert.ok(repository.getAvailableModelNames().length > 0);
+	});
+
+	await t.test("should not overwrite an existing file", async () => {
+		// Arrange
+		const configPath = await givenConfigPath();
+		await writeFile(configPath, "{}");
+
+		// Act
+		const init = runInitCommand([configPath]);
+
+		// Assert
+		await assert.rejects(init, /--force/);
+		assert.strictEqual(await readFile(configPath, "utf8"), "{}");
+	});
+
+	await t.test("should overwrite an existing file with --force", async () => {
+		// Arrange
+		const configPath = await givenConfigPath();
+		await writeFile(configPath, "{}");
+
+		// Act
+		await runInitCommand([configPath, "--force"]);
+
+		// Assert
+		assert.notStrictEqual(await readFile(configPath, "utf8"), "{}");
+	});
+});
@@ -0,0 +1,50 @@
+import assert from "node:assert";
+import { spawn } from "node:child_process";
+import { once } from "node:events";
+import { text } from "node:stream/consumers";
+import { test } from "node:test";
+import { fileURLToPath } from "node:url";
+
+const INDEX_PATH = fileURLToPath(new URL("../src/index.ts", import.meta.url));
+
+/**
+ * Run the router with the given arguments until it exits
+ */
+async function runRouter(args: string[]) {
+	const child = spawn(process.execPath, [INDEX_PATH, ...args]);
+	const [stdout, stderr, [code]] = await Promise.all([
+		text(child.stdout),
+		text(child.stderr),
+		once(child, "close"),
+	]);
+	return { code, stdout, stderr };
+}
+
+test("--log-format", async (t) => {
+	await t.test("should log one JSON object per line", async () => {
+		// Act
+		const { code, stderr } = await runRouter([
+			"--log-format",
+			"json",
+			"/missing/config.json",
+		]);
+
+		// Assert
+		assert.strictEqual(code, 1);
+		const lines = stderr.trimEnd().split("\n");
+		assert.strictEqual(lines.length, 1);
+		const log = JSON.parse(lines[0]);
+		assert.strictEqual(log.level, "error");
+		assert.ok(!Number.isNaN(Date.parse(log.time)));
+		assert.match(log.message, /\/missing\/config\.json/);
+	});
+
+	await t.test("should reject an unknown format", async () => {
+		// Act
+		const { code, stderr } = await runRouter(["--log-format", "xml"]);
+
+		// Assert
+		assert.strictEqual(code, 1);
+		assert.match(stderr, /Invalid log format "xml"/);
+	});
+});
@@ -0,0 +1,708 @@
+import assert from "node:assert";
+import { mkdir, mkdtemp, readFile, writeFile } from "node:fs/promises";
+import { tmpdir } from "node:os";
+import path from "node:path";
+import { test } from "node:test";
+import {
+	CONFIG_VERSION,
+	ConfigError,
+	ConfigRepository,
+	createModelConfiguration,
+} from "#src/repositories/configRepository.ts";
+
+function givenConfig(model: Record<string, unknown> = {}) {
+	return {
+		owner: "bob",
+		// The model files are checked by the "model files" tests only
+		strictModelFiles: false,
+		system: {
+			llamaServer: "/usr/bin/llama-server",
+			ggufParser: "/usr/bin/gguf-parser",
+			rocmSmi: "/usr/bin/rocm-smi",
+		},
+		server: {},
+		models: {
+			"model-a": {
+				modelFilePath: "/models/model-a.gguf",
+				common: {},
+				sampling: {},
+				network: { port: 8081 },
+				...model,
+			},
+		},
+	};
+}
+
+async function createConfigFile(config: unknown) {
+	const directory = await mkdtemp(path.join(tmpdir(), "config-"));
+	const configPath = path.join(directory, "config.json");
+	await writeFile(configPath, JSON.stringify(config), { encoding: "utf8" });
+	return configPath;
+}
+
+async function createRepository(config: unknown) {
+	return ConfigRepository.createFromFile(await createConfigFile(config));
+}
+
+test("penaltyProfile", async (t) => {
+	const cases = [
+		{
+			profile: "none",
+			expected: {
+				repeatPenalty: 1.0,
+				presencePenalty: 0,
+				frequencyPenalty: 0,
+			},
+		},
+		{
+			profile: "light",
+			expected: {
+				repeatPenalty: 1.05,
+				presencePenalty: 0,
+				frequencyPenalty: 0,
+			},
+		},
+		{
+			profile: "aggressive",
+			expected: {
+				repeatPenalty: 1.1,
+				presencePenalty: 0.3,
+				frequencyPenalty: 0,
+			},
+		},
+	];
+
+	for (const { profile, expected } of cases) {
+		await t.test(`should resolve the ${profile} preset`, async () => {
+			// Arrange
+			const config = givenConfig({ sampling: { penaltyProfile: profile } });
+
+			// Act
+			const repository = await createRepository(config);
+
+			// Assert
+			const sampling = repository.getModelConfiguration("model-a")?.sampling;
+			assert.partialDeepStrictEqual(sampling, expected);
+		});
+	}
+
+	await t.test(
+		"should let explicit penalties override the preset",
+		async () => {
+			// Arrange
+			const config = givenConfig({
+				sampling: { penaltyProfile: "aggressive", presencePenalty: 0 },
+			});
+
+			// Act
+			const repository = await createRepository(config);
+
+			// Assert
+			const sampling = repository.getModelConfiguration("model-a")?.sampling;
+			assert.partialDeepStrictEqual(sampling, {
+				repeatPenalty: 1.1,
+				presencePenalty: 0,
+			});
+		},
+	);
+});
+
+test("models order", async (t) => {
+	await t.test(
+		"should keep the declaration order on load and save",
+		async () => {
+			// Arrange
+			const { models, ...config } = givenConfig();
+			const modelA = models["model-a"];
+			const givenModels = {
+				"model-c": { ...modelA, network: { port: 8083 } },
+				"model-a": modelA,
+				"model-b": { ...modelA, network: { port: 8082 } },
+			};
+			const configPath = await createConfigFile({
+				...config,
+				models: givenModels,
+			});
+			const repository = await ConfigRepository.createFromFile(configPath);
+
+			// Act
+			await repository.reloadFromJson(repository.getConfig());
+
+			// Assert
+			const expectedNames = ["model-c", "model-a", "model-b"];
+			assert.deepStrictEqual(
+				repository.getAvailableModelNames(),
+				expectedNames,
+			);
+			const saved = JSON.parse(await readFile(configPath, "utf8"));
+			assert.deepStrictEqual(Object.keys(saved.models), expectedNames);
+		},
+	);
+});
+
+test("contextSize", async (t) => {
+	await t.test("should parse the M suffix", async () => {
+		// Arrange
+		const config = givenConfig({ common: { contextSize: "2M" } });
+
+		// Act
+		const repository = await createRepository(config);
+
+		// Assert
+		const common = repository.getModelConfiguration("model-a")?.common;
+		assert.strictEqual(common?.contextSize, 2 * 1024 * 1024);
+	});
+
+	for (const [contextSize, expected] of [
+		["32_768", 32_768],
+		["1_024k", 1_024 * 1024],
+	] as const) {
+		await t.test(`should accept digit separators in ${contextSize}`, async () => {
+			// Arrange
+			const config = givenConfig({ common: { contextSize } });
+
+			// Act
+			const repository = await createRepository(config);
+
+			// Assert
+			const common = repository.getModelConfiguration("model-a")?.common;
+			assert.strictEqual(common?.contextSize, expected);
+		});
+	}
+
+	await t.test("should reject an invalid context size", async () => {
+		// Arrange
+		const config = givenConfig({ common: { contextSize: "3_2x" } });
+
+		// Act
+		const repository = createRepository(config);
+
+		// Assert
+		await assert.rejects(repository, /contextSize/);
+	});
+
+	for (const contextSize of ["k", "M", "_k"]) {
+		await t.test(`should reject the bare suffix ${contextSize}`, async () => {
+			// Arrange
+			const config = givenConfig({ common: { contextSize } });
+
+			// Act
+			const repository = createRepository(config);
+
+			// Assert
+			await assert.rejects(repository, /contextSize/);
+		});
+	}
+
+	await t.test("should save multiples of M with the suffix", async () => {
+		// Arrange
+		const configPath = await createConfigFile(
+			givenConfig({ common: { contextSize: "2M" } }),
+		);
+		const repository = await ConfigRepository.createFromFile(configPath);
+
+		// Act
+		await repository.reloadFromJson(repository.getConfig());
+
+		// Assert
+		const saved = JSON.parse(await readFile(configPath, "utf8"));
+		assert.strictEqual(saved.models["model-a"].common.contextSize, "2M");
+		const reloaded = await ConfigRepository.createFromFile(configPath);
+		assert.strictEqual(
+			reloaded.getModelConfiguration("model-a")?.common.contextSize,
+			2 * 1024 * 1024,
+		);
+	});
+});
+
+test("ropeScaling", async (t) => {
+	await t.test("should accept a scaling type known by llama.cpp", async () => {
+		// Arrange
+		const config = givenConfig({ common: { ropeScaling: "yarn" } });
+
+		// Act
+		const repository = await createRepository(config);
+
+		// Assert
+		const common = repository.getModelConfiguration("model-a")?.common;
+		assert.strictEqual(common?.ropeScaling, "yarn");
+	});
+
+	await t.test("should reject an unknown scaling type", async () => {
+		// Arrange
+		const config = givenConfig({ common: { ropeScaling: "ntk" } });
+
+		// Act
+		const repository = createRepository(config);
+
+		// Assert
+		await assert.rejects(repository, /ropeScaling/);
+	});
+});
+
+test("task", async (t) => {
+	await t.test("should default to chat", async () => {
+		// Act
+		const repository = await createRepository(givenConfig());
+
+		// Assert
+		const model = repository.getModelConfiguration("model-a");
+		assert.strictEqual(model?.task, "chat");
+	});
+
+	await t.test("should read the deprecated embeddings flag", async () => {
+		// Arrange
+		const config = givenConfig({ embeddings: true, pooling: "mean" });
+
+		// Act
+		const repository = await createRepository(config);
+
+		// Assert
+		const model = repository.getModelConfiguration("model-a");
+		assert.strictEqual(model?.task, "embedding");
+	});
+
+	await t.test("should require a pooling for embedding models", async () => {
+		// Arrange
+		const config = givenConfig({ task: "embedding" });
+
+		// Act
+		const repository = createRepository(config);
+
+		// Assert
+		await assert.rejects(repository, /pooling/);
+	});
+});
+
+test("model files", async (t) => {
+	await t.test("should accept a model file in a subdirectory", async () => {
+		// Arrange
+		const directory = await mkdtemp(path.join(tmpdir(), "models-"));
+		const modelFilePath = path.join(directory, "qwen", "qwen2-7b-Q4.gguf");
+		await mkdir(path.dirname(modelFilePath));
+		await writeFile(modelFilePath, "");
+		const warn = t.mock.method(console, "warn", () => {});
+
+		// Act
+		const repository = await createRepository(givenConfig({ modelFilePath }));
+
+		// Assert
+		const model = repository.getModelConfiguration("model-a");
+		assert.strictEqual(model?.modelFilePath, modelFilePath);
+		assert.strictEqual(warn.mock.callCount(), 0);
+	});
+
+	await t.test("should reject a missing model file", async () => {
+		// Arrange
+		const { strictModelFiles: _strict, ...config } = givenConfig();
+
+		// Act
+		const repository = createRepository(config);
+
+		// Assert
+		await assert.rejects(repository, (error: ConfigError) => {
+			assert.ok(error instanceof ConfigError);
+			assert.match(error.message, /"model-a"/);
+			assert.match(error.message, /"\/models\/model-a\.gguf"/);
+			return true;
+		});
+	});
+
+	await t.test("should only warn when not strict", async () => {
+		// Arrange
+		const warn = t.mock.method(console, "warn", () => {});
+
+		// Act
+		await createRepository(givenConfig());
+
+		// Assert
+		assert.strictEqual(warn.mock.callCount(), 1);
+		assert.match(warn.mock.calls[0].arguments[0], /model-a\.gguf/);
+	});
+});
+
+test("createModelConfiguration", async (t) => {
+	await t.test("should fill in the defaults of a configuration file", () => {
+		// Act
+		const model = createModelConfiguration({
+			modelFilePath: "/models/model.gguf",
+			network: { port: 8081 },
+			common: { contextSize: "32k" },
+		});
+
+		// Assert
+		assert.strictEqual(model.task, "chat");
+		assert.strictEqual(model.network.host, "127.0.0.1");
+		assert.strictEqual(model.common.contextSize, 32 * 1024);
+		assert.strictEqual(model.common.cacheType, "q8_0");
+		assert.strictEqual(model.sampling.temperature, 0.8);
+	});
+
+	await t.test("should validate the options", () => {
+		// Act
+		const create = () =>
+			createModelConfiguration({
+				modelFilePath: "/models/model.gguf",
+				network: { port: 8081 },
+				task: "embedding",
+			});
+
+		// Assert
+		assert.throws(create, /Pooling should be one of/);
+	});
+});
+
+test("version", async (t) => {
+	await t.test("should migrate and save an unversioned file", async () => {
+		// Arrange
+		const configPath = await createConfigFile(
+			givenConfig({ embeddings: true, pooling: "mean" }),
+		);
+		const warn = t.mock.method(console, "warn", () => {});
+		const repository = await ConfigRepository.createFromFile(configPath);
+
+		// Act
+		await repository.reloadFromJson(repository.getConfig());
+
+		// Assert
+		assert.ok(
+			warn.mock.calls.some(({ arguments: [message] }) =>
+				/embeddings became task "embedding"/.test(message),
+			),
+		);
+		const saved = JSON.parse(await readFile(configPath, "utf8"));
+		assert.strictEqual(saved.version, CONFIG_VERSION);
+		assert.strictEqual(saved.models["model-a"].task, "embedding");
+		assert.strictEqual(saved.models["model-a"].embeddings, undefined);
+	});
+
+	await t.test("should reject a file from a newer version", async () => {
+		// Arrange
+		const config = { ...givenConfig(), version: CONFIG_VERSION + 1 };
+
+		// Act
+		const repository = createRepository(config);
+
+		// Assert
+		await assert.rejects(repository, /version/);
+	});
+});
+
+test("include", async (t) => {
+	async function givenIncludedFiles() {
+		const { models, ...config } = givenConfig();
+		const modelA = models["model-a"];
+		const configPath = await createConfigFile({
+			...config,
+			include: ["models/b.json", "models/c.json"],
+			models: { "model-a": modelA },
+		});
+		const directory = path.join(path.dirname(configPath), "models");
+		await mkdir(directory);
+		await writeFile(
+			path.join(directory, "b.json"),
+			JSON.stringify({
+				models: {
+					"model-a": { ...modelA, unloadable: false },
+					"model-b": { ...modelA, network: { port: 8082 } },
+				},
+			}),
+		);
+		await writeFile(
+			path.join(directory, "c.json"),
+			JSON.stringify({
+				models: { "model-b": { ...modelA, network: { port: 8083 } } },
+			}),
+		);
+		return configPath;
+	}
+
+	await t.test("should merge the models of included files", async () => {
+		// Arrange
+		const configPath = await givenIncludedFiles();
+
+		// Act
+		const repository = await ConfigRepository.createFromFile(configPath);
+
+		// Assert
+		const modelA = repository.getModelConfiguration("model-a");
+		const modelB = repository.getModelConfiguration("model-b");
+		assert.strictEqual(modelA?.unloadable, true);
+		assert.strictEqual(modelB?.network.port, 8083);
+	});
+
+	await t.test("should not save unchanged included models", async () => {
+		// Arrange
+		const configPath = await givenIncludedFiles();
+		const repository = await ConfigRepository.createFromFile(configPath);
+
+		// Act
+		await repository.reloadFromJson(repository.getConfig());
+
+		// Assert
+		const saved = JSON.parse(await readFile(configPath, "utf8"));
+		assert.deepStrictEqual(Object.keys(saved.models), ["model-a"]);
+		assert.deepStrictEqual(saved.include, ["models/b.json", "models/c.json"]);
+	});
+
+	await t.test("should migrate an included file by its version", async () => {
+		// Arrange
+		const { models, ...config } = givenConfig();
+		const configPath = await createConfigFile({
+			...config,
+			version: CONFIG_VERSION,
+			include: ["b.json"],
+			models: {},
+		});
+		await writeFile(
+			path.join(path.dirname(configPath), "b.json"),
+			JSON.stringify({
+				models: {
+					"model-b": { ...models["model-a"], embeddings: true },
+				},
+			}),
+		);
+		t.mock.method(console, "warn", () => {});
+
+		// Act
+		const repository = await ConfigRepository.createFromFile(configPath);
+
+		// Assert
+		const modelB = repository.getModelConfiguration("model-b");
+		assert.strictEqual(modelB?.task, "embedding");
+	});
+
+	await t.test("should reject an include of a newer version", async () => {
+		// Arrange
+		const { models, ...config } = givenConfig();
+		const configPath = await createConfigFile({
+			...config,
+			include: ["b.json"],
+			models: {},
+		});
+		await writeFile(
+			path.join(path.dirname(configPath), "b.json"),
+			JSON.stringify({ version: CONFIG_VERSION + 1, models }),
+		);
+
+		// Act
+		const repository = ConfigRepository.createFromFile(configPath);
+
+		// Assert
+		await assert.rejects(repository, /newer version/);
+	});
+
+	await t.test("should reject an include cycle", async () => {
+		// Arrange
+		const configPath = await createConfigFile({
+			...givenConfig(),
+			include: ["config.json"],
+		});
+
+		// Act
+		const repository = ConfigRepository.createFromFile(configPath);
+
+		// Assert
+		await assert.rejects(repository, /include cycle/);
+	});
+});
+
+test("defaults", async (t) => {
+	function givenConfigWithDefaults() {
+		const { models, ...config } = givenConfig({
+			common: { cacheType: "f16" },
+		});
+		return {
+			...config,
+			defaults: { common: { contextSize: "32k", cacheType: "q4_0" } },
+			models: {
+				...models,
+				"model-b": {
+					...models["model-a"],
+					common: {},
+					network: { port: 8082 },
+				},
+			},
+		};
+	}
+
+	await t.test("should merge the defaults into each model", async () => {
+		// Arrange
+		const config = givenConfigWithDefaults();
+
+		// Act
+		const repository = await createRepository(config);
+
+		// Assert
+		const modelA = repository.getModelConfiguration("model-a");
+		const modelB = repository.getModelConfiguration("model-b");
+		assert.strictEqual(modelA?.common.contextSize, 32 * 1024);
+		assert.strictEqual(modelA?.common.cacheType, "f16");
+		assert.strictEqual(modelB?.common.cacheType, "q4_0");
+		assert.strictEqual(modelB?.common.threads, -1);
+	});
+
+	await t.test("should not save the inherited values", async () => {
+		// Arrange
+		const configPath = await createConfigFile(givenConfigWithDefaults());
+		const repository = await ConfigRepository.createFromFile(configPath);
+
+		// Act
+		await repository.reloadFromJson(repository.getConfig());
+
+		// Assert
+		const saved = JSON.parse(await readFile(configPath, "utf8"));
+		assert.deepStrictEqual(saved.models["model-a"].common, {
+			cacheType: "f16",
+		});
+		assert.deepStrictEqual(saved.models["model-b"].common, {});
+		assert.deepStrictEqual(saved.models["model-b"].network, {
+			host: "127.0.0.1",
+			port: 8082,
+		});
+	});
+});
+
+test("modelsAutodiscover", async (t) => {
+	async function givenModelsDirectory(config: Record<string, unknown>) {
+		const configPath = await createConfigFile({
+			...config,
+			modelsAutodiscover: true,
+		});
+		const directory = path.join(path.dirname(configPath), "models");
+		await mkdir(path.join(directory, "split"), { recursive: true });
+		const files = [
+			"model-a.gguf",
+			"Qwen2.5-7B.gguf",
+			"mmproj-Qwen2.5-7B.gguf",
+			"split/big-00001-of-00002.gguf",
+			"split/big-00002-of-00002.gguf",
+			"notes.txt",
+		];
+		for (const file of files) {
+			await writeFile(path.join(directory, file), "");
+		}
+		return configPath;
+	}
+
+	await t.test("should serve the GGUF files of the directory", async () => {
+		// Arrange
+		const configPath = await givenModelsDirectory(givenConfig());
+
+		// Act
+		const repository = await ConfigRepository.createFromFile(configPath);
+
+		// Assert
+		const directory = path.join(path.dirname(configPath), "models");
+		const big = repository.getModelConfiguration("big");
+		const qwen = repository.getModelConfiguration("Qwen2-5-7B");
+		assert.deepStrictEqual(repository.getAvailableModelNames().sort(), [
+			"Qwen2-5-7B",
+			"big",
+			"model-a",
+		]);
+		assert.strictEqual(
+			big?.modelFilePath,
+			path.join(directory, "split/big-00001-of-00002.gguf"),
+		);
+		assert.strictEqual(qwen?.network.port, 8100);
+		assert.strictEqual(big?.network.port, 8101);
+	});
+
+	await t.test("should prefer the models of the configuration", async () => {
+		// Arrange
+		const configPath = await givenModelsDirectory(
+			givenConfig({ network: { port: 8100 } }),
+		);
+
+		// Act
+		const repository = await ConfigRepository.createFromFile(configPath);
+
+		// Assert
+		const modelA = repository.getModelConfiguration("model-a");
+		const qwen = repository.getModelConfiguration("Qwen2-5-7B");
+		assert.strictEqual(modelA?.modelFilePath, "/models/model-a.gguf");
+		assert.strictEqual(qwen?.network.port, 8101);
+	});
+
+	await t.test("should not save the discovered models", async () => {
+		// Arrange
+		const configPath = await givenModelsDirectory(givenConfig());
+		const repository = await ConfigRepository.createFromFile(configPath);
+
+		// Act
+		await repository.reloadFromJson(repository.getConfig());
+
+		// Assert
+		const saved = JSON.parse(await readFile(configPath, "utf8"));
+		assert.deepStrictEqual(Object.keys(saved.models), ["model-a"]);
+	});
+});
+
+test("auth", async (t) => {
+	await t.test("should merge the keys of the file and variable", async () => {
+		// Arrange
+		const directory = await mkdtemp(path.join(tmpdir(), "keys-"));
+		const apiKeysFile = path.join(directory, "keys.txt");
+		await writeFile(apiKeysFile, "from-file-1\n\nfrom-file-2\n");
+		process.env.ROUTER_API_KEYS = "from-env-1, from-env-2";
+		t.after(() => {
+			delete process.env.ROUTER_API_KEYS;
+		});
+		const config = {
+			...givenConfig(),
+			auth: {
+				apiKeys: ["inline"],
+				apiKeysFile,
+				apiKeysEnv: "ROUTER_API_KEYS",
+			},
+		};
+
+		// Act
+		const repository = await createRepository(config);
+
+		// Assert
+		assert.deepStrictEqual(
+			[...repository.getApiKeys()],
+			["inline", "from-file-1", "from-file-2", "from-env-1", "from-env-2"],
+		);
+	});
+});
+
+test("reloadFromFile", async (t) => {
+	await t.test("should read the edited file and API keys", async () => {
+		// Arrange
+		const configPath = await createConfigFile(givenConfig());
+		const repository = await ConfigRepository.createFromFile(configPath);
+		const edited = {
+			...givenConfig({ network: { port: 9091 } }),
+			auth: { apiKeys: ["new-key"] },
+		};
+		await writeFile(configPath, JSON.stringify(edited), { encoding: "utf8" });
+
+		// Act
+		await repository.reloadFromFile();
+
+		// Assert
+		const modelA = repository.getModelConfiguration("model-a");
+		assert.strictEqual(modelA?.network.port, 9091);
+		assert.deepStrictEqual([...repository.getApiKeys()], ["new-key"]);
+		const saved = JSON.parse(await readFile(configPath, "utf8"));
+		assert.deepStrictEqual(saved, edited);
+	});
+
+	await t.test("should keep the configuration of an invalid file", async () => {
+		// Arrange
+		const configPath = await createConfigFile(givenConfig());
+		const repository = await ConfigRepository.createFromFile(configPath);
+		await writeFile(configPath, "{", { encoding: "utf8" });
+
+		// Act
+		const reload = repository.reloadFromFile();
+
+		// Assert
+		await assert.rejects(reload);
+		const modelA = repository.getModelConfiguration("model-a");
+		assert.strictEqual(modelA?.network.port, 8081);
+	});
+});
@@ -0,0 +1,95 @@
+import assert from "node:assert";
+import { test } from "node:test";
+import { createModelConfiguration } from "#src/repositories/configRepository.ts";
+import {
+	DRY_RUN_CONTENT,
+	DryRunLlamaServerRepository,
+} from "#src/repositories/dryRunLlamaServerRepository.ts";
+import { LlamaServerStopError } from "#src/repositories/llamaServerRepository.ts";
+
+const givenModelConfiguration = createModelConfiguration({
+	modelFilePath: "/models/model-a.gguf",
+	network: { port: 18_081 },
+});
+
+test("DryRunLlamaServerRepository", async (t) => {
+	await t.test("should answer chat completions with a stub", async () => {
+		// Arrange
+		const repository = new DryRunLlamaServerRepository();
+		const handle = await repository.start(givenModelConfiguration);
+
+		// Act
+		const response = await fetch(
+			"http://127.0.0.1:18081/v1/chat/completions",
+			{
+				method: "POST",
+				body: JSON.stringify({ model: "model-a", messages: [] }),
+			},
+		);
+		const body = await response.json();
+		await repository.stop(handle.pid);
+
+		// Assert
+		assert.strictEqual(response.status, 200);
+		assert.strictEqual(body.choices[0].message.content, DRY_RUN_CONTENT);
+	});
+
+	await t.test("should stream the stub when asked to", async () => {
+		// Arrange
+		const repository = new DryRunLlamaServerRepository();
+		const handle = await repository.start(givenModelConfiguration);
+
+		// Act
+		const response = await fetch(
+			"http://127.0.0.1:18081/v1/chat/completions",
+			{
+				method: "POST",
+				body: JSON.stringify({ model: "model-a", stream: true }),
+			},
+		);
+		const text = await response.text();
+		await repository.stop(handle.pid);
+
+		// Assert
+		assert.match(text, /"content":"This is a dry run response\."/);
+		assert.ok(text.endsWith("data: [DONE]\n\n"));
+	});
+
+	await t.test("should answer embeddings and reranks with zeros", async () => {
+		// Arrange
+		const repository = new DryRunLlamaServerRepository();
+		const handle = await repository.start(givenModelConfiguration);
+
+		// Act
+		const embeddings = await fetch("http://127.0.0.1:18081/v1/embeddings", {
+			method: "POST",
+			body: JSON.stringify({ model: "model-a", input: ["a", "b"] }),
+		}).then((response) => response.json());
+		const rerank = await fetch("http://127.0.0.1:18081/v1/rerank", {
+			method: "POST",
+			body: JSON.stringify({ model: "model-a", documents: ["a", "b"] }),
+		}).then((response) => response.json());
+		await repository.stop(handle.pid);
+
+		// Assert
+		assert.deepStrictEqual(
+			embeddings.data.map((item: { index: number }) => item.index),
+			[0, 1],
+		);
+		assert.deepStrictEqual(rerank.results, [
+			{ index: 0, relevance_score: 0 },
+			{ index: 1, relevance_score: 0 },
+		]);
+	});
+
+	await t.test("should reject stopping an unknown server", async () => {
+		// Arrange
+		const repository = new DryRunLlamaServerRepository();
+
+		// Act
+		const stop = repository.stop(42);
+
+		// Assert
+		await assert.rejects(stop, LlamaServerStopError);
+	});
+});
@@ -0,0 +1,368 @@
+import assert from "node:assert";
+import { spawn } from "node:child_process";
+import { once } from "node:events";
+import { chmod, mkdtemp, symlink, writeFile } from "node:fs/promises";
+import { tmpdir } from "node:os";
+import path from "node:path";
+import { test } from "node:test";
+import {
+	createModelConfiguration,
+	type ModelConfiguration,
+} from "#src/repositories/configRepository.ts";
+import {
+	LlamaServerRepository,
+	type LlamaServerStartError,
+	LlamaServerStartTimeoutError,
+} from "#src/repositories/llamaServerRepository.ts";
+
+const givenModelConfiguration = createModelConfiguration({
+	modelFilePath: "/models/model-a.gguf",
+	network: { port: 8081 },
+});
+
+async function createFakeBinary(script: string) {
+	const directory = await mkdtemp(path.join(tmpdir(), "llama-server-"));
+	const binaryPath = path.join(directory, "llama-server");
+	await writeFile(binaryPath, `#!/bin/sh\n${script}\n`, { encoding: "utf8" });
+	await chmod(binaryPath, 0o755);
+	return binaryPath;
+}
+
+test("start", async (t) => {
+	await t.test(
+		"should time out when the server never reports listening",
+		async () => {
+			// Arrange
+			const binaryPath = await createFakeBinary("exec sleep 10");
+			const repository = new LlamaServerRepository(binaryPath);
+
+			// Act
+			const start = repository.start(givenModelConfiguration, {
+				timeoutMs: 100,
+			});
+
+			// Assert
+			await assert.rejects(start, LlamaServerStartTimeoutError);
+		},
+	);
+
+	await t.test(
+		"should report the last log lines when the server exits",
+		async () => {
+			// Arrange
+			const binaryPath = await createFakeBinary(
+				'echo "error: failed to load model" >&2\nexit 1',
+			);
+			const repository = new LlamaServerRepository(binaryPath);
+
+			// Act
+			const start = repository.start(givenModelConfiguration, {
+				timeoutMs: 5_000,
+			});
+
+			// Assert
+			await assert.rejects(start, (error: LlamaServerStartError) => {
+				assert.match(error.message, /code=1/);
+				assert.strictEqual(error.stderr, "error: failed to load model");
+				return true;
+			});
+		},
+	);
+
+	await t.test("should resolve once the server is listening", async () => {
+		// Arrange
+		const binaryPath = await createFakeBinary(
+			'echo "main: server is listening on http://127.0.0.1:8081" >&2\nexec sleep 10',
+		);
+		const repository = new LlamaServerRepository(binaryPath);
+
+		// Act
+		const handle = await repository.start(givenModelConfiguration, {
+			timeoutMs: 5_000,
+		});
+
+		// Assert
+		assert.ok(handle.pid > 0);
+		await repository.stop(handle.pid);
+	});
+
+	await t.test(
+		"should omit the sampling parameters when they are not baked",
+		async () => {
+			// Arrange
+			const binaryPath = await createFakeBinary('echo "$@" >&2\nexit 1');
+			const repository = new LlamaServerRepository(binaryPath);
+
+			// Act
+			const start = repository.start(
+				{ ...givenModelConfiguration, bakeSamplingParams: false },
+				{ timeoutMs: 5_000 },
+			);
+
+			// Assert
+			await assert.rejects(start, (error: LlamaServerStartError) => {
+				assert.match(error.stderr, /--ctx-size 4096/);
+				assert.doesNotMatch(error.stderr, /--temp/);
+				return true;
+			});
+		},
+	);
+
+	await t.test("should pass the batch sizes when they are set", async () => {
+		// Arrange
+		const binaryPath = await createFakeBinary('echo "$@" >&2\nexit 1');
+		const repository = new LlamaServerRepository(binaryPath);
+
+		// Act
+		const start = repository.start(
+			{
+				...givenModelConfiguration,
+				common: {
+					...givenModelConfiguration.common,
+					batchSize: 4096,
+					ubatchSize: 1024,
+				},
+			},
+			{ timeoutMs: 5_000 },
+		);
+
+		// Assert
+		await assert.rejects(start, (error: LlamaServerStartError) => {
+			assert.match(error.stderr, /--batch-size 4096/);
+			assert.match(error.stderr, /--ubatch-size 1024/);
+			return true;
+		});
+	});
+
+	await t.test("should pass the RoPE scaling when it is set", async () => {
+		// Arrange
+		const binaryPath = await createFakeBinary('echo "$@" >&2\nexit 1');
+		const repository = new LlamaServerRepository(binaryPath);
+
+		// Act
+		const start = repository.start(
+			{
+				...givenModelConfiguration,
+				common: {
+					...givenModelConfiguration.common,
+					ropeScaling: "yarn",
+					ropeFreqBase: 1_000_000,
+					ropeFreqScale: 0.25,
+				},
+			},
+			{ timeoutMs: 5_000 },
+		);
+
+		// Assert
+		await assert.rejects(start, (error: LlamaServerStartError) => {
+			assert.match(error.stderr, /--rope-scaling yarn/);
+			assert.match(error.stderr, /--rope-freq-base 1000000/);
+			assert.match(error.stderr, /--rope-freq-scale 0.25/);
+			return true;
+		});
+	});
+
+	await t.test("should toggle continuous batching", async () => {
+		// Arrange
+		const binaryPath = await createFakeBinary('echo "$@" >&2\nexit 1');
+		const repository = new LlamaServerRepository(binaryPath);
+		const startWith = (contBatching: boolean) =>
+			repository.start(
+				{
+					...givenModelConfiguration,
+					common: { ...givenModelConfiguration.common, contBatching },
+				},
+				{ timeoutMs: 5_000 },
+			);
+
+		// Act
+		const enabled = startWith(true);
+		const disabled = startWith(false);
+
+		// Assert
+		await assert.rejects(enabled, (error: LlamaServerStartError) => {
+			assert.match(error.stderr, /(^| )--cont-batching/);
+			return true;
+		});
+		await assert.rejects(disabled, (error: LlamaServerStartError) => {
+			assert.match(error.stderr, /--no-cont-batching/);
+			return true;
+		});
+	});
+
+	await t.test("should pass --mlock when mlock is set", async () => {
+		// Arrange
+		const binaryPath = await createFakeBinary('echo "$@" >&2\nexit 1');
+		const repository = new LlamaServerRepository(binaryPath);
+
+		// Act
+		const start = repository.start(
+			{
+				...givenModelConfiguration,
+				common: { ...givenModelConfiguration.common, mlock: true },
+			},
+			{ timeoutMs: 5_000 },
+		);
+
+		// Assert
+		await assert.rejects(start, (error: LlamaServerStartError) => {
+			assert.match(error.stderr, /--mlock/);
+			return true;
+		});
+	});
+
+	await t.test("should only expose the GPU of a pinned model", async () => {
+		// Arrange
+		const binaryPath = await createFakeBinary(
+			'echo "gpu=$HIP_VISIBLE_DEVICES" >&2\nexit 1',
+		);
+		const repository = new LlamaServerRepository(binaryPath);
+
+		// Act
+		const start = repository.start(
+			{ ...givenModelConfiguration, gpuDevice: 1 },
+			{ timeoutMs: 5_000 },
+		);
+
+		// Assert
+		await assert.rejects(start, (error: LlamaServerStartError) => {
+			assert.strictEqual(error.stderr, "gpu=1");
+			return true;
+		});
+	});
+
+	await t.test("should launch a rerank model without sampling", async () => {
+		// Arrange
+		const binaryPath = await createFakeBinary('echo "$@" >&2\nexit 1');
+		const repository = new LlamaServerRepository(binaryPath);
+
+		// Act
+		const start = repository.start(
+			{ ...givenModelConfiguration, task: "rerank" },
+			{ timeoutMs: 5_000 },
+		);
+
+		// Assert
+		await assert.rejects(start, (error: LlamaServerStartError) => {
+			assert.match(error.stderr, /--reranking/);
+			assert.doesNotMatch(error.stderr, /--temp/);
+			return true;
+		});
+	});
+});
+
+test("adopt", async (t) => {
+	/**
+	 * Stand-in for a llama-server left running by a previous router, with the
+	 * given port in its arguments
+	 */
+	async function spawnLeftServer(port: string) {
+		const child = spawn(process.execPath, [
+			"-e",
+			"setInterval(() => {}, 1_000)",
+			"--",
+			"--port",
+			port,
+		]);
+		t.after(() => child.kill("SIGKILL"));
+		await once(child, "spawn");
+		return child;
+	}
+
+	/**
+	 * Start a server with a router that then goes away. The binary is bash, so
+	 * that its command line is the one of llama-server, running the model
+	 * file as a script that reports listening and then sleeps.
+	 */
+	async function startWithPreviousRouter(opts: ModelConfiguration) {
+		const directory = await mkdtemp(path.join(tmpdir(), "llama-server-"));
+		const binaryPath = path.join(directory, "llama-server");
+		await symlink("/bin/bash", binaryPath);
+		const modelFilePath = path.join(directory, "model.gguf");
+		await writeFile(
+			modelFilePath,
+			'echo "main: server is listening on" >&2\nwhile :; do sleep 1; done\n',
+		);
+		const modelConfiguration = { ...opts, modelFilePath };
+		const { pid } = await new LlamaServerRepository(binaryPath).start(
+			modelConfiguration,
+			{ timeoutMs: 5_000 },
+		);
+		t.after(() => process.kill(pid, "SIGKILL"));
+		return { binaryPath, modelConfiguration, pid };
+	}
+
+	await t.test("should adopt a server with the same arguments", async () => {
+		// Arrange
+		const { binaryPath, modelConfiguration, pid } =
+			await startWithPreviousRouter(givenModelConfiguration);
+		const repository = new LlamaServerRepository(binaryPath);
+
+		// Act
+		const handle = await repository.adopt(modelConfiguration);
+
+		// Assert
+		assert.deepStrictEqual(handle, { pid });
+	});
+
+	await t.test(
+		"should adopt an automatic slot count with its context size",
+		async () => {
+			// Arrange
+			const { binaryPath, modelConfiguration, pid } =
+				await startWithPreviousRouter({
+					...givenModelConfiguration,
+					common: {
+						...givenModelConfiguration.common,
+						parallel: 3,
+						contextSize: 3 * 4096,
+					},
+				});
+			const repository = new LlamaServerRepository(binaryPath);
+
+			// Act
+			const handle = await repository.adopt({
+				...modelConfiguration,
+				common: {
+					...modelConfiguration.common,
+					parallel: "auto",
+					contextSize: 4096,
+				},
+			});
+
+			// Assert
+			assert.deepStrictEqual(handle, { pid });
+		},
+	);
+
+	await t.test("should leave a server with other arguments", async () => {
+		// Arrange
+		const warn = t.mock.method(console, "warn", () => {});
+		const child = await spawnLeftServer("8081");
+		const repository = new LlamaServerRepository(process.execPath);
+
+		// Act
+		const handle = await repository.adopt(givenModelConfiguration);
+
+		// Assert
+		assert.strictEqual(handle, null);
+		assert.strictEqual(child.exitCode, null);
+		assert.match(
+			String(warn.mock.calls[0].arguments[0]),
+			new RegExp(`Not adopting llama-server ${child.pid}`),
+		);
+	});
+
+	await t.test("should leave the servers of other ports running", async () => {
+		// Arrange
+		const child = await spawnLeftServer("8082");
+		const repository = new LlamaServerRepository(process.execPath);
+
+		// Act
+		const handle = await repository.adopt(givenModelConfiguration);
+
+		// Assert
+		assert.strictEqual(handle, null);
+		assert.strictEqual(child.exitCode, null);
+	});
+});
@@ -0,0 +1,138 @@
+import assert from "node:assert";
+import { chmod, mkdtemp, readFile, writeFile } from "node:fs/promises";
+import { tmpdir } from "node:os";
+import path from "node:path";
+import { test } from "node:test";
+import { RocmSmiRepository } from "#src/repositories/rocmSmiRepository.ts";
+
+const givenOutput = {
+	card0: {
+		"VRAM Total Memory (B)": "17163091968",
+		"VRAM Total Used Memory (B)": "6442450944",
+	},
+};
+
+/**
+ * Create a fake rocm-smi appending a line to a file each time it is called
+ */
+async function createFakeBinary(output: unknown = givenOutput) {
+	const directory = await mkdtemp(path.join(tmpdir(), "rocm-smi-"));
+	const binaryPath = path.join(directory, "rocm-smi");
+	const callsPath = path.join(directory, "calls");
+	await writeFile(callsPath, "");
+	await writeFile(
+		binaryPath,
+		`#!/bin/sh\necho >> ${callsPath}\necho '${JSON.stringify(output)}'\n`,
+		{ encoding: "utf8" },
+	);
+	await chmod(binaryPath, 0o755);
+	const countCalls = async () => (await readFile(callsPath, "utf8")).length;
+	return { binaryPath, countCalls };
+}
+
+test("getVramInfo", async (t) => {
+	await t.test("should parse the VRAM of each card", async () => {
+		// Arrange
+		const { binaryPath } = await createFakeBinary();
+		const repository = new RocmSmiRepository(binaryPath);
+
+		// Act
+		const vramInfos = await repository.getVramInfo();
+
+		// Assert
+		assert.deepStrictEqual(vramInfos, [
+			{ card: "card0", totalBytes: 17163091968, usedBytes: 6442450944 },
+		]);
+	});
+
+	await t.test("should reuse a recent poll", async () => {
+		// Arrange
+		const { binaryPath, countCalls } = await createFakeBinary();
+		const repository = new RocmSmiRepository(binaryPath, 60_000);
+
+		// Act
+		await repository.getVramInfo({ device: 0 });
+		await repository.getVramInfo({ device: 0 });
+
+		// Assert
+		assert.strictEqual(await countCalls(), 1);
+	});
+
+	await t.test("should poll again when forced to", async () => {
+		// Arrange
+		const { binaryPath, countCalls } = await createFakeBinary();
+		const repository = new RocmSmiRepository(binaryPath, 60_000);
+
+		// Act
+		await repository.getVramInfo({ device: 0 });
+		await repository.getVramInfo({ device: 0, forceRefresh: true });
+
+		// Assert
+		assert.strictEqual(await countCalls(), 2);
+	});
+
+	for (const [release, output] of [
+		[
+			"ROCm 4",
+			{
+				card0: {
+					"vram Total Memory (B)": "17163091968",
+					"vram Total Used Memory (B)": "6442450944",
+				},
+			},
+		],
+		[
+			"ROCm 6",
+			{
+				card0: {
+					"VRAM Total Memory (B)": "17163091968",
+					"VRAM Total Used Memory (B)": "6442450944",
+				},
+				system: { "Driver version": "6.8.5" },
+			},
+		],
+	] as const) {
+		await t.test(`should parse the output of ${release}`, async () => {
+			// Arrange
+			const { binaryPath } = await createFakeBinary(output);
+			const repository = new RocmSmiRepository(binaryPath);
+
+			// Act
+			const vramInfos = await repository.getVramInfo();
+
+			// Assert
+			assert.deepStrictEqual(vramInfos, [
+				{ card: "card0", totalBytes: 17163091968, usedBytes: 6442450944 },
+			]);
+		});
+	}
+
+	await t.test("should reject an output without any VRAM", async () => {
+		// Arrange
+		const { binaryPath } = await createFakeBinary({
+			card0: { "GPU Memory Total": "17163091968" },
+		});
+		const repository = new RocmSmiRepository(binaryPath);
+
+		// Act
+		const vramInfos = repository.getVramInfo();
+
+		// Assert
+		await assert.rejects(vramInfos, /did not report the VRAM of any card/);
+	});
+
+	await t.test("should reject when rocm-smi is missing", async () => {
+		// Arrange
+		const directory = await mkdtemp(path.join(tmpdir(), "rocm-smi-"));
+		const repository = new RocmSmiRepository(path.join(directory, "missing"));
+
+		// Act
+		const vramInfos = repository.getVramInfo();
+
+		// Assert
+		await assert.rejects(vramInfos, {
+			name: "RocmSmiError",
+			message: /Failed to run rocm-smi: spawn .* ENOENT/,
+		});
+	});
+});
@@ -0,0 +1,131 @@
+import assert from "node:assert";
+import { test } from "node:test";
+import { HTTPException } from "hono/http-exception";
+import {
+	LlamaServerStartError,
+	LlamaServerStartTimeoutError,
+} from "#src/repositories/llamaServerRepository.ts";
+import { toApiError } from "#src/server/apiError.ts";
+import {
+	InsufficientMemoryError,
+	ModelLoadingError,
+	NotSupportedError,
+	ResponseTooLargeError,
+	ServerBusyError,
+	StreamIdleTimeoutError,
+	TooManyRequestsError,
+	UpstreamError,
+} from "#src/services/llamaProxyService.ts";
+import { ModelNotFoundError } from "#src/services/modelFitService.ts";
+
+test("toApiError", async (t) => {
+	const cases = [
+		{
+			error: new ModelNotFoundError("missing"),
+			status: 404,
+			expected: { type: "invalid_request_error", code: "model_not_found" },
+		},
+		{
+			error: new InsufficientMemoryError("too big"),
+			status: 503,
+			expected: { type: "server_error", code: "insufficient_memory" },
+		},
+		{
+			error: new NotSupportedError("no embeddings"),
+			status: 400,
+			expected: { type: "invalid_request_error", code: "not_supported" },
+		},
+		{
+			error: new ResponseTooLargeError("too long"),
+			status: 502,
+			expected: { type: "server_error", code: "response_too_large" },
+		},
+		{
+			error: new UpstreamError("bad request", 400),
+			status: 400,
+			expected: { type: "invalid_request_error", code: "upstream_error" },
+		},
+		{
+			error: new UpstreamError("overloaded", 503),
+			status: 502,
+			expected: { type: "server_error", code: "upstream_error" },
+		},
+		{
+			error: new LlamaServerStartTimeoutError("timed out", "", ""),
+			status: 504,
+			expected: { type: "server_error", code: "model_startup_timeout" },
+		},
+		{
+			error: new TooManyRequestsError("model busy"),
+			status: 429,
+			expected: { type: "invalid_request_error", code: "too_many_requests" },
+		},
+		{
+			error: new ServerBusyError("busy"),
+			status: 503,
+			expected: { type: "server_error", code: "server_busy" },
+		},
+		{
+			error: new StreamIdleTimeoutError("stalled"),
+			status: 504,
+			expected: { type: "server_error", code: "stream_idle_timeout" },
+		},
+		{
+			error: new LlamaServerStartError("crashed", "", ""),
+			status: 502,
+			expected: { type: "server_error", code: "model_startup_failed" },
+		},
+		{
+			error: new Error("unexpected"),
+			status: 500,
+			expected: { type: "server_error", code: null },
+		},
+	];
+
+	for (const { error, status, expected } of cases) {
+		await t.test(`should convert ${error.constructor.name}`, () => {
+			// Act
+			const result = toApiError(error);
+
+			// Assert
+			assert.strictEqual(result.status, status);
+			assert.deepStrictEqual(result.body, {
+				error: { message: error.message, ...expected },
+			});
+		});
+	}
+
+	await t.test("should ask to retry while a model is loading", () => {
+		// Arrange
+		const error = new ModelLoadingError("loading", 5);
+
+		// Act
+		const { status, body, headers } = toApiError(error);
+
+		// Assert
+		assert.strictEqual(status, 503);
+		assert.strictEqual(body.error.code, "model_loading");
+		assert.deepStrictEqual(headers, { "Retry-After": "5" });
+	});
+
+	await t.test("should use the status and cause of an HTTPException", () => {
+		// Arrange
+		const error = new HTTPException(404, {
+			message: "not found",
+			cause: new ModelNotFoundError("missing"),
+		});
+
+		// Act
+		const { status, body } = toApiError(error);
+
+		// Assert
+		assert.strictEqual(status, 404);
+		assert.deepStrictEqual(body, {
+			error: {
+				message: "not found",
+				type: "invalid_request_error",
+				code: "model_not_found",
+			},
+		});
+	});
+});
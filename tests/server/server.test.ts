@@ -1,33 +1,116 @@
 import assert from "node:assert";
+import { once } from "node:events";
+import { readFileSync } from "node:fs";
+import { mkdtemp, rm } from "node:fs/promises";
+import { createServer, request as httpRequest } from "node:http";
+import { request as httpsRequest } from "node:https";
+import { type AddressInfo, connect } from "node:net";
+import { tmpdir } from "node:os";
+import path from "node:path";
+import { text } from "node:stream/consumers";
 import { mock, test } from "node:test";
+import { fileURLToPath } from "node:url";
+import { gunzipSync } from "node:zlib";
+import type { HttpBindings } from "@hono/node-server";
 import type { ConfigRepository } from "#src/repositories/configRepository.ts";
+import { AnthropicController } from "#src/server/controllers/AnthropicController.ts";
 import { ChatController } from "#src/server/controllers/ChatController.ts";
 import { ConfigController } from "#src/server/controllers/ConfigController.ts";
 import { EmbeddingsController } from "#src/server/controllers/EmbeddingsController.ts";
 import { ModelFitsController } from "#src/server/controllers/ModelFitsController.ts";
 import { ModelsController } from "#src/server/controllers/ModelsController.ts";
+import { OllamaController } from "#src/server/controllers/OllamaController.ts";
+import { RerankController } from "#src/server/controllers/RerankController.ts";
 import { Router } from "#src/server/router.ts";
 import { Server } from "#src/server/server.ts";
 import type { ConfigService } from "#src/services/configService.ts";
-import type { LlamaProxyService } from "#src/services/llamaProxyService.ts";
+import {
+	InsufficientMemoryError,
+	type LlamaProxyService,
+	StreamIdleTimeoutError,
+	TooManyRequestsError,
+} from "#src/services/llamaProxyService.ts";
 import type { ModelFitService } from "#src/services/modelFitService.ts";
 import { Model, type ModelsService } from "#src/services/modelsService.ts";
+import { UsageService } from "#src/services/usageService.ts";
 
-function mockRouter(owner: string = "", ...models: string[]) {
+function mockRouter(
+	owner: string = "",
+	models: string[] = [],
+	basePath?: string,
+	completionChunks: string[] = [],
+	apiKeys: string[] = [],
+) {
 	const modelService = {
-		getModels: mock.fn(() => models.map((id) => new Model(id, owner))),
+		getModels: mock.fn(async () =>
+			models.map((id) =>
+				new Model(
+					id,
+					owner,
+					`${id} display name`,
+					0,
+					{ vision: id === "model-a", tools: false, jsonMode: false },
+					id === "model-a" ? ["chat", "vision"] : ["chat"],
+				),
+			),
+		),
 	};
 	const modelFitService = {} as ModelFitService;
-	const llamaProxyService = {} as LlamaProxyService;
+	const llamaProxyService = {
+		getLoadedModels: mock.fn(() => models.slice(0, 1)),
+		getLastUsed: mock.fn((name: string) =>
+			name === models[0] ? 1_700_000_000_000 : undefined,
+		),
+		getLoadDuration: mock.fn((name: string) =>
+			name === models[0] ? 12_345 : undefined,
+		),
+		checkRequest: mock.fn((_model: string, _task: string) => {}),
+		chatCompletion: mock.fn(
+			async (_model: string, _signal: AbortSignal, _body: string) =>
+				ReadableStream.from(
+					completionChunks.map((chunk) => new TextEncoder().encode(chunk)),
+				),
+		),
+	};
+	const usageService = new UsageService();
 	const configService = {} as ConfigService;
+	const configRepository = {
+		getLoadingEvents: mock.fn(() => true),
+		getLoadMode: mock.fn(() => "block"),
+		getModelConfiguration: mock.fn((name: string) =>
+			models.includes(name) ? {} : null,
+		),
+		getApiKeys: mock.fn(() => new Set(apiKeys)),
+		getServerConfiguration: mock.fn(() => ({
+			maxRequestBodyBytes: 1024,
+			requestTimeout: 0,
+		})),
+	};
 
 	const modelsController = new ModelsController(
 		modelService as unknown as ModelsService,
+		llamaProxyService as unknown as LlamaProxyService,
+		usageService,
 	);
 	const modelFitsController = new ModelFitsController(modelFitService);
-	const chatController = new ChatController(llamaProxyService);
-	const embeddingsController = new EmbeddingsController(llamaProxyService);
+	const chatController = new ChatController(
+		llamaProxyService as unknown as LlamaProxyService,
+		configRepository as unknown as ConfigRepository,
+	);
+	const embeddingsController = new EmbeddingsController(
+		llamaProxyService as unknown as LlamaProxyService,
+	);
 	const configController = new ConfigController(configService);
+	const ollamaController = new OllamaController(
+		modelService as unknown as ModelsService,
+		llamaProxyService as unknown as LlamaProxyService,
+	);
+	const anthropicController = new AnthropicController(
+		llamaProxyService as unknown as LlamaProxyService,
+	);
+	const rerankController = new RerankController(
+		llamaProxyService as unknown as LlamaProxyService,
+	);
 
 	const router = new Router(
 		modelsController,
@@ -35,8 +118,19 @@ function mockRouter(owner: string = "", ...models: string[]) {
 		chatController,
 		embeddingsController,
 		configController,
+		ollamaController,
+		anthropicController,
+		rerankController,
+		configRepository as unknown as ConfigRepository,
+		basePath,
 	);
-	return { router, modelService };
+	return {
+		router,
+		modelService,
+		llamaProxyService,
+		configRepository,
+		usageService,
+	};
 }
 
 test("handleRequest", async (t) => {
@@ -58,7 +152,13 @@ test("handleRequest", async (t) => {
 		// Assert
 		assert.strictEqual(res.status, 404);
 		const body = await res.json();
-		assert.deepStrictEqual(body, { notFound: true });
+		assert.deepStrictEqual(body, {
+			error: {
+				message: "Not found",
+				type: "invalid_request_error",
+				code: "not_found",
+			},
+		});
 	});
 });
 
@@ -67,18 +167,22 @@ test("GET /v1/models", async (t) => {
 		// Arrange
 		const givenOwner = "bob";
 		const givenModels = ["model-a", "model-b"];
-		const { router, modelService } = mockRouter(givenOwner, ...givenModels);
+		const { router, modelService } = mockRouter(givenOwner, givenModels);
 		const app = router.getApp();
 		const expectedResponse = {
 			object: "list",
 			data: [
 				{
 					id: "model-a",
+					display_name: "model-a display name",
 					object: "model",
 					owned_by: "bob",
+					capabilities: { vision: true, tools: false, json_mode: false },
+					tags: ["chat", "vision"],
 				},
 				{
 					id: "model-b",
+					display_name: "model-b display name",
 					object: "model",
 					owned_by: "bob",
 				},
@@ -122,4 +226,1078 @@ test("GET /v1/models", async (t) => {
 			);
 		},
 	);
+
+	for (const [tag, expectedIds] of [
+		["vision", ["model-a"]],
+		["chat", ["model-a", "model-b"]],
+		["unknown", []],
+	] as const) {
+		await t.test(`should filter the models tagged ${tag}`, async () => {
+			// Arrange
+			const { router } = mockRouter("bob", ["model-a", "model-b"]);
+
+			// Act
+			const res = await router.getApp().request(`/v1/models?tag=${tag}`);
+
+			// Assert
+			assert.strictEqual(res.status, 200);
+			const { data } = await res.json();
+			assert.deepStrictEqual(
+				data.map((model: { id: string }) => model.id),
+				expectedIds,
+			);
+		});
+	}
+
+	await t.test("should report the loaded models when asked", async () => {
+		// Arrange
+		const { router, usageService } = mockRouter("bob", ["model-a", "model-b"]);
+		const app = router.getApp();
+		usageService.record("model-a", {
+			prompt_tokens: 12,
+			completion_tokens: 34,
+		});
+
+		// Act
+		const res = await app.request("/v1/models?status=true");
+		const defaultRes = await app.request("/v1/models");
+
+		// Assert
+		const { data } = await res.json();
+		assert.deepStrictEqual(
+			data.map(({ loaded, last_used, load_duration_ms }) => [
+				loaded,
+				last_used,
+				load_duration_ms,
+			]),
+			[
+				[true, 1_700_000_000, 12_345],
+				[false, null, null],
+			],
+		);
+		assert.deepStrictEqual(data[0].usage, {
+			requests: 0,
+			prompt_tokens: 12,
+			completion_tokens: 34,
+		});
+		const { data: defaultData } = await defaultRes.json();
+		assert.ok(!("loaded" in defaultData[0]));
+	});
+
+	await t.test("should be served under the configured base path", async () => {
+		// Arrange
+		const { router, modelService } = mockRouter("bob", ["model-a"], "/api/v1");
+		const app = router.getApp();
+
+		// Act
+		const res = await app.request("/api/v1/models", { method: "GET" });
+		const defaultRes = await app.request("/v1/models", { method: "GET" });
+
+		// Assert
+		assert.strictEqual(res.status, 200);
+		assert.strictEqual(defaultRes.status, 404);
+		assert.strictEqual(modelService.getModels.mock.callCount(), 1);
+	});
+});
+
+test("API keys", async (t) => {
+	await t.test("should reject a request without a valid key", async () => {
+		// Arrange
+		const { router } = mockRouter("bob", ["model-a"], "/v1", [], ["secret"]);
+		const app = router.getApp();
+
+		// Act
+		const res = await app.request("/v1/models", {
+			headers: { Authorization: "Bearer wrong" },
+		});
+
+		// Assert
+		assert.strictEqual(res.status, 401);
+		assert.strictEqual((await res.json()).error.code, "invalid_api_key");
+	});
+
+	for (const [header, value] of [
+		["Authorization", "Bearer secret"],
+		["x-api-key", "secret"],
+	]) {
+		await t.test(`should accept a key in the ${header} header`, async () => {
+			// Arrange
+			const { router } = mockRouter("bob", ["model-a"], "/v1", [], ["secret"]);
+			const app = router.getApp();
+
+			// Act
+			const res = await app.request("/v1/models", {
+				headers: { [header]: value },
+			});
+
+			// Assert
+			assert.strictEqual(res.status, 200);
+		});
+	}
+});
+
+// Node bindings the controllers rely on, missing from app.request
+const givenBindings = {
+	outgoing: { on: () => {} },
+} as unknown as HttpBindings;
+
+test("Ollama API", async (t) => {
+	await t.test("should list the models as tags", async () => {
+		// Arrange
+		const { router } = mockRouter("bob", ["model-a"]);
+		const app = router.getApp();
+
+		// Act
+		const res = await app.request("/api/tags", { method: "GET" });
+
+		// Assert
+		assert.strictEqual(res.status, 200);
+		assert.partialDeepStrictEqual(await res.json(), {
+			models: [{ name: "model-a", model: "model-a" }],
+		});
+	});
+
+	await t.test(
+		"should translate a chat request and its response",
+		async () => {
+			// Arrange
+			const completion = {
+				choices: [{ message: { content: "Hi!" }, finish_reason: "stop" }],
+				usage: { prompt_tokens: 4, completion_tokens: 2 },
+			};
+			const { router, llamaProxyService } = mockRouter(
+				"bob",
+				["model-a"],
+				"/v1",
+				[JSON.stringify(completion)],
+			);
+			const app = router.getApp();
+			const request = {
+				model: "model-a",
+				messages: [{ role: "user", content: "Hello" }],
+				stream: false,
+				options: { temperature: 0.2, num_predict: 16 },
+			};
+
+			// Act
+			const res = await app.request(
+				"/api/chat",
+				{ method: "POST", body: JSON.stringify(request) },
+				givenBindings,
+			);
+
+			// Assert
+			assert.strictEqual(res.status, 200);
+			const [, , body] =
+				llamaProxyService.chatCompletion.mock.calls[0].arguments;
+			assert.partialDeepStrictEqual(JSON.parse(body), {
+				model: "model-a",
+				messages: [{ role: "user", content: "Hello" }],
+				temperature: 0.2,
+				max_tokens: 16,
+				stream: false,
+			});
+			assert.partialDeepStrictEqual(await res.json(), {
+				model: "model-a",
+				message: { role: "assistant", content: "Hi!" },
+				done: true,
+				done_reason: "stop",
+				prompt_eval_count: 4,
+				eval_count: 2,
+			});
+		},
+	);
+
+	await t.test("should stream the response as NDJSON", async () => {
+		// Arrange
+		const chunks = [
+			'data: {"choices":[{"delta":{"content":"Hi"}}]}\n\n',
+			'data: {"choices":[{"delta":{"content":"!"},"finish_reason":"stop"}]}\n\n',
+			"data: [DONE]\n\n",
+		];
+		const { router } = mockRouter("bob", ["model-a"], "/v1", chunks);
+		const app = router.getApp();
+		const request = {
+			model: "model-a",
+			messages: [{ role: "user", content: "Hello" }],
+		};
+
+		// Act
+		const res = await app.request(
+			"/api/chat",
+			{ method: "POST", body: JSON.stringify(request) },
+			givenBindings,
+		);
+
+		// Assert
+		assert.strictEqual(
+			res.headers.get("Content-Type"),
+			"application/x-ndjson",
+		);
+		const lines = (await res.text())
+			.trim()
+			.split("\n")
+			.map((line) => JSON.parse(line));
+		assert.deepStrictEqual(
+			lines.map((line) => [line.message.content, line.done]),
+			[
+				["Hi", false],
+				["!", false],
+				["", true],
+			],
+		);
+	});
+
+	await t.test("should label the images with their media type", async () => {
+		// Arrange
+		const { router, llamaProxyService } = mockRouter("bob", ["model-a"]);
+		const app = router.getApp();
+		const jpeg = Buffer.from([0xff, 0xd8, 0xff, 0xe0]).toString("base64");
+		const webp = Buffer.from("RIFF\0\0\0\0WEBPVP8 ").toString("base64");
+		const request = {
+			model: "model-a",
+			messages: [{ role: "user", content: "What?", images: [jpeg, webp] }],
+		};
+
+		// Act
+		await app.request(
+			"/api/chat",
+			{ method: "POST", body: JSON.stringify(request) },
+			givenBindings,
+		);
+
+		// Assert
+		const [, , body] =
+			llamaProxyService.chatCompletion.mock.calls[0].arguments;
+		const [, ...images] = JSON.parse(body).messages[0].content;
+		assert.deepStrictEqual(
+			images.map(({ image_url }: { image_url: { url: string } }) =>
+				image_url.url.slice(0, image_url.url.indexOf(";")),
+			),
+			["data:image/jpeg", "data:image/webp"],
+		);
+	});
+
+	await t.test("should end the stream with an error line", async () => {
+		// Arrange
+		const { router, llamaProxyService } = mockRouter("bob", ["model-a"]);
+		llamaProxyService.chatCompletion.mock.mockImplementation(
+			async () =>
+				new ReadableStream({
+					start(controller) {
+						controller.error(new StreamIdleTimeoutError("model-a stalled"));
+					},
+				}),
+		);
+		const app = router.getApp();
+		const request = {
+			model: "model-a",
+			messages: [{ role: "user", content: "Hello" }],
+		};
+
+		// Act
+		const res = await app.request(
+			"/api/chat",
+			{ method: "POST", body: JSON.stringify(request) },
+			givenBindings,
+		);
+
+		// Assert
+		assert.strictEqual(
+			await res.text(),
+			`${JSON.stringify({ error: "model-a stalled" })}\n`,
+		);
+	});
+});
+
+test("POST /v1/messages", async (t) => {
+	await t.test(
+		"should translate a message request and its response",
+		async () => {
+			// Arrange
+			const chunks = [
+				'data: {"choices":[{"delta":{"content":"Hi!"}}]}\n\n',
+				'data: {"choices":[{"delta":{},"finish_reason":"length"}]}\n\n',
+				'data: {"choices":[],"usage":{"prompt_tokens":4,"completion_tokens":2}}\n\n',
+				"data: [DONE]\n\n",
+			];
+			const { router, llamaProxyService } = mockRouter(
+				"bob",
+				["model-a"],
+				"/v1",
+				chunks,
+			);
+			const app = router.getApp();
+			const request = {
+				model: "model-a",
+				system: "Be brief.",
+				messages: [{ role: "user", content: "Hello" }],
+				max_tokens: 2,
+			};
+
+			// Act
+			const res = await app.request(
+				"/v1/messages",
+				{ method: "POST", body: JSON.stringify(request) },
+				givenBindings,
+			);
+
+			// Assert
+			assert.strictEqual(res.status, 200);
+			const [, , body] =
+				llamaProxyService.chatCompletion.mock.calls[0].arguments;
+			assert.partialDeepStrictEqual(JSON.parse(body), {
+				messages: [
+					{ role: "system", content: "Be brief." },
+					{ role: "user", content: "Hello" },
+				],
+				max_tokens: 2,
+				stream: true,
+			});
+			assert.partialDeepStrictEqual(await res.json(), {
+				type: "message",
+				role: "assistant",
+				content: [{ type: "text", text: "Hi!" }],
+				stop_reason: "max_tokens",
+				usage: { input_tokens: 4, output_tokens: 2 },
+			});
+		},
+	);
+
+	await t.test("should stream Anthropic events", async () => {
+		// Arrange
+		const chunks = [
+			'data: {"choices":[{"delta":{"content":"Hi"}}]}\n\n',
+			'data: {"choices":[{"delta":{},"finish_reason":"stop"}]}\n\n',
+			"data: [DONE]\n\n",
+		];
+		const { router } = mockRouter("bob", ["model-a"], "/v1", chunks);
+		const app = router.getApp();
+		const request = {
+			model: "model-a",
+			messages: [{ role: "user", content: "Hello" }],
+			max_tokens: 16,
+			stream: true,
+		};
+
+		// Act
+		const res = await app.request(
+			"/v1/messages",
+			{ method: "POST", body: JSON.stringify(request) },
+			givenBindings,
+		);
+
+		// Assert
+		const events = (await res.text())
+			.split("\n")
+			.filter((line) => line.startsWith("event:"))
+			.map((line) => line.slice("event:".length).trim());
+		assert.deepStrictEqual(events, [
+			"message_start",
+			"content_block_start",
+			"content_block_delta",
+			"content_block_stop",
+			"message_delta",
+			"message_stop",
+		]);
+	});
+
+	await t.test(
+		"should report the stop sequence that ended the message",
+		async () => {
+			// Arrange
+			const chunks = [
+				'data: {"choices":[{"delta":{"content":"Hello"}}]}\n\n',
+				'data: {"choices":[{"delta":{"content":" wor"}}]}\n\n',
+				'data: {"choices":[{"delta":{"content":"ld E"}}]}\n\n',
+				'data: {"choices":[{"delta":{"content":"ND more"}}]}\n\n',
+			];
+			const { router, llamaProxyService } = mockRouter(
+				"bob",
+				["model-a"],
+				"/v1",
+				chunks,
+			);
+			const app = router.getApp();
+			const request = {
+				model: "model-a",
+				messages: [{ role: "user", content: "Hello" }],
+				max_tokens: 16,
+				stop_sequences: ["END"],
+			};
+
+			// Act
+			const res = await app.request(
+				"/v1/messages",
+				{ method: "POST", body: JSON.stringify(request) },
+				givenBindings,
+			);
+
+			// Assert
+			const [, , body] =
+				llamaProxyService.chatCompletion.mock.calls[0].arguments;
+			assert.strictEqual(JSON.parse(body).stop, undefined);
+			assert.partialDeepStrictEqual(await res.json(), {
+				content: [{ type: "text", text: "Hello world " }],
+				stop_reason: "stop_sequence",
+				stop_sequence: "END",
+			});
+		},
+	);
+
+	await t.test("should answer errors in the Anthropic shape", async () => {
+		// Arrange
+		const { router, llamaProxyService } = mockRouter("bob", ["model-a"]);
+		llamaProxyService.chatCompletion.mock.mockImplementation(async () => {
+			throw new InsufficientMemoryError("model-a does not fit");
+		});
+		const app = router.getApp();
+		const request = {
+			model: "model-a",
+			messages: [{ role: "user", content: "Hello" }],
+			max_tokens: 16,
+			stream: true,
+		};
+
+		// Act
+		const res = await app.request(
+			"/v1/messages",
+			{ method: "POST", body: JSON.stringify(request) },
+			givenBindings,
+		);
+
+		// Assert
+		assert.strictEqual(res.status, 503);
+		assert.deepStrictEqual(await res.json(), {
+			type: "error",
+			error: { type: "overloaded_error", message: "model-a does not fit" },
+		});
+	});
+
+	await t.test(
+		"should answer a rejected request before streaming a cold model",
+		async () => {
+			// Arrange
+			const { router, llamaProxyService } = mockRouter("bob", [
+				"model-a",
+				"model-b",
+			]);
+			llamaProxyService.checkRequest.mock.mockImplementation(() => {
+				throw new TooManyRequestsError("model-b is already serving 1 requests");
+			});
+			const app = router.getApp();
+			const request = {
+				model: "model-b",
+				stream: true,
+				messages: [{ role: "user", content: "Hello" }],
+			};
+
+			// Act
+			const res = await app.request(
+				"/v1/chat/completions",
+				{ method: "POST", body: JSON.stringify(request) },
+				givenBindings,
+			);
+
+			// Assert
+			assert.strictEqual(res.status, 429);
+			const body = await res.json();
+			assert.strictEqual(body.error.code, "too_many_requests");
+			assert.strictEqual(llamaProxyService.chatCompletion.mock.callCount(), 0);
+		},
+	);
+
+	await t.test(
+		"should send an error event when the stream fails midway",
+		async () => {
+			// Arrange
+			const { router, llamaProxyService } = mockRouter("bob", ["model-a"]);
+			llamaProxyService.chatCompletion.mock.mockImplementation(
+				async () =>
+					new ReadableStream({
+						start(controller) {
+							controller.enqueue(
+								new TextEncoder().encode(
+									'data: {"choices":[{"delta":{"content":"Hi"}}]}\n\n',
+								),
+							);
+							controller.error(new StreamIdleTimeoutError("model-a stalled"));
+						},
+					}),
+			);
+			const app = router.getApp();
+			const request = {
+				model: "model-a",
+				messages: [{ role: "user", content: "Hello" }],
+				max_tokens: 16,
+				stream: true,
+			};
+
+			// Act
+			const res = await app.request(
+				"/v1/messages",
+				{ method: "POST", body: JSON.stringify(request) },
+				givenBindings,
+			);
+
+			// Assert
+			const lines = (await res.text()).split("\n");
+			const events = lines
+				.filter((line) => line.startsWith("event:"))
+				.map((line) => line.slice("event:".length).trim());
+			assert.deepStrictEqual(events, [
+				"message_start",
+				"content_block_start",
+				"content_block_delta",
+				"error",
+			]);
+			const data = lines.filter((line) => line.startsWith("data:")).at(-1);
+			assert.deepStrictEqual(JSON.parse(data?.slice("data:".length) ?? ""), {
+				type: "error",
+				error: { type: "timeout_error", message: "model-a stalled" },
+			});
+		},
+	);
+});
+
+test("POST /v1/chat/completions", async (t) => {
+	await t.test("should forward the stream up to [DONE] as is", async () => {
+		// Arrange
+		const chunks = [
+			'data: {"choices":[{"delta":{"content":"Hi"}}]}\n\n',
+			'data: {"choices":[{"delta":{},"finish_reason":"stop"}]}\n\n',
+			"data: [DONE]\n\n",
+		];
+		const { router } = mockRouter("bob", ["model-a"], "/v1", chunks);
+		const app = router.getApp();
+		const request = {
+			model: "model-a",
+			stream: true,
+			messages: [{ role: "user", content: "Hello" }],
+		};
+
+		// Act
+		const res = await app.request(
+			"/v1/chat/completions",
+			{ method: "POST", body: JSON.stringify(request) },
+			givenBindings,
+		);
+
+		// Assert
+		assert.strictEqual(res.headers.get("Content-Type"), "text/event-stream");
+		assert.strictEqual(await res.text(), chunks.join(""));
+	});
+
+	await t.test(
+		"should send an error event when a loading model fails",
+		async () => {
+			// Arrange
+			const { router, llamaProxyService } = mockRouter("bob", [
+				"model-a",
+				"model-b",
+			]);
+			llamaProxyService.chatCompletion.mock.mockImplementation(async () => {
+				throw new InsufficientMemoryError("model-b does not fit");
+			});
+			const app = router.getApp();
+			const request = {
+				model: "model-b",
+				stream: true,
+				messages: [{ role: "user", content: "Hello" }],
+			};
+
+			// Act
+			const res = await app.request(
+				"/v1/chat/completions",
+				{ method: "POST", body: JSON.stringify(request) },
+				givenBindings,
+			);
+
+			// Assert
+			assert.strictEqual(res.status, 200);
+			assert.strictEqual(
+				await res.text(),
+				`data: ${JSON.stringify({
+					error: {
+						message: "model-b does not fit",
+						type: "server_error",
+						code: "insufficient_memory",
+					},
+				})}\n\n`,
+			);
+		},
+	);
+
+	await t.test(
+		"should send an error event when the stream fails midway",
+		async () => {
+			// Arrange
+			const { router, llamaProxyService } = mockRouter("bob", ["model-a"]);
+			llamaProxyService.chatCompletion.mock.mockImplementation(
+				async () =>
+					new ReadableStream({
+						start(controller) {
+							controller.enqueue(new TextEncoder().encode("data: {}\n\n"));
+							controller.error(new StreamIdleTimeoutError("model-a stalled"));
+						},
+					}),
+			);
+			const app = router.getApp();
+			const request = {
+				model: "model-a",
+				stream: true,
+				messages: [{ role: "user", content: "Hello" }],
+			};
+
+			// Act
+			const res = await app.request(
+				"/v1/chat/completions",
+				{ method: "POST", body: JSON.stringify(request) },
+				givenBindings,
+			);
+
+			// Assert
+			assert.strictEqual(
+				await res.text(),
+				`data: {}\n\ndata: ${JSON.stringify({
+					error: {
+						message: "model-a stalled",
+						type: "server_error",
+						code: "stream_idle_timeout",
+					},
+				})}\n\n`,
+			);
+		},
+	);
+});
+
+test("compression", async (t) => {
+	await t.test("should compress a response when accepted", async () => {
+		// Arrange
+		const models = Array.from({ length: 50 }, (_, index) => `model-${index}`);
+		const { router } = mockRouter("bob", models);
+		const app = router.getApp();
+
+		// Act
+		const res = await app.request("/v1/models", {
+			headers: { "Accept-Encoding": "gzip" },
+		});
+
+		// Assert
+		assert.strictEqual(res.headers.get("Content-Encoding"), "gzip");
+		const body = gunzipSync(Buffer.from(await res.arrayBuffer()));
+		assert.strictEqual(JSON.parse(body.toString()).data.length, 50);
+	});
+
+	await t.test("should never compress a stream", async () => {
+		// Arrange
+		const chunks = ["data: [DONE]\n\n"];
+		const { router } = mockRouter("bob", ["model-a"], "/v1", chunks);
+		const app = router.getApp();
+		const request = { model: "model-a", stream: true, messages: [] };
+
+		// Act
+		const res = await app.request(
+			"/v1/chat/completions",
+			{
+				method: "POST",
+				headers: { "Accept-Encoding": "gzip" },
+				body: JSON.stringify(request),
+			},
+			givenBindings,
+		);
+
+		// Assert
+		assert.strictEqual(res.headers.get("Content-Encoding"), null);
+		assert.strictEqual(await res.text(), chunks.join(""));
+	});
+});
+
+test("request body limit", async (t) => {
+	await t.test("should reject a body larger than the limit", async () => {
+		// Arrange
+		const { router, llamaProxyService } = mockRouter("bob", ["model-a"]);
+		const app = router.getApp();
+		const request = {
+			model: "model-a",
+			stream: false,
+			messages: [{ role: "user", content: "x".repeat(2048) }],
+		};
+
+		// Act
+		const res = await app.request(
+			"/v1/chat/completions",
+			{ method: "POST", body: JSON.stringify(request) },
+			givenBindings,
+		);
+
+		// Assert
+		assert.strictEqual(res.status, 413);
+		const body = await res.json();
+		assert.strictEqual(body.error.code, "request_too_large");
+		assert.strictEqual(llamaProxyService.chatCompletion.mock.callCount(), 0);
+	});
+});
+
+test("request timeout", async (t) => {
+	await t.test("should answer 504 to a request taking too long", async () => {
+		// Arrange
+		mock.timers.enable({ apis: ["setTimeout"] });
+		t.after(() => mock.timers.reset());
+		const { router, llamaProxyService, configRepository } = mockRouter("bob", [
+			"model-a",
+		]);
+		const app = router.getApp();
+		new Server(app, configRepository as unknown as ConfigRepository);
+		configRepository.getServerConfiguration.mock.mockImplementation(() => ({
+			maxRequestBodyBytes: 1024,
+			requestTimeout: 5,
+		}));
+		llamaProxyService.chatCompletion.mock.mockImplementation(
+			() => new Promise<ReadableStream>(() => {}),
+		);
+		const request = { model: "model-a", stream: false, messages: [] };
+
+		// Act
+		const pending = app.request(
+			"/v1/chat/completions",
+			{ method: "POST", body: JSON.stringify(request) },
+			givenBindings,
+		);
+		while (llamaProxyService.chatCompletion.mock.callCount() === 0) {
+			await new Promise((resolve) => setImmediate(resolve));
+		}
+		mock.timers.tick(5_000);
+		const res = await pending;
+
+		// Assert
+		assert.strictEqual(res.status, 504);
+		const body = await res.json();
+		assert.strictEqual(body.error.code, "request_timeout");
+	});
+});
+
+test("WebSocket", async (t) => {
+	/**
+	 * Serve the WebSocket routes of a mocked router on a free port
+	 */
+	async function listenForUpgrades(router: Router, configRepository: unknown) {
+		const server = new Server(
+			router.getApp(),
+			configRepository as ConfigRepository,
+			router.getWebSocketRoutes(),
+		);
+		const httpServer = createServer();
+		httpServer.on("upgrade", (request, socket, head) =>
+			server.handleUpgrade(request, socket, head),
+		);
+		httpServer.listen(0, "127.0.0.1");
+		await once(httpServer, "listening");
+		t.after(() => httpServer.close());
+		return (httpServer.address() as AddressInfo).port;
+	}
+
+	/**
+	 * Open a WebSocket by hand to send a frame no client would, then read the
+	 * code of the close frame answering it
+	 */
+	async function sendRawFrame(port: number, frame: Buffer): Promise<number> {
+		const socket = connect(port, "127.0.0.1");
+		t.after(() => socket.destroy());
+		socket.write(
+			[
+				"GET /v1/chat/completions/ws HTTP/1.1",
+				"Host: 127.0.0.1",
+				"Connection: Upgrade",
+				"Upgrade: websocket",
+				"Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==",
+				"Sec-WebSocket-Version: 13",
+				"",
+				"",
+			].join("\r\n"),
+		);
+		socket.write(frame);
+		let received = Buffer.alloc(0);
+		for await (const chunk of socket) {
+			received = Buffer.concat([received, chunk]);
+			const headerEnd = received.indexOf("\r\n\r\n");
+			const closeFrame = received.subarray(headerEnd + 4);
+			if (headerEnd !== -1 && closeFrame.length >= 4) {
+				assert.strictEqual(closeFrame[0], 0x88, "expected a close frame");
+				return closeFrame.readUInt16BE(2);
+			}
+		}
+		throw new Error("Connection closed without a close frame");
+	}
+
+	await t.test("should relay the stream up to [DONE]", async () => {
+		// Arrange
+		const chunks = [
+			'data: {"choices":[{"delta":{"content":"Hi"}}]}\n\n',
+			"data: [DONE]\n\n",
+		];
+		const { router, configRepository, llamaProxyService } = mockRouter(
+			"bob",
+			["model-a"],
+			"/v1",
+			chunks,
+		);
+		const port = await listenForUpgrades(router, configRepository);
+		const request = {
+			model: "model-a",
+			messages: [{ role: "user", content: "Hello" }],
+		};
+
+		// Act
+		const socket = new WebSocket(
+			`ws://127.0.0.1:${port}/v1/chat/completions/ws`,
+		);
+		const messages: string[] = [];
+		socket.addEventListener("open", () => socket.send(JSON.stringify(request)));
+		socket.addEventListener("message", (event) => messages.push(event.data));
+		const [closeEvent] = await once(socket, "close");
+
+		// Assert
+		assert.deepStrictEqual(messages, [
+			'{"choices":[{"delta":{"content":"Hi"}}]}',
+			"[DONE]",
+		]);
+		assert.strictEqual(closeEvent.code, 1000);
+		const [, , body] =
+			llamaProxyService.chatCompletion.mock.calls[0].arguments;
+		assert.strictEqual(JSON.parse(body).stream, true);
+	});
+
+	await t.test("should send the error of a failed request", async () => {
+		// Arrange
+		const { router, configRepository, llamaProxyService } = mockRouter("bob", [
+			"model-a",
+		]);
+		llamaProxyService.chatCompletion.mock.mockImplementation(async () => {
+			throw new InsufficientMemoryError("model-a does not fit");
+		});
+		const port = await listenForUpgrades(router, configRepository);
+
+		// Act
+		const socket = new WebSocket(
+			`ws://127.0.0.1:${port}/v1/chat/completions/ws`,
+		);
+		const messages: string[] = [];
+		socket.addEventListener("open", () =>
+			socket.send(JSON.stringify({ model: "model-a", messages: [] })),
+		);
+		socket.addEventListener("message", (event) => messages.push(event.data));
+		const [closeEvent] = await once(socket, "close");
+
+		// Assert
+		assert.strictEqual(messages.length, 1);
+		assert.strictEqual(
+			JSON.parse(messages[0]).error.code,
+			"insufficient_memory",
+		);
+		assert.strictEqual(closeEvent.code, 1011);
+	});
+
+	await t.test("should close on an unmasked frame", async () => {
+		// Arrange
+		const { router, configRepository } = mockRouter("bob", ["model-a"]);
+		const port = await listenForUpgrades(router, configRepository);
+		// Text frame with "hi", which clients must mask
+		const frame = Buffer.from([0x81, 0x02, 0x68, 0x69]);
+
+		// Act
+		const code = await sendRawFrame(port, frame);
+
+		// Assert
+		assert.strictEqual(code, 1002);
+	});
+
+	await t.test("should close on an oversized control frame", async () => {
+		// Arrange
+		const { router, configRepository } = mockRouter("bob", ["model-a"]);
+		const port = await listenForUpgrades(router, configRepository);
+		// Masked ping of 126 B, over the 125 B allowed for control frames
+		const frame = Buffer.concat([
+			Buffer.from([0x89, 0x80 | 126, 0, 126, 0, 0, 0, 0]),
+			Buffer.alloc(126),
+		]);
+
+		// Act
+		const code = await sendRawFrame(port, frame);
+
+		// Assert
+		assert.strictEqual(code, 1002);
+	});
+
+	await t.test("should abort the completion on a client close", async () => {
+		// Arrange
+		const { router, configRepository, llamaProxyService } = mockRouter("bob", [
+			"model-a",
+		]);
+		llamaProxyService.chatCompletion.mock.mockImplementation(
+			async (_model: string, signal: AbortSignal) =>
+				new ReadableStream({
+					start(controller) {
+						controller.enqueue(
+							new TextEncoder().encode('data: {"choices":[]}\n\n'),
+						);
+						// Never ends unless aborted
+						signal.addEventListener("abort", () =>
+							controller.error(signal.reason),
+						);
+					},
+				}),
+		);
+		const port = await listenForUpgrades(router, configRepository);
+
+		// Act
+		const socket = new WebSocket(
+			`ws://127.0.0.1:${port}/v1/chat/completions/ws`,
+		);
+		socket.addEventListener("open", () =>
+			socket.send(JSON.stringify({ model: "model-a", messages: [] })),
+		);
+		socket.addEventListener("message", () => socket.close(), { once: true });
+		await once(socket, "close");
+		const [, signal] =
+			llamaProxyService.chatCompletion.mock.calls[0].arguments;
+		if (!signal.aborted) {
+			await once(signal, "abort");
+		}
+
+		// Assert
+		assert.ok(signal.aborted);
+	});
+
+	await t.test("should reject an upgrade without a valid key", async () => {
+		// Arrange
+		const { router, configRepository } = mockRouter(
+			"bob",
+			["model-a"],
+			"/v1",
+			[],
+			["secret"],
+		);
+		const port = await listenForUpgrades(router, configRepository);
+
+		// Act
+		const upgrade = httpRequest({
+			port,
+			host: "127.0.0.1",
+			path: "/v1/chat/completions/ws",
+			headers: {
+				Connection: "Upgrade",
+				Upgrade: "websocket",
+				"Sec-WebSocket-Key": "dGhlIHNhbXBsZSBub25jZQ==",
+				"Sec-WebSocket-Version": "13",
+			},
+		});
+		upgrade.end();
+		const [response] = await once(upgrade, "response");
+
+		// Assert
+		assert.strictEqual(response.statusCode, 401);
+		response.resume();
+	});
+});
+
+test("run", async (t) => {
+	/**
+	 * Run the server of a mocked router and wait for the address it logs
+	 */
+	async function runServer(serverConfiguration: object, shutdownTimeout = 0) {
+		const { router, llamaProxyService } = mockRouter("bob", ["model-a"]);
+		const server = new Server(router.getApp(), {
+			getServerConfiguration: mock.fn(() => serverConfiguration),
+			getShutdownTimeout: mock.fn(() => shutdownTimeout),
+			getApiKeys: mock.fn(() => new Set()),
+		} as unknown as ConfigRepository);
+		const listening = new Promise<string>((resolve) => {
+			t.mock.method(console, "log", (_message: string, address: string) =>
+				resolve(address),
+			);
+		});
+		server.run();
+		t.after(() => server.shutdown());
+		return { server, llamaProxyService, address: await listening };
+	}
+
+	await t.test("should serve over HTTPS with a certificate", async () => {
+		// Arrange
+		const certPath = fileURLToPath(
+			new URL("fixtures/localhost.crt", import.meta.url),
+		);
+		const keyPath = fileURLToPath(
+			new URL("fixtures/localhost.key", import.meta.url),
+		);
+		const { address } = await runServer({
+			hostname: "127.0.0.1",
+			port: 0,
+			tls: { certPath, keyPath },
+		});
+
+		// Act
+		const request = httpsRequest(`${address}/v1/models`, {
+			ca: readFileSync(certPath),
+		});
+		request.end();
+		const [response] = await once(request, "response");
+
+		// Assert
+		assert.match(address, /^https:\/\//);
+		assert.strictEqual(response.statusCode, 200);
+		const body = JSON.parse(await text(response));
+		assert.strictEqual(body.data[0].id, "model-a");
+	});
+
+	await t.test("should serve over a Unix socket", async () => {
+		// Arrange
+		const directory = await mkdtemp(path.join(tmpdir(), "router-"));
+		t.after(() => rm(directory, { recursive: true, force: true }));
+		const { address } = await runServer({
+			socketPath: path.join(directory, "router.sock"),
+		});
+
+		// Act
+		const request = httpRequest({ socketPath: address, path: "/v1/models" });
+		request.end();
+		const [response] = await once(request, "response");
+
+		// Assert
+		assert.strictEqual(response.statusCode, 200);
+		const body = JSON.parse(await text(response));
+		assert.strictEqual(body.data[0].id, "model-a");
+	});
+
+	await t.test("should close the hanging connections on timeout", async (t) => {
+		// Arrange
+		const { server, llamaProxyService, address } = await runServer(
+			{ hostname: "127.0.0.1", port: 0 },
+			5,
+		);
+		const received = new Promise<void>((resolve) => {
+			llamaProxyService.chatCompletion.mock.mockImplementation(() => {
+				resolve();
+				return new Promise(() => {});
+			});
+		});
+		const request = httpRequest(`${address}/v1/chat/completions`, {
+			method: "POST",
+		});
+		request.end(JSON.stringify({ model: "model-a", messages: [] }));
+		const closed = once(request, "error");
+		await received;
+		t.mock.method(console, "warn", () => {});
+		mock.timers.enable({ apis: ["setTimeout"] });
+		t.after(() => mock.timers.reset());
+
+		// Act
+		const shutdown = server.shutdown();
+		mock.timers.tick(5_000);
+		await shutdown;
+
+		// Assert
+		const [error] = await closed;
+		assert.strictEqual((error as NodeJS.ErrnoException).code, "ECONNRESET");
+	});
 });
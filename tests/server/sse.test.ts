@@ -0,0 +1,37 @@
+import assert from "node:assert";
+import { test } from "node:test";
+import { readSseData } from "#src/server/sse.ts";
+
+test("readSseData", async (t) => {
+	await t.test(
+		"should decode a character split across two chunks",
+		async () => {
+			// Arrange
+			const bytes = new TextEncoder().encode('data: {"content":"é"}\n\n');
+			const split = bytes.indexOf(0xc3) + 1;
+			const body = ReadableStream.from([
+				bytes.slice(0, split),
+				bytes.slice(split),
+			]);
+
+			// Act
+			const data = await Array.fromAsync(readSseData(body));
+
+			// Assert
+			assert.deepStrictEqual(data, ['{"content":"é"}']);
+		},
+	);
+
+	await t.test("should read a last event without a newline", async () => {
+		// Arrange
+		const body = ReadableStream.from([
+			new TextEncoder().encode("data: 1\n\ndata: [DONE]"),
+		]);
+
+		// Act
+		const data = await Array.fromAsync(readSseData(body));
+
+		// Assert
+		assert.deepStrictEqual(data, ["1", "[DONE]"]);
+	});
+});
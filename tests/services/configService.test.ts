@@ -0,0 +1,66 @@
+import assert from "node:assert";
+import { mock, test } from "node:test";
+import type { ConfigRepository } from "#src/repositories/configRepository.ts";
+import { ConfigService } from "#src/services/configService.ts";
+
+function mockConfigService() {
+	const configRepository = {
+		getConfig: mock.fn(() => ({
+			loadMode: "block",
+			auth: { apiKeys: ["secret"], apiKeysFile: "/etc/router/keys" },
+			models: {},
+		})),
+		reloadFromJson: mock.fn(async (_json: unknown) => {}),
+	};
+	const configService = new ConfigService(
+		configRepository as unknown as ConfigRepository,
+	);
+	return { configService, configRepository };
+}
+
+test("getConfig", async (t) => {
+	await t.test("should not return the API keys", () => {
+		// Arrange
+		const { configService } = mockConfigService();
+
+		// Act
+		const config = configService.getConfig();
+
+		// Assert
+		assert.deepStrictEqual(config, { loadMode: "block", models: {} });
+		assert.doesNotMatch(JSON.stringify(config), /secret/);
+	});
+});
+
+test("reloadConfig", async (t) => {
+	await t.test("should reject a configuration with auth", async () => {
+		// Arrange
+		const { configService, configRepository } = mockConfigService();
+
+		// Act
+		const reload = configService.reloadConfig({
+			models: {},
+			auth: { apiKeys: ["mine"] },
+		});
+
+		// Assert
+		await assert.rejects(reload, /auth cannot be changed/);
+		assert.strictEqual(configRepository.reloadFromJson.mock.callCount(), 0);
+	});
+
+	await t.test("should keep the current auth", async () => {
+		// Arrange
+		const { configService, configRepository } = mockConfigService();
+
+		// Act
+		await configService.reloadConfig({ loadMode: "retry", models: {} });
+
+		// Assert
+		const [json] = configRepository.reloadFromJson.mock.calls[0].arguments;
+		assert.deepStrictEqual(json, {
+			loadMode: "retry",
+			models: {},
+			auth: { apiKeys: ["secret"], apiKeysFile: "/etc/router/keys" },
+		});
+	});
+});
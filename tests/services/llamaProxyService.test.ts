@@ -0,0 +1,954 @@
+import assert from "node:assert";
+import { mock, test } from "node:test";
+import { setImmediate } from "node:timers/promises";
+import type {
+	ConfigRepository,
+	ModelConfiguration,
+} from "#src/repositories/configRepository.ts";
+import type {
+	LlamaServerHandle,
+	LlamaServerRuntime,
+} from "#src/repositories/llamaServerRepository.ts";
+import {
+	InsufficientMemoryError,
+	LlamaProxyService,
+	ModelLoadingError,
+	NotSupportedError,
+	ResponseTooLargeError,
+	selectModelsToEvict,
+	ServerBusyError,
+	StreamIdleTimeoutError,
+	TooManyRequestsError,
+	UpstreamError,
+} from "#src/services/llamaProxyService.ts";
+import type { ModelFitService } from "#src/services/modelFitService.ts";
+import { UsageService } from "#src/services/usageService.ts";
+
+function givenModelConfiguration(
+	port: number,
+	overrides: Partial<ModelConfiguration> = {},
+) {
+	return {
+		modelFilePath: `/models/model-${port}.gguf`,
+		unloadable: true,
+		task: "chat",
+		maxResponseBytes: null,
+		common: { contextSize: 4096 },
+		network: { host: "127.0.0.1", port },
+		...overrides,
+	} as unknown as ModelConfiguration;
+}
+
+function mockProxyService(
+	models: string[],
+	overrides: Partial<ModelConfiguration> = {},
+) {
+	const modelConfigs = new Map(
+		models.map((name, index) => [
+			name,
+			givenModelConfiguration(8081 + index, overrides),
+		]),
+	);
+	let nextPid = 1;
+
+	const configRepository = {
+		getAvailableModelNames: mock.fn(() => models),
+		getModelConfiguration: mock.fn((name: string) => modelConfigs.get(name)),
+		getConcurrentModels: mock.fn(() => 0),
+		getMaxConcurrentRequests: mock.fn((): number | undefined => undefined),
+		getLoadMode: mock.fn(() => "block"),
+		getModelUnloadDuration: mock.fn(() => 30),
+		getStartupTimeout: mock.fn(() => 300),
+		getStartupLogLines: mock.fn(() => 20),
+		getStreamIdleTimeout: mock.fn(() => 0),
+	};
+	const llamaServerRepository = {
+		start: mock.fn(async () => ({ pid: nextPid++ })),
+		stop: mock.fn(async (_pid: number) => {}),
+		onProcessCrash: mock.fn(),
+		adopt: mock.fn(
+			async (_opts: ModelConfiguration): Promise<LlamaServerHandle | null> =>
+				null,
+		),
+	} satisfies LlamaServerRuntime;
+	const modelFitService = {
+		reserveIfFits: mock.fn(async (_modelName: string) => ({
+			fits: true,
+			requiredVramBytes: 0,
+			freeVramBytes: 0,
+			message: "",
+		})),
+		releaseReservation: mock.fn(),
+		sharesDevice: mock.fn((_modelName: string, _otherName: string) => true),
+		estimateRequiredVram: mock.fn(async (_modelName: string) => 0),
+		getModelMetadata: mock.fn(async () => null),
+		resolveContextSize: mock.fn(async (_modelName: string) => 8192),
+	};
+
+	const usageService = new UsageService();
+
+	const llamaProxyService = new LlamaProxyService(
+		configRepository as unknown as ConfigRepository,
+		llamaServerRepository,
+		modelFitService as unknown as ModelFitService,
+		usageService,
+	);
+	return {
+		llamaProxyService,
+		configRepository,
+		llamaServerRepository,
+		modelFitService,
+		usageService,
+	};
+}
+
+test("chatCompletion", async (t) => {
+	t.beforeEach(() => {
+		mock.timers.enable({ apis: ["setTimeout"] });
+		mock.method(globalThis, "fetch", async () => new Response("{}"));
+	});
+	t.afterEach(() => {
+		mock.timers.reset();
+		mock.restoreAll();
+	});
+
+	await t.test(
+		"should start a cold model once for concurrent requests",
+		async () => {
+			// Arrange
+			const { llamaProxyService, llamaServerRepository } = mockProxyService([
+				"model-a",
+			]);
+
+			// Act
+			await Promise.all(
+				Array.from({ length: 5 }, () =>
+					llamaProxyService.chatCompletion(
+						"model-a",
+						new AbortController().signal,
+						"{}",
+					),
+				),
+			);
+
+			// Assert
+			assert.strictEqual(
+				llamaServerRepository.start.mock.callCount(),
+				1,
+				"model should be started once",
+			);
+		},
+	);
+
+	await t.test(
+		"should serve a warm model while another one is loading",
+		async () => {
+			// Arrange
+			const { llamaProxyService, llamaServerRepository } = mockProxyService([
+				"model-a",
+				"model-b",
+			]);
+			await llamaProxyService.chatCompletion(
+				"model-a",
+				new AbortController().signal,
+				"{}",
+			);
+			const load = Promise.withResolvers<{ pid: number }>();
+			llamaServerRepository.start.mock.mockImplementationOnce(
+				() => load.promise,
+			);
+			let coldSettled = false;
+			const cold = Promise.all(
+				Array.from({ length: 3 }, () =>
+					llamaProxyService.chatCompletion(
+						"model-b",
+						new AbortController().signal,
+						"{}",
+					),
+				),
+			).finally(() => {
+				coldSettled = true;
+			});
+
+			// Act
+			await llamaProxyService.chatCompletion(
+				"model-a",
+				new AbortController().signal,
+				"{}",
+			);
+
+			// Assert
+			assert.strictEqual(coldSettled, false, "model-b should still load");
+			load.resolve({ pid: 99 });
+			await cold;
+			assert.strictEqual(llamaServerRepository.start.mock.callCount(), 2);
+		},
+	);
+
+	await t.test(
+		"should ask to retry and load in the background in retry mode",
+		async () => {
+			// Arrange
+			const { llamaProxyService, configRepository, llamaServerRepository } =
+				mockProxyService(["model-a"]);
+			configRepository.getLoadMode.mock.mockImplementation(() => "retry");
+
+			// Act
+			const cold = llamaProxyService.chatCompletion(
+				"model-a",
+				new AbortController().signal,
+				"{}",
+			);
+
+			// Assert
+			await assert.rejects(cold, ModelLoadingError);
+			await setImmediate();
+			const warm = await llamaProxyService.chatCompletion(
+				"model-a",
+				new AbortController().signal,
+				"{}",
+			);
+			assert.ok(warm);
+			assert.strictEqual(llamaServerRepository.start.mock.callCount(), 1);
+		},
+	);
+
+	await t.test(
+		"should return the error of a failed load until the config changes",
+		async () => {
+			// Arrange
+			const { llamaProxyService, configRepository, llamaServerRepository } =
+				mockProxyService(["model-a"]);
+			configRepository.getLoadMode.mock.mockImplementation(() => "retry");
+			const startError = new Error("model-a failed to start");
+			llamaServerRepository.start.mock.mockImplementation(async () => {
+				throw startError;
+			});
+			const request = () =>
+				llamaProxyService.chatCompletion(
+					"model-a",
+					new AbortController().signal,
+					"{}",
+				);
+
+			// Act
+			await assert.rejects(request(), ModelLoadingError);
+			await setImmediate();
+			const failed = request();
+
+			// Assert
+			await assert.rejects(failed, startError);
+			assert.strictEqual(llamaServerRepository.start.mock.callCount(), 1);
+			configRepository.getModelConfiguration.mock.mockImplementation(() =>
+				givenModelConfiguration(8081),
+			);
+			await assert.rejects(request(), ModelLoadingError);
+			assert.strictEqual(llamaServerRepository.start.mock.callCount(), 2);
+		},
+	);
+
+	await t.test("should load again after the error cooldown", async () => {
+		// Arrange
+		mock.timers.reset();
+		mock.timers.enable({ apis: ["setTimeout", "Date"] });
+		const { llamaProxyService, configRepository, llamaServerRepository } =
+			mockProxyService(["model-a"]);
+		configRepository.getLoadMode.mock.mockImplementation(() => "retry");
+		const startError = new Error("model-a failed to start");
+		llamaServerRepository.start.mock.mockImplementation(async () => {
+			throw startError;
+		});
+		const request = () =>
+			llamaProxyService.chatCompletion(
+				"model-a",
+				new AbortController().signal,
+				"{}",
+			);
+		await assert.rejects(request(), ModelLoadingError);
+		await setImmediate();
+		await assert.rejects(request(), startError);
+
+		// Act
+		mock.timers.tick(30_000);
+		const retried = request();
+
+		// Assert
+		await assert.rejects(retried, ModelLoadingError);
+		assert.strictEqual(llamaServerRepository.start.mock.callCount(), 2);
+	});
+
+	await t.test("should reject a model launched for another task", async () => {
+		// Arrange
+		const { llamaProxyService, llamaServerRepository } = mockProxyService(
+			["model-a"],
+			{ task: "embedding" },
+		);
+
+		// Act
+		const response = llamaProxyService.chatCompletion(
+			"model-a",
+			new AbortController().signal,
+			"{}",
+		);
+
+		// Assert
+		await assert.rejects(response, NotSupportedError);
+		assert.strictEqual(llamaServerRepository.start.mock.callCount(), 0);
+	});
+
+	await t.test(
+		"should reject a response whose length exceeds the limit",
+		async () => {
+			// Arrange
+			const { llamaProxyService } = mockProxyService(["model-a"], {
+				maxResponseBytes: 4,
+			});
+			mock.method(
+				globalThis,
+				"fetch",
+				async () =>
+					new Response("0123456789", {
+						headers: { "Content-Length": "10" },
+					}),
+			);
+
+			// Act
+			const response = llamaProxyService.chatCompletion(
+				"model-a",
+				new AbortController().signal,
+				"{}",
+			);
+
+			// Assert
+			await assert.rejects(response, ResponseTooLargeError);
+		},
+	);
+
+	await t.test(
+		"should reject a chunked response exceeding the limit",
+		async () => {
+			// Arrange
+			const { llamaProxyService } = mockProxyService(["model-a"], {
+				maxResponseBytes: 4,
+			});
+			mock.method(globalThis, "fetch", async () => {
+				const body = ReadableStream.from([
+					new TextEncoder().encode("012"),
+					new TextEncoder().encode("345"),
+				]);
+				return new Response(body, {
+					headers: { "Content-Type": "application/json" },
+				});
+			});
+
+			// Act
+			const response = llamaProxyService.chatCompletion(
+				"model-a",
+				new AbortController().signal,
+				"{}",
+			);
+
+			// Assert
+			await assert.rejects(response, ResponseTooLargeError);
+		},
+	);
+
+	await t.test("should report the status of a failed response", async () => {
+		// Arrange
+		const { llamaProxyService } = mockProxyService(["model-a"]);
+		mock.method(
+			globalThis,
+			"fetch",
+			async () => new Response("context too long", { status: 400 }),
+		);
+
+		// Act
+		const response = llamaProxyService.chatCompletion(
+			"model-a",
+			new AbortController().signal,
+			"{}",
+		);
+
+		// Assert
+		await assert.rejects(response, { status: 400 });
+		await assert.rejects(response, UpstreamError);
+	});
+
+	await t.test("should record the usage of a streamed response", async () => {
+		// Arrange
+		const { llamaProxyService, usageService } = mockProxyService(["model-a"]);
+		const sse = [
+			'data: {"choices":[{"delta":{"content":"Hi"}}]}\n\n',
+			'data: {"choices":[],"usage":{"prompt_tokens":3,"completion_tokens":1}}\n\n',
+			"data: [DONE]\n\n",
+		].join("");
+		mock.method(
+			globalThis,
+			"fetch",
+			async () =>
+				new Response(sse, { headers: { "Content-Type": "text/event-stream" } }),
+		);
+
+		// Act
+		const response = await llamaProxyService.chatCompletion(
+			"model-a",
+			new AbortController().signal,
+			"{}",
+		);
+		const text = await new Response(response).text();
+
+		// Assert
+		assert.strictEqual(text, sse);
+		assert.deepStrictEqual(usageService.getUsage("model-a"), {
+			requests: 1,
+			promptTokens: 3,
+			completionTokens: 1,
+		});
+	});
+
+	await t.test("should abort a response that stalls", async () => {
+		// Arrange
+		const { llamaProxyService, configRepository } = mockProxyService([
+			"model-a",
+		]);
+		configRepository.getStreamIdleTimeout.mock.mockImplementation(() => 10);
+		mock.method(
+			globalThis,
+			"fetch",
+			async () => new Response(new ReadableStream()),
+		);
+		const response = await llamaProxyService.chatCompletion(
+			"model-a",
+			new AbortController().signal,
+			"{}",
+		);
+		assert.ok(response);
+
+		// Act
+		const chunks = Array.fromAsync(response);
+		await setImmediate();
+		mock.timers.tick(10_000);
+
+		// Assert
+		await assert.rejects(chunks, StreamIdleTimeoutError);
+	});
+
+	await t.test(
+		"should reject requests above the global limit until a response ends",
+		async () => {
+			// Arrange
+			const { llamaProxyService, configRepository } = mockProxyService([
+				"model-a",
+			]);
+			configRepository.getMaxConcurrentRequests.mock.mockImplementation(
+				() => 1,
+			);
+			mock.method(
+				globalThis,
+				"fetch",
+				async () => new Response(new ReadableStream()),
+			);
+			const signal = new AbortController().signal;
+			const first = await llamaProxyService.chatCompletion(
+				"model-a",
+				signal,
+				"{}",
+			);
+
+			// Act
+			const rejected = await llamaProxyService
+				.chatCompletion("model-a", signal, "{}")
+				.catch((error) => error);
+			await first?.cancel();
+			const accepted = llamaProxyService.chatCompletion(
+				"model-a",
+				signal,
+				"{}",
+			);
+
+			// Assert
+			assert.ok(rejected instanceof ServerBusyError);
+			assert.ok(await accepted);
+		},
+	);
+
+	await t.test(
+		"should reject a request above the limit of the model",
+		async () => {
+			// Arrange
+			const { llamaProxyService } = mockProxyService(["model-a"], {
+				maxConcurrent: 2,
+			});
+			mock.method(
+				globalThis,
+				"fetch",
+				async () => new Response(new ReadableStream()),
+			);
+			const signal = new AbortController().signal;
+			await llamaProxyService.chatCompletion("model-a", signal, "{}");
+			await llamaProxyService.chatCompletion("model-a", signal, "{}");
+
+			// Act
+			const response = llamaProxyService.chatCompletion(
+				"model-a",
+				signal,
+				"{}",
+			);
+
+			// Assert
+			await assert.rejects(response, TooManyRequestsError);
+		},
+	);
+
+	await t.test(
+		"should forward extra headers to the llama-server",
+		async () => {
+			// Arrange
+			const { llamaProxyService } = mockProxyService(["model-a"]);
+			const fetch = mock.method(
+				globalThis,
+				"fetch",
+				async (_url: string, _init: RequestInit) => new Response("{}"),
+			);
+
+			// Act
+			await llamaProxyService.chatCompletion(
+				"model-a",
+				new AbortController().signal,
+				"{}",
+				{ Authorization: "Bearer secret" },
+			);
+
+			// Assert
+			const [, init] = fetch.mock.calls[0].arguments;
+			assert.deepStrictEqual(init.headers, {
+				"Content-Type": "application/json",
+				Accept: "application/json",
+				Authorization: "Bearer secret",
+			});
+		},
+	);
+
+	await t.test(
+		"should terminate a streamed response exceeding the limit",
+		async () => {
+			// Arrange
+			const { llamaProxyService } = mockProxyService(["model-a"], {
+				maxResponseBytes: 4,
+			});
+			mock.method(globalThis, "fetch", async () => {
+				const body = ReadableStream.from([
+					new TextEncoder().encode("012"),
+					new TextEncoder().encode("345"),
+				]);
+				return new Response(body, {
+					headers: { "Content-Type": "text/event-stream" },
+				});
+			});
+
+			// Act
+			const response = await llamaProxyService.chatCompletion(
+				"model-a",
+				new AbortController().signal,
+				"{}",
+			);
+
+			// Assert
+			assert.ok(response);
+			await assert.rejects(Array.fromAsync(response), ResponseTooLargeError);
+		},
+	);
+});
+
+test("unloadAll", async (t) => {
+	t.beforeEach(() => {
+		mock.method(globalThis, "fetch", async () => new Response("{}"));
+	});
+	t.afterEach(() => {
+		mock.restoreAll();
+	});
+
+	await t.test("should stop every loaded model", async () => {
+		// Arrange
+		const { llamaProxyService, llamaServerRepository } = mockProxyService([
+			"model-a",
+			"model-b",
+		]);
+		const signal = new AbortController().signal;
+		await llamaProxyService.chatCompletion("model-a", signal, "{}");
+		await llamaProxyService.chatCompletion("model-b", signal, "{}");
+
+		// Act
+		await llamaProxyService.unloadAll();
+
+		// Assert
+		assert.strictEqual(llamaServerRepository.stop.mock.callCount(), 2);
+		assert.deepStrictEqual(llamaProxyService.getLoadedModels(), []);
+	});
+});
+
+test("adoptRunningModels", async (t) => {
+	t.beforeEach(() => {
+		mock.method(globalThis, "fetch", async () => new Response("{}"));
+	});
+	t.afterEach(() => {
+		mock.restoreAll();
+	});
+
+	await t.test("should serve an adopted model without a start", async () => {
+		// Arrange
+		const { llamaProxyService, llamaServerRepository } = mockProxyService([
+			"model-a",
+			"model-b",
+		]);
+		llamaServerRepository.adopt.mock.mockImplementation(
+			async (opts: ModelConfiguration) =>
+				opts.network.port === 8081 ? { pid: 42 } : null,
+		);
+
+		// Act
+		await llamaProxyService.adoptRunningModels();
+		await llamaProxyService.chatCompletion(
+			"model-a",
+			new AbortController().signal,
+			"{}",
+		);
+
+		// Assert
+		assert.deepStrictEqual(llamaProxyService.getLoadedModels(), ["model-a"]);
+		assert.notStrictEqual(llamaProxyService.getLastUsed("model-a"), undefined);
+		assert.strictEqual(llamaServerRepository.start.mock.callCount(), 0);
+		assert.strictEqual(
+			llamaServerRepository.onProcessCrash.mock.calls[0].arguments[0],
+			42,
+		);
+		await llamaProxyService.unloadAll();
+	});
+
+	await t.test(
+		"should look for an automatic slot count with the context of one slot",
+		async () => {
+			// Arrange
+			const overrides = {
+				common: { contextSize: 0, parallel: "auto" },
+			} as unknown as Partial<ModelConfiguration>;
+			const { llamaProxyService, llamaServerRepository } = mockProxyService(
+				["model-a"],
+				overrides,
+			);
+
+			// Act
+			await llamaProxyService.adoptRunningModels();
+
+			// Assert
+			const [opts] = llamaServerRepository.adopt.mock.calls[0].arguments;
+			assert.strictEqual(opts.common.parallel, "auto");
+			assert.strictEqual(opts.common.contextSize, 8192);
+		},
+	);
+});
+
+/**
+ * Back the fit checks of a mocked service with a GPU of `totalBytes` where
+ * each loaded model takes the VRAM given by `vramBytes`
+ */
+function mockVram(
+	{ llamaProxyService, modelFitService }: ReturnType<typeof mockProxyService>,
+	vramBytes: Record<string, number>,
+	totalBytes: number,
+) {
+	modelFitService.estimateRequiredVram.mock.mockImplementation(
+		async (modelName: string) => vramBytes[modelName],
+	);
+	modelFitService.reserveIfFits.mock.mockImplementation(
+		async (modelName: string) => {
+			const usedBytes = llamaProxyService
+				.getLoadedModels()
+				.reduce((sum, name) => sum + vramBytes[name], 0);
+			const freeVramBytes = totalBytes - usedBytes;
+			const requiredVramBytes = vramBytes[modelName];
+			return {
+				fits: requiredVramBytes <= freeVramBytes,
+				requiredVramBytes,
+				freeVramBytes,
+				message: "",
+			};
+		},
+	);
+}
+
+test("eviction", async (t) => {
+	const GiB = 1024 * 1024 * 1024;
+
+	async function completeChat(
+		llamaProxyService: LlamaProxyService,
+		modelName: string,
+	) {
+		const response = await llamaProxyService.chatCompletion(
+			modelName,
+			new AbortController().signal,
+			"{}",
+		);
+		// Models stay in use until their response is fully read
+		await Array.fromAsync(response ?? []);
+		mock.timers.tick(1_000);
+	}
+
+	t.beforeEach(() => {
+		mock.timers.enable({ apis: ["setTimeout", "Date"], now: 0 });
+		mock.method(globalThis, "fetch", async () => new Response("{}"));
+	});
+	t.afterEach(() => {
+		mock.timers.reset();
+		mock.restoreAll();
+	});
+
+	await t.test(
+		"should unload the least recently used model that frees enough",
+		async () => {
+			// Arrange
+			const harness = mockProxyService([
+				"model-a",
+				"model-b",
+				"model-c",
+				"model-d",
+			]);
+			const { llamaProxyService, llamaServerRepository } = harness;
+			mockVram(
+				harness,
+				{
+					"model-a": 4 * GiB,
+					"model-b": 2 * GiB,
+					"model-c": 2 * GiB,
+					"model-d": 2 * GiB,
+				},
+				8 * GiB,
+			);
+			for (const name of ["model-a", "model-b", "model-c", "model-a"]) {
+				await completeChat(llamaProxyService, name);
+			}
+
+			// Act
+			await completeChat(llamaProxyService, "model-d");
+
+			// Assert
+			assert.strictEqual(llamaServerRepository.stop.mock.callCount(), 1);
+			const loadedModels = llamaProxyService.getLoadedModels().sort();
+			assert.deepStrictEqual(loadedModels, ["model-a", "model-c", "model-d"]);
+		},
+	);
+
+	await t.test(
+		"should unload small models rather than an older large one",
+		async () => {
+			// Arrange
+			const harness = mockProxyService([
+				"model-a",
+				"model-b",
+				"model-c",
+				"model-d",
+			]);
+			const { llamaProxyService, llamaServerRepository } = harness;
+			mockVram(
+				harness,
+				{
+					"model-a": 6 * GiB,
+					"model-b": GiB,
+					"model-c": GiB,
+					"model-d": 2 * GiB,
+				},
+				8 * GiB,
+			);
+			for (const name of ["model-a", "model-b", "model-c"]) {
+				await completeChat(llamaProxyService, name);
+			}
+
+			// Act
+			await completeChat(llamaProxyService, "model-d");
+
+			// Assert
+			assert.strictEqual(llamaServerRepository.stop.mock.callCount(), 2);
+			const loadedModels = llamaProxyService.getLoadedModels().sort();
+			assert.deepStrictEqual(loadedModels, ["model-a", "model-d"]);
+		},
+	);
+
+	await t.test(
+		"should unload the selected models least recently used first",
+		async () => {
+			// Arrange
+			const harness = mockProxyService([
+				"model-a",
+				"model-b",
+				"model-c",
+				"model-d",
+				"model-e",
+			]);
+			const { llamaProxyService, llamaServerRepository } = harness;
+			mockVram(
+				harness,
+				{
+					"model-a": 5 * GiB,
+					"model-b": GiB,
+					"model-c": 2 * GiB,
+					"model-d": 4 * GiB,
+					"model-e": 3 * GiB,
+				},
+				12 * GiB,
+			);
+			// Loaded with the pids 1 to 4, then used in the order c, d, a, b
+			for (const name of ["model-a", "model-b", "model-c", "model-d"]) {
+				await completeChat(llamaProxyService, name);
+			}
+			await completeChat(llamaProxyService, "model-a");
+			await completeChat(llamaProxyService, "model-b");
+
+			// Act
+			await completeChat(llamaProxyService, "model-e");
+
+			// Assert
+			const stoppedPids = llamaServerRepository.stop.mock.calls.map(
+				(call) => call.arguments[0],
+			);
+			assert.deepStrictEqual(stoppedPids, [3, 2]);
+			const loadedModels = llamaProxyService.getLoadedModels().sort();
+			assert.deepStrictEqual(loadedModels, ["model-a", "model-d", "model-e"]);
+		},
+	);
+
+	await t.test(
+		"should never unload a model that is not unloadable",
+		async () => {
+			// Arrange
+			const harness = mockProxyService(["model-a", "model-b", "model-c"]);
+			const { llamaProxyService, configRepository } = harness;
+			configRepository.getModelConfiguration.mock.mockImplementation(
+				(name: string) =>
+					givenModelConfiguration(8081, {
+						unloadable: name !== "model-a",
+					}),
+			);
+			mockVram(
+				harness,
+				{ "model-a": 4 * GiB, "model-b": 4 * GiB, "model-c": 4 * GiB },
+				8 * GiB,
+			);
+			await completeChat(llamaProxyService, "model-a");
+			await completeChat(llamaProxyService, "model-b");
+
+			// Act
+			await completeChat(llamaProxyService, "model-c");
+
+			// Assert
+			const loadedModels = llamaProxyService.getLoadedModels().sort();
+			assert.deepStrictEqual(loadedModels, ["model-a", "model-c"]);
+		},
+	);
+
+	await t.test("should only unload models from the same GPU", async () => {
+		// Arrange
+		const harness = mockProxyService(["model-a", "model-b", "model-c"]);
+		const { llamaProxyService, modelFitService } = harness;
+		// model-a is pinned to another GPU than the others
+		modelFitService.sharesDevice.mock.mockImplementation(
+			(name: string, otherName: string) =>
+				(name === "model-a") === (otherName === "model-a"),
+		);
+		mockVram(
+			harness,
+			{ "model-a": 4 * GiB, "model-b": 4 * GiB, "model-c": 4 * GiB },
+			8 * GiB,
+		);
+		await completeChat(llamaProxyService, "model-a");
+		await completeChat(llamaProxyService, "model-b");
+
+		// Act
+		await completeChat(llamaProxyService, "model-c");
+
+		// Assert
+		const loadedModels = llamaProxyService.getLoadedModels().sort();
+		assert.deepStrictEqual(loadedModels, ["model-a", "model-c"]);
+	});
+
+	await t.test(
+		"should fail when unloading every model does not free enough",
+		async () => {
+			// Arrange
+			const harness = mockProxyService(["model-a", "model-b"]);
+			const { llamaProxyService, llamaServerRepository } = harness;
+			mockVram(harness, { "model-a": 4 * GiB, "model-b": 10 * GiB }, 8 * GiB);
+			await completeChat(llamaProxyService, "model-a");
+
+			// Act
+			const response = completeChat(llamaProxyService, "model-b");
+
+			// Assert
+			await assert.rejects(response, InsufficientMemoryError);
+			assert.strictEqual(llamaServerRepository.stop.mock.callCount(), 1);
+			assert.deepStrictEqual(llamaProxyService.getLoadedModels(), []);
+		},
+	);
+});
+
+test("selectModelsToEvict", async (t) => {
+	const GiB = 1024 * 1024 * 1024;
+
+	await t.test("should keep a large model when small ones suffice", () => {
+		// Arrange
+		const candidates = [
+			{ name: "large", vramBytes: 10 * GiB },
+			{ name: "small-a", vramBytes: GiB },
+			{ name: "small-b", vramBytes: GiB },
+		];
+
+		// Act
+		const selected = selectModelsToEvict(candidates, 2 * GiB);
+
+		// Assert
+		assert.deepStrictEqual(selected, ["small-a", "small-b"]);
+	});
+
+	await t.test("should unload fewer models when they free as much", () => {
+		// Arrange
+		const candidates = [
+			{ name: "small-a", vramBytes: GiB },
+			{ name: "medium", vramBytes: 2 * GiB },
+			{ name: "small-b", vramBytes: GiB },
+		];
+
+		// Act
+		const selected = selectModelsToEvict(candidates, 2 * GiB);
+
+		// Assert
+		assert.deepStrictEqual(selected, ["medium"]);
+	});
+
+	await t.test("should keep the LRU order of the selected models", () => {
+		// Arrange
+		const candidates = [
+			{ name: "model-a", vramBytes: 3 * GiB },
+			{ name: "model-b", vramBytes: 5 * GiB },
+			{ name: "model-c", vramBytes: 4 * GiB },
+		];
+
+		// Act
+		const selected = selectModelsToEvict(candidates, 7 * GiB);
+
+		// Assert
+		assert.deepStrictEqual(selected, ["model-a", "model-c"]);
+	});
+
+	await t.test("should select every model when none suffice", () => {
+		// Arrange
+		const candidates = [
+			{ name: "model-a", vramBytes: GiB },
+			{ name: "model-b", vramBytes: GiB },
+		];
+
+		// Act
+		const selected = selectModelsToEvict(candidates, 4 * GiB);
+
+		// Assert
+		assert.deepStrictEqual(selected, ["model-a", "model-b"]);
+	});
+});
@@ -0,0 +1,448 @@
+import assert from "node:assert";
+import { chmod, mkdtemp, writeFile } from "node:fs/promises";
+import { tmpdir } from "node:os";
+import path from "node:path";
+import { mock, test } from "node:test";
+import type { ConfigRepository } from "#src/repositories/configRepository.ts";
+import type {
+	EstimateParameters,
+	GgufParserRepository,
+} from "#src/repositories/ggufParserRepository.ts";
+import {
+	type RocmSmiQueryOptions,
+	RocmSmiRepository,
+} from "#src/repositories/rocmSmiRepository.ts";
+import {
+	computeParallelSlots,
+	ModelFitService,
+} from "#src/services/modelFitService.ts";
+
+const MiB = 1024 * 1024;
+
+function mockModelFitService(
+	requiredBytes: number,
+	freeBytes: number,
+	vramHeadroom: number = 0,
+	ggufParserRepository = {
+		getMemoryEstimate: mock.fn(async (_params: EstimateParameters) => ({
+			estimate: { items: [{ vrams: [{ nonuma: requiredBytes }] }] },
+		})),
+	},
+) {
+	const rocmSmiRepository = {
+		getVramInfo: mock.fn(async () => [
+			{ card: "card0", totalBytes: freeBytes, usedBytes: 0 },
+		]),
+		invalidate: mock.fn(),
+	};
+	const configRepository = {
+		getAvailableModelNames: mock.fn(() => ["model-a"]),
+		getGpuDevice: mock.fn(() => 0),
+		getVramHeadroom: mock.fn(() => vramHeadroom),
+		getModelConfiguration: mock.fn(() => ({
+			modelFilePath: "/models/model.gguf",
+			multimodalProjectorFilePath: null,
+			common: { contextSize: 4096, cacheType: "q8_0", batchSize: 4096 },
+		})),
+	};
+
+	return new ModelFitService(
+		ggufParserRepository as unknown as GgufParserRepository,
+		rocmSmiRepository as unknown as RocmSmiRepository,
+		configRepository as unknown as ConfigRepository,
+	);
+}
+
+/**
+ * Mock a service on two 16 GB GPUs, where the first one has 12 GB used
+ */
+function mockMultiGpuModelFitService(
+	gpuDevice: number,
+	modelGpuDevice?: number,
+) {
+	const rocmSmiRepository = {
+		getVramInfo: mock.fn(async (_opts: RocmSmiQueryOptions) => [
+			{ card: "card0", totalBytes: 16_000 * MiB, usedBytes: 12_000 * MiB },
+			{ card: "card1", totalBytes: 16_000 * MiB, usedBytes: 0 },
+		]),
+		invalidate: mock.fn(),
+	};
+	const modelFitService = new ModelFitService(
+		{
+			getMemoryEstimate: mock.fn(async () => ({
+				estimate: { items: [{ vrams: [{ nonuma: 6_000 * MiB }] }] },
+			})),
+		} as unknown as GgufParserRepository,
+		rocmSmiRepository as unknown as RocmSmiRepository,
+		{
+			getGpuDevice: mock.fn(() => gpuDevice),
+			getVramHeadroom: mock.fn(() => 0),
+			getModelConfiguration: mock.fn(() => ({
+				modelFilePath: "/models/model.gguf",
+				multimodalProjectorFilePath: null,
+				gpuDevice: modelGpuDevice,
+				common: { contextSize: 4096, cacheType: "q8_0" },
+			})),
+		} as unknown as ConfigRepository,
+	);
+	return { modelFitService, rocmSmiRepository };
+}
+
+test("computeParallelSlots", async (t) => {
+	await t.test("should fit as many slots as the budget allows", () => {
+		// Arrange
+		const weightBytes = 4_000 * MiB;
+		const perSlotBytes = 512 * MiB;
+		const budgetBytes = 8_000 * MiB;
+
+		// Act
+		const slots = computeParallelSlots(weightBytes, perSlotBytes, budgetBytes);
+
+		// Assert
+		assert.strictEqual(slots, 7);
+	});
+
+	await t.test("should keep at least one slot", () => {
+		// Act
+		const slots = computeParallelSlots(8_000 * MiB, 512 * MiB, 4_000 * MiB);
+
+		// Assert
+		assert.strictEqual(slots, 1);
+	});
+
+	await t.test("should not exceed the maximum slot count", () => {
+		// Act
+		const slots = computeParallelSlots(MiB, MiB, 1_000 * MiB);
+
+		// Assert
+		assert.strictEqual(slots, 32);
+	});
+});
+
+/**
+ * gguf-parser estimating 6 GB for one 4096 context, and 500 MB per other one
+ */
+function mockSlotEstimates() {
+	return {
+		getMemoryEstimate: mock.fn(async (params: EstimateParameters) => {
+			const contexts = (params.contextSize ?? 0) / 4096;
+			const nonuma = 5_500 * MiB + contexts * 500 * MiB;
+			return { estimate: { items: [{ vrams: [{ nonuma }] }] } };
+		}),
+	};
+}
+
+test("estimateParallelSlots", async (t) => {
+	await t.test("should estimate the cost of a slot once", async () => {
+		// Arrange
+		const ggufParserRepository = mockSlotEstimates();
+		const modelFitService = mockModelFitService(
+			0,
+			10_000 * MiB,
+			0,
+			ggufParserRepository,
+		);
+
+		// Act
+		const first = await modelFitService.estimateParallelSlots("model-a");
+		const second = await modelFitService.estimateParallelSlots("model-a");
+
+		// Assert
+		assert.strictEqual(first, 9);
+		assert.strictEqual(second, 9);
+		assert.strictEqual(
+			ggufParserRepository.getMemoryEstimate.mock.callCount(),
+			2,
+		);
+	});
+});
+
+test("reserveParallelSlots", async (t) => {
+	await t.test("should reserve the VRAM of every slot", async () => {
+		// Arrange
+		const modelFitService = mockModelFitService(
+			0,
+			10_000 * MiB,
+			0,
+			mockSlotEstimates(),
+		);
+		await modelFitService.reserveIfFits("model-a");
+
+		// Act
+		await modelFitService.reserveParallelSlots("model-a", 4);
+
+		// Assert
+		const result = await modelFitService.willModelFit("model-b");
+		assert.strictEqual(result.freeVramBytes, 2_500 * MiB);
+	});
+});
+
+test("willModelFit", async (t) => {
+	await t.test("should add the headroom to the estimate", async () => {
+		// Arrange
+		const modelFitService = mockModelFitService(
+			7_500 * MiB,
+			8_000 * MiB,
+			0.1,
+		);
+
+		// Act
+		const result = await modelFitService.willModelFit("model-a");
+
+		// Assert
+		assert.strictEqual(result.requiredVramBytes, 8_250 * MiB);
+		assert.strictEqual(result.fits, false);
+	});
+
+	await t.test("should estimate with the configured batch size", async () => {
+		// Arrange
+		const ggufParserRepository = {
+			getMemoryEstimate: mock.fn(async (_params: EstimateParameters) => ({
+				estimate: { items: [{ vrams: [{ nonuma: MiB }] }] },
+			})),
+		};
+		const modelFitService = mockModelFitService(
+			MiB,
+			8_000 * MiB,
+			0,
+			ggufParserRepository,
+		);
+
+		// Act
+		await modelFitService.willModelFit("model-a");
+
+		// Assert
+		const [params] =
+			ggufParserRepository.getMemoryEstimate.mock.calls[0].arguments;
+		assert.strictEqual(params.batchSize, 4096);
+		assert.strictEqual(params.ubatchSize, undefined);
+	});
+
+	await t.test("should measure the configured GPU", async () => {
+		// Arrange
+		const { modelFitService, rocmSmiRepository } =
+			mockMultiGpuModelFitService(1);
+
+		// Act
+		const result = await modelFitService.willModelFit("model-a");
+
+		// Assert
+		const [opts] = rocmSmiRepository.getVramInfo.mock.calls[0].arguments;
+		assert.strictEqual(opts.device, 1);
+		assert.strictEqual(result.freeVramBytes, 16_000 * MiB);
+		assert.strictEqual(result.fits, true);
+	});
+
+	await t.test("should measure the GPU the model is pinned to", async () => {
+		// Arrange
+		const { modelFitService, rocmSmiRepository } =
+			mockMultiGpuModelFitService(0, 1);
+
+		// Act
+		const result = await modelFitService.willModelFit("model-a");
+
+		// Assert
+		const [opts] = rocmSmiRepository.getVramInfo.mock.calls[0].arguments;
+		assert.strictEqual(opts.device, 1);
+		assert.strictEqual(result.fits, true);
+	});
+});
+
+test("willModelFit with every GPU", async (t) => {
+	await t.test("should sum the GPUs of an unpinned model", async () => {
+		// Arrange
+		const directory = await mkdtemp(path.join(tmpdir(), "rocm-smi-"));
+		const binaryPath = path.join(directory, "rocm-smi");
+		const outputPath = path.join(directory, "output.json");
+		await writeFile(
+			outputPath,
+			JSON.stringify({
+				card0: {
+					"VRAM Total Memory (B)": `${16_000 * MiB}`,
+					"VRAM Total Used Memory (B)": `${12_000 * MiB}`,
+				},
+				card1: {
+					"VRAM Total Memory (B)": `${16_000 * MiB}`,
+					"VRAM Total Used Memory (B)": "0",
+				},
+			}),
+		);
+		await writeFile(binaryPath, `#!/bin/sh\ncat ${outputPath}\n`);
+		await chmod(binaryPath, 0o755);
+		const modelFitService = new ModelFitService(
+			{
+				getMemoryEstimate: mock.fn(async () => ({
+					estimate: { items: [{ vrams: [{ nonuma: 18_000 * MiB }] }] },
+				})),
+			} as unknown as GgufParserRepository,
+			new RocmSmiRepository(binaryPath),
+			{
+				getGpuDevice: mock.fn(() => undefined),
+				getVramHeadroom: mock.fn(() => 0),
+				getModelConfiguration: mock.fn(() => ({
+					modelFilePath: "/models/model.gguf",
+					multimodalProjectorFilePath: null,
+					common: { contextSize: 4096, cacheType: "q8_0" },
+				})),
+			} as unknown as ConfigRepository,
+		);
+
+		// Act
+		const result = await modelFitService.willModelFit("model-a");
+
+		// Assert
+		assert.strictEqual(result.fits, true);
+		assert.strictEqual(result.freeVramBytes, 20_000 * MiB);
+		assert.match(result.details ?? "", /card0\+card1/);
+	});
+});
+
+test("getOversizedModels", async (t) => {
+	await t.test("should report a model larger than the whole GPU", async () => {
+		// Arrange
+		const modelFitService = mockModelFitService(12_000 * MiB, 8_000 * MiB);
+
+		// Act
+		const oversizedModels = await modelFitService.getOversizedModels();
+
+		// Assert
+		assert.deepStrictEqual(oversizedModels, [
+			{
+				modelName: "model-a",
+				requiredVramBytes: 12_000 * MiB,
+				totalVramBytes: 8_000 * MiB,
+			},
+		]);
+	});
+
+	await t.test("should not report a model that fits", async () => {
+		// Arrange
+		const modelFitService = mockModelFitService(6_000 * MiB, 8_000 * MiB);
+
+		// Act
+		const oversizedModels = await modelFitService.getOversizedModels();
+
+		// Assert
+		assert.deepStrictEqual(oversizedModels, []);
+	});
+});
+
+test("reserveIfFits", async (t) => {
+	await t.test(
+		"should not let concurrent loads share the same free VRAM",
+		async () => {
+			// Arrange
+			const modelFitService = mockModelFitService(6_000 * MiB, 10_000 * MiB);
+
+			// Act
+			const results = await Promise.all([
+				modelFitService.reserveIfFits("model-a"),
+				modelFitService.reserveIfFits("model-b"),
+			]);
+
+			// Assert
+			const fits = results.map((result) => result.fits);
+			assert.deepStrictEqual(fits, [true, false]);
+		},
+	);
+
+	await t.test(
+		"should free the VRAM once the reservation is released",
+		async () => {
+			// Arrange
+			const modelFitService = mockModelFitService(6_000 * MiB, 10_000 * MiB);
+			await modelFitService.reserveIfFits("model-a");
+
+			// Act
+			modelFitService.releaseReservation("model-a");
+
+			// Assert
+			const result = await modelFitService.willModelFit("model-b");
+			assert.strictEqual(result.fits, true);
+		},
+	);
+
+	await t.test(
+		"should measure a model loaded within the poll cache lifetime",
+		async () => {
+			// Arrange
+			const directory = await mkdtemp(path.join(tmpdir(), "rocm-smi-"));
+			const binaryPath = path.join(directory, "rocm-smi");
+			const outputPath = path.join(directory, "output.json");
+			const writeUsedBytes = (usedBytes: number) =>
+				writeFile(
+					outputPath,
+					JSON.stringify({
+						card0: {
+							"VRAM Total Memory (B)": `${10_000 * MiB}`,
+							"VRAM Total Used Memory (B)": `${usedBytes}`,
+						},
+					}),
+				);
+			await writeUsedBytes(0);
+			await writeFile(binaryPath, `#!/bin/sh\ncat ${outputPath}\n`);
+			await chmod(binaryPath, 0o755);
+			const modelFitService = new ModelFitService(
+				{
+					getMemoryEstimate: mock.fn(async () => ({
+						estimate: { items: [{ vrams: [{ nonuma: 6_000 * MiB }] }] },
+					})),
+				} as unknown as GgufParserRepository,
+				new RocmSmiRepository(binaryPath, 60_000),
+				{
+					getGpuDevice: mock.fn(() => 0),
+					getVramHeadroom: mock.fn(() => 0),
+					getModelConfiguration: mock.fn(() => ({
+						modelFilePath: "/models/model.gguf",
+						multimodalProjectorFilePath: null,
+						common: { contextSize: 4096, cacheType: "q8_0" },
+					})),
+				} as unknown as ConfigRepository,
+			);
+
+			// Act
+			const first = await modelFitService.reserveIfFits("model-a");
+			// model-a is listening and its VRAM is now allocated
+			await writeUsedBytes(6_000 * MiB);
+			modelFitService.releaseReservation("model-a");
+			const second = await modelFitService.reserveIfFits("model-b");
+
+			// Assert
+			assert.strictEqual(first.fits, true);
+			assert.strictEqual(second.fits, false);
+		},
+	);
+});
+
+test("getModelMetadata", async (t) => {
+	await t.test("should not parse a failing file again", async () => {
+		// Arrange
+		const ggufParserRepository = {
+			getMemoryEstimate: mock.fn(async (_params: EstimateParameters) => {
+				throw new Error("failed to open /models/model.gguf");
+			}),
+		};
+		const modelFitService = mockModelFitService(
+			0,
+			0,
+			0,
+			ggufParserRepository as unknown as Parameters<
+				typeof mockModelFitService
+			>[3],
+		);
+		const error = t.mock.method(console, "error", () => {});
+
+		// Act
+		const first = await modelFitService.getModelMetadata("model-a");
+		const second = await modelFitService.getModelMetadata("model-a");
+
+		// Assert
+		assert.strictEqual(first, null);
+		assert.strictEqual(second, null);
+		assert.strictEqual(
+			ggufParserRepository.getMemoryEstimate.mock.callCount(),
+			1,
+		);
+		assert.strictEqual(error.mock.callCount(), 1);
+	});
+});
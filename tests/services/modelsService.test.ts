@@ -0,0 +1,219 @@
+import assert from "node:assert";
+import { mkdtemp, rm, utimes, writeFile } from "node:fs/promises";
+import { tmpdir } from "node:os";
+import path from "node:path";
+import { mock, test } from "node:test";
+import type { ConfigRepository } from "#src/repositories/configRepository.ts";
+import type { MetadataPayload } from "#src/repositories/ggufParserRepository.ts";
+import type { ModelFitService } from "#src/services/modelFitService.ts";
+import { ModelsService } from "#src/services/modelsService.ts";
+
+function mockModelsService(
+	metadata: Record<string, MetadataPayload | null>,
+	modelFilePath = "/missing.gguf",
+) {
+	const configRepository = {
+		getAvailableModelNames: mock.fn(() => Object.keys(metadata)),
+		getModelOwnerName: mock.fn(() => "bob"),
+		getModelConfiguration: mock.fn((name: string) =>
+			name === "model-b"
+				? {
+						modelFilePath,
+						owner: "alice",
+						capabilities: { vision: true, tools: true, jsonMode: false },
+						tags: ["chat"],
+					}
+				: null,
+		),
+	};
+	const modelFitService = {
+		getModelMetadata: mock.fn(async (name: string) => metadata[name]),
+	};
+
+	const modelsService = new ModelsService(
+		configRepository as unknown as ConfigRepository,
+		modelFitService as unknown as ModelFitService,
+	);
+	return { modelsService };
+}
+
+test("getModels", async (t) => {
+	/**
+	 * Write an empty model file last modified at `mtimeSeconds`
+	 */
+	async function givenModelFile(mtimeSeconds: number) {
+		const directory = await mkdtemp(path.join(tmpdir(), "models-"));
+		t.after(() => rm(directory, { recursive: true, force: true }));
+		const modelFilePath = path.join(directory, "model-b.gguf");
+		await writeFile(modelFilePath, "");
+		await utimes(modelFilePath, mtimeSeconds, mtimeSeconds);
+		return modelFilePath;
+	}
+
+	await t.test("should use general.name as the display name", async () => {
+		// Arrange
+		const { modelsService } = mockModelsService({
+			"model-a": { name: "Granite 4.0 H 1b", architecture: "granitehybrid" },
+		});
+
+		// Act
+		const models = await modelsService.getModels();
+
+		// Assert
+		assert.strictEqual(models[0].displayName, "Granite 4.0 H 1b");
+	});
+
+	await t.test("should expose the architecture and quantization", async () => {
+		// Arrange
+		const { modelsService } = mockModelsService({
+			"model-a": { architecture: "qwen2", fileType: "Q4_K_M" },
+			"model-b": null,
+		});
+
+		// Act
+		const models = await modelsService.getModels();
+
+		// Assert
+		assert.deepStrictEqual(
+			models.map(({ architecture, quantization }) => [
+				architecture,
+				quantization,
+			]),
+			[
+				["qwen2", "Q4_K_M"],
+				[undefined, undefined],
+			],
+		);
+	});
+
+	await t.test(
+		"should fall back to the id when general.name is absent",
+		async () => {
+			// Arrange
+			const { modelsService } = mockModelsService({
+				"model-a": {},
+				"model-b": null,
+			});
+
+			// Act
+			const models = await modelsService.getModels();
+
+			// Assert
+			assert.deepStrictEqual(models.map((model) => model.displayName), [
+				"model-a",
+				"model-b",
+			]);
+		},
+	);
+
+	await t.test(
+		"should list models in the same order on every call",
+		async () => {
+			// Arrange
+			const { modelsService } = mockModelsService({
+				"model-c": null,
+				"model-a": null,
+				"model-b": null,
+			});
+
+			// Act
+			const first = await modelsService.getModels();
+			const second = await modelsService.getModels();
+
+			// Assert
+			const expectedIds = ["model-c", "model-a", "model-b"];
+			assert.deepStrictEqual(first.map((model) => model.id), expectedIds);
+			assert.deepStrictEqual(second.map((model) => model.id), expectedIds);
+		},
+	);
+
+	await t.test("should let a model override the owner", async () => {
+		// Arrange
+		const { modelsService } = mockModelsService({
+			"model-a": null,
+			"model-b": null,
+		});
+
+		// Act
+		const models = await modelsService.getModels();
+
+		// Assert
+		assert.deepStrictEqual(models.map((model) => model.owner), [
+			"bob",
+			"alice",
+		]);
+	});
+
+	await t.test(
+		"should return the same creation time on every call",
+		async (t) => {
+			// Arrange
+			const modelFilePath = await givenModelFile(1_700_000_000);
+			mock.timers.enable({ apis: ["Date"], now: Date.now() });
+			t.after(() => mock.timers.reset());
+			const { modelsService } = mockModelsService(
+				{ "model-a": null, "model-b": null },
+				modelFilePath,
+			);
+
+			// Act
+			const first = await modelsService.getModels();
+			mock.timers.tick(5_000);
+			const second = await modelsService.getModels();
+
+			// Assert
+			assert.strictEqual(first[1].created, 1_700_000_000);
+			assert.strictEqual(second[1].created, 1_700_000_000);
+			assert.strictEqual(first[0].created, second[0].created);
+		},
+	);
+
+	await t.test("should use the model file time as creation time", async () => {
+		// Arrange
+		const modelFilePath = await givenModelFile(1_700_000_000);
+		const { modelsService } = mockModelsService(
+			{ "model-a": null, "model-b": null },
+			modelFilePath,
+		);
+
+		// Act
+		const models = await modelsService.getModels();
+
+		// Assert
+		assert.strictEqual(models[1].created, 1_700_000_000);
+	});
+
+	await t.test("should default to no capabilities", async () => {
+		// Arrange
+		const { modelsService } = mockModelsService({
+			"model-a": null,
+			"model-b": null,
+		});
+
+		// Act
+		const models = await modelsService.getModels();
+
+		// Assert
+		assert.deepStrictEqual(models.map((model) => model.capabilities), [
+			{ vision: false, tools: false, jsonMode: false },
+			{ vision: true, tools: true, jsonMode: false },
+		]);
+	});
+
+	await t.test("should default to no tags", async () => {
+		// Arrange
+		const { modelsService } = mockModelsService({
+			"model-a": null,
+			"model-b": null,
+		});
+
+		// Act
+		const models = await modelsService.getModels();
+
+		// Assert
+		assert.deepStrictEqual(
+			models.map((model) => model.tags),
+			[[], ["chat"]],
+		);
+	});
+});
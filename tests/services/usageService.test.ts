@@ -0,0 +1,183 @@
+import assert from "node:assert";
+import { mkdtemp, writeFile } from "node:fs/promises";
+import { tmpdir } from "node:os";
+import path from "node:path";
+import { test } from "node:test";
+import { UsageRepository } from "#src/repositories/usageRepository.ts";
+import { UsageService } from "#src/services/usageService.ts";
+
+function toStream(chunks: string[]) {
+	const encoder = new TextEncoder();
+	return ReadableStream.from(chunks.map((chunk) => encoder.encode(chunk)));
+}
+
+test("trackUsage", async (t) => {
+	await t.test("should record the usage of a JSON response", async () => {
+		// Arrange
+		const usageService = new UsageService();
+		const json = JSON.stringify({
+			choices: [{ message: { content: "Hi" } }],
+			usage: { prompt_tokens: 5, completion_tokens: 2 },
+		});
+
+		// Act
+		const body = usageService.trackUsage(
+			"model-a",
+			toStream([json.slice(0, 10), json.slice(10)]),
+			false,
+		);
+		const text = await new Response(body).text();
+
+		// Assert
+		assert.strictEqual(text, json);
+		assert.deepStrictEqual(usageService.getUsage("model-a"), {
+			requests: 1,
+			promptTokens: 5,
+			completionTokens: 2,
+		});
+	});
+
+	await t.test("should read the usage at the end of a long body", async () => {
+		// Arrange
+		const usageService = new UsageService();
+		const json = JSON.stringify({
+			choices: [{ message: { content: "Hi ".repeat(100_000) } }],
+			usage: {
+				prompt_tokens: 5,
+				completion_tokens: 2,
+				prompt_tokens_details: { cached_tokens: 3 },
+			},
+		});
+		const chunks = json.match(/.{1,1000}/gs) ?? [];
+
+		// Act
+		const body = usageService.trackUsage("model-a", toStream(chunks), false);
+		const text = await new Response(body).text();
+
+		// Assert
+		assert.strictEqual(text, json);
+		assert.deepStrictEqual(usageService.getUsage("model-a"), {
+			requests: 1,
+			promptTokens: 5,
+			completionTokens: 2,
+		});
+	});
+
+	await t.test("should read a usage chunk split across reads", async () => {
+		// Arrange
+		const usageService = new UsageService();
+		const chunk =
+			'data: {"choices":[],"usage":{"prompt_tokens":7,"completion_tokens":3}}\n\n';
+
+		// Act
+		const body = usageService.trackUsage(
+			"model-a",
+			toStream([chunk.slice(0, 30), chunk.slice(30), "data: [DONE]\n\n"]),
+			true,
+		);
+		await new Response(body).text();
+
+		// Assert
+		assert.deepStrictEqual(usageService.getUsage("model-a"), {
+			requests: 1,
+			promptTokens: 7,
+			completionTokens: 3,
+		});
+	});
+
+	await t.test("should forward a malformed chunk untouched", async () => {
+		// Arrange
+		const usageService = new UsageService();
+		const chunks = ['data: {"usage": oops\n\n', "data: [DONE]\n\n"];
+
+		// Act
+		const body = usageService.trackUsage("model-a", toStream(chunks), true);
+		const text = await new Response(body).text();
+
+		// Assert
+		assert.strictEqual(text, chunks.join(""));
+		assert.deepStrictEqual(usageService.getUsage("model-a"), {
+			requests: 1,
+			promptTokens: 0,
+			completionTokens: 0,
+		});
+	});
+
+	await t.test("should add up the usage of every response", async () => {
+		// Arrange
+		const usageService = new UsageService();
+		usageService.record("model-a", { prompt_tokens: 1, completion_tokens: 2 });
+
+		// Act
+		usageService.record("model-a", { prompt_tokens: 3, completion_tokens: 4 });
+
+		// Assert
+		assert.deepStrictEqual(usageService.getUsage("model-a"), {
+			requests: 0,
+			promptTokens: 4,
+			completionTokens: 6,
+		});
+	});
+});
+
+test("load", async (t) => {
+	await t.test("should restore the counters of a previous run", async () => {
+		// Arrange
+		const directory = await mkdtemp(path.join(tmpdir(), "usage-"));
+		const usageRepository = new UsageRepository(
+			path.join(directory, "usage.json"),
+		);
+		const previousRun = new UsageService(usageRepository);
+		previousRun.trackUsage("model-a", toStream([]), false);
+		previousRun.record("model-a", { prompt_tokens: 5, completion_tokens: 2 });
+		await previousRun.save();
+		const usageService = new UsageService(usageRepository);
+
+		// Act
+		await usageService.load();
+		usageService.record("model-a", { prompt_tokens: 1, completion_tokens: 1 });
+
+		// Assert
+		assert.deepStrictEqual(usageService.getUsage("model-a"), {
+			requests: 1,
+			promptTokens: 6,
+			completionTokens: 3,
+		});
+	});
+
+	await t.test("should start from zero without a saved file", async () => {
+		// Arrange
+		const directory = await mkdtemp(path.join(tmpdir(), "usage-"));
+		const usageService = new UsageService(
+			new UsageRepository(path.join(directory, "usage.json")),
+		);
+
+		// Act
+		await usageService.load();
+
+		// Assert
+		assert.strictEqual(usageService.getUsage("model-a"), undefined);
+	});
+
+	for (const [corruption, content] of [
+		["invalid JSON", '{"model-a": {"requests": 1'],
+		["invalid counters", '{"model-a": {"requests": "one"}}'],
+	]) {
+		await t.test(`should start from zero with ${corruption}`, async () => {
+			// Arrange
+			const directory = await mkdtemp(path.join(tmpdir(), "usage-"));
+			const filePath = path.join(directory, "usage.json");
+			await writeFile(filePath, content, { encoding: "utf8" });
+			const usageService = new UsageService(new UsageRepository(filePath));
+			const warn = t.mock.method(console, "warn", () => {});
+
+			// Act
+			await usageService.load();
+
+			// Assert
+			assert.strictEqual(usageService.getUsage("model-a"), undefined);
+			assert.strictEqual(warn.mock.callCount(), 1);
+			assert.match(warn.mock.calls[0].arguments[0], /usage\.json/);
+		});
+	}
+});
@@ -0,0 +1,77 @@
+import assert from "node:assert";
+import { mock, test } from "node:test";
+import { setImmediate } from "node:timers/promises";
+import type { ConfigRepository } from "#src/repositories/configRepository.ts";
+import type { RocmSmiRepository } from "#src/repositories/rocmSmiRepository.ts";
+import type { LlamaProxyService } from "#src/services/llamaProxyService.ts";
+import type { ModelFitService } from "#src/services/modelFitService.ts";
+import { VramLoggerService } from "#src/services/vramLoggerService.ts";
+
+function mockVramLoggerService(interval: number) {
+	const configRepository = {
+		getVramLogConfiguration: mock.fn(() => ({ interval, level: "info" })),
+		getGpuDevice: mock.fn(() => 0),
+	};
+	const rocmSmiRepository = {
+		getVramInfo: mock.fn(async () => [
+			{ card: "card0", totalBytes: 16_000, usedBytes: 6_000 },
+		]),
+	};
+	const llamaProxyService = {
+		getLoadedModels: mock.fn(() => ["model-a"]),
+	};
+	const modelFitService = {
+		estimateRequiredVram: mock.fn(async () => 5_000),
+	};
+
+	const vramLoggerService = new VramLoggerService(
+		configRepository as unknown as ConfigRepository,
+		rocmSmiRepository as unknown as RocmSmiRepository,
+		llamaProxyService as unknown as LlamaProxyService,
+		modelFitService as unknown as ModelFitService,
+	);
+	return { vramLoggerService, rocmSmiRepository };
+}
+
+test("VramLoggerService", async (t) => {
+	t.beforeEach(() => {
+		mock.timers.enable({ apis: ["setInterval"] });
+	});
+	t.afterEach(() => {
+		mock.timers.reset();
+		mock.restoreAll();
+	});
+
+	await t.test("should log the VRAM state every interval", async () => {
+		// Arrange
+		const info = mock.method(console, "info", () => {});
+		const { vramLoggerService } = mockVramLoggerService(60);
+
+		// Act
+		vramLoggerService.start();
+		mock.timers.tick(60_000);
+		await setImmediate();
+		vramLoggerService.stop();
+
+		// Assert
+		assert.strictEqual(info.mock.callCount(), 1);
+		const line = info.mock.calls[0].arguments[0];
+		assert.match(line, /16,000 B total/);
+		assert.match(line, /6,000 B used/);
+		assert.match(line, /10,000 B free/);
+		assert.match(line, /model-a \(~5,000 B\)/);
+	});
+
+	await t.test("should not log when the interval is disabled", async () => {
+		// Arrange
+		const { vramLoggerService, rocmSmiRepository } = mockVramLoggerService(0);
+
+		// Act
+		vramLoggerService.start();
+		mock.timers.tick(60_000);
+		await setImmediate();
+
+		// Assert
+		assert.strictEqual(rocmSmiRepository.getVramInfo.mock.callCount(), 0);
+	});
+});